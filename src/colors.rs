@@ -42,11 +42,30 @@ impl Color {
     }
 
     pub fn darker(self: Self) -> Self {
+        self.darken(0.75)
+    }
+
+    /// Multiply the color by `factor`, from 0.0 (black) to 1.0 (unchanged). The alpha is kept.
+    pub fn darken(self: Self, factor: f32) -> Self {
+        let factor = factor.max(0.0).min(1.0);
         Color {
             a: self.a,
-            r: (self.r as f32 * 0.75).round() as u8,
-            g: (self.g as f32 * 0.75).round() as u8,
-            b: (self.b as f32 * 0.75).round() as u8,
+            r: (self.r as f32 * factor).round() as u8,
+            g: (self.g as f32 * factor).round() as u8,
+            b: (self.b as f32 * factor).round() as u8,
+        }
+    }
+
+    /// Blend from `self` to `other`, `ratio` going from 0.0 (`self`) to 1.0 (`other`).
+    pub fn lerp(self: Self, other: Color, ratio: f32) -> Self {
+        let ratio = ratio.max(0.0).min(1.0);
+        let blend =
+            |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * ratio).round() as u8;
+        Color {
+            a: blend(self.a, other.a),
+            r: blend(self.r, other.r),
+            g: blend(self.g, other.g),
+            b: blend(self.b, other.b),
         }
     }
 }
@@ -92,6 +111,15 @@ mod tests {
         )
     }
 
+    #[test]
+    fn darken_scales_the_channels() {
+        let color = Color::from_argb(0x80c86432);
+
+        assert_eq!(color.darken(0.5), Color::from_argb(0x80643219));
+        assert_eq!(color.darken(2.0), color);
+        assert_eq!(color.darker(), color.darken(0.75));
+    }
+
     #[test]
     fn color_from_rgb() {
         let color = Color::from_rgb(0x123456);
@@ -107,6 +135,24 @@ mod tests {
         )
     }
 
+    #[test]
+    fn lerp() {
+        let from = Color::from_argb(0x00000000);
+        let to = Color::from_argb(0xff6432c8);
+
+        assert_eq!(from.lerp(to, 0.0), from);
+        assert_eq!(from.lerp(to, 1.0), to);
+        assert_eq!(
+            from.lerp(to, 0.5),
+            Color {
+                a: 128,
+                r: 50,
+                g: 25,
+                b: 100
+            }
+        );
+    }
+
     #[test]
     fn darker() {
         let color = DARK_RED.darker();
@@ -1,12 +1,46 @@
 use crate::colors::Color;
 use crate::game::Ai;
-use legion::Entity;
+use crate::map::Position;
+use crate::spawner::MonsterType;
+use legion::{Entity, EntityStore, IntoQuery};
 
+/// When several bodies share a tile, the one with the highest priority is drawn.
+pub const RENDER_PRIORITY_FURNITURE: i32 = 0;
+pub const RENDER_PRIORITY_ITEM: i32 = 1;
+pub const RENDER_PRIORITY_CORPSE: i32 = 2;
+pub const RENDER_PRIORITY_MONSTER: i32 = 3;
+pub const RENDER_PRIORITY_PLAYER: i32 = 4;
+
+#[derive(Clone)]
 pub struct Body {
     pub name: String,
     pub blocking: bool,
     pub char: char,
     pub color: Color,
+    pub render_priority: i32,
+}
+
+/// How an entity reads in the interface, like "an orc (hp 8/10)". Shared by the tooltips and
+/// the targeting, so that they all agree.
+pub fn describe<W: EntityStore>(world: &W, entity: Entity) -> String {
+    let name = match <&Body>::query().get(world, entity) {
+        Ok(_) if <&Player>::query().get(world, entity).is_ok() => "you".to_string(),
+        Ok(body) => with_article(&body.name),
+        Err(_) => "something".to_string(),
+    };
+
+    match <&CombatStats>::query().get(world, entity) {
+        Ok(stats) => format!("{} (hp {}/{})", name, stats.hp, stats.max_hp),
+        Err(_) => name,
+    }
+}
+
+fn with_article(name: &str) -> String {
+    let article = match name.chars().next() {
+        Some('a') | Some('e') | Some('i') | Some('o') | Some('u') => "an",
+        _ => "a",
+    };
+    format!("{} {}", article, name)
 }
 
 pub struct Player {
@@ -17,6 +51,172 @@ pub struct Monster {
     pub ai: Ai,
     pub speed: u32,
     pub tick: i32,
+    /// The direction of the last step taken while wandering.
+    pub last_move: (i32, i32),
+    /// Below this fraction of its max hp, the monster runs away from the player.
+    pub flee_threshold: f32,
+    pub fleeing: bool,
+    /// How sure the monster is that the player is around. It only goes after them past
+    /// `AWARENESS_THRESHOLD`.
+    pub awareness: f32,
+    /// How close the player has to come for the monster to go after them, in tiles, however far
+    /// it sees. Cautious or territorial monsters leave the player alone until then.
+    pub aggression_range: i32,
+}
+
+/// How aware of the player a monster has to be to turn hostile.
+pub const AWARENESS_THRESHOLD: f32 = 1.0;
+
+impl Monster {
+    pub fn is_aware(&self) -> bool {
+        self.awareness >= AWARENESS_THRESHOLD
+    }
+}
+
+/// Makes the player harder to notice: monsters seeing them don't attack right away, they first
+/// have to become aware of them, all the slower with a higher level.
+pub struct Stealth {
+    pub level: i32,
+    /// Where the player stood on the previous monster turn, to tell whether they moved since.
+    pub last_position: Position,
+}
+
+/// Who a creature sides with. Monsters only go after the creatures their faction is hostile to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Faction {
+    /// The player, and whoever fights on their side.
+    Player,
+    /// Every monster of the dungeon, out for the player.
+    Monsters,
+    /// Creatures minding their own business.
+    Neutral,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reaction {
+    Hostile,
+    Neutral,
+    Friendly,
+}
+
+impl Faction {
+    /// How the members of this faction behave towards the members of `other`.
+    pub fn reaction_to(&self, other: Faction) -> Reaction {
+        match (self, other) {
+            (Faction::Player, Faction::Player) | (Faction::Monsters, Faction::Monsters) => {
+                Reaction::Friendly
+            }
+            (Faction::Player, Faction::Monsters) | (Faction::Monsters, Faction::Player) => {
+                Reaction::Hostile
+            }
+            (Faction::Neutral, _) | (_, Faction::Neutral) => Reaction::Neutral,
+        }
+    }
+
+    pub fn is_hostile_to(&self, other: Faction) -> bool {
+        self.reaction_to(other) == Reaction::Hostile
+    }
+}
+
+/// Where and when the player last saw a monster, so that it can be remembered once out of sight.
+pub struct LastSeen {
+    pub position: Position,
+    pub turn: u32,
+}
+
+/// Lets a monster track noises it cannot see the source of.
+pub struct Hearing {
+    /// The quietest noise the monster reacts to.
+    pub threshold: i32,
+}
+
+/// What an entity can see, recalculated when flagged as dirty.
+pub struct FieldOfView {
+    pub radius: i32,
+    pub visible: Vec<(i32, i32)>,
+    pub dirty: bool,
+}
+
+impl FieldOfView {
+    pub fn new(radius: i32) -> Self {
+        FieldOfView {
+            radius,
+            visible: vec![],
+            dirty: true,
+        }
+    }
+}
+
+/// How far the player sees, with an optional temporary boost from a light source.
+/// The floor's light caps the natural radius, but not the boost.
+pub struct Vision {
+    pub radius: i32,
+    pub boost: i32,
+    pub boost_turns: i32,
+}
+
+impl Vision {
+    pub fn new(radius: i32) -> Self {
+        Vision {
+            radius,
+            boost: 0,
+            boost_turns: 0,
+        }
+    }
+
+    /// The radius to use for the field of view given the light of the floor, never negative.
+    pub fn effective_radius(&self, light_radius: i32) -> i32 {
+        (self.radius.min(light_radius) + self.boost).max(0)
+    }
+}
+
+/// How long an animation lasts, in seconds.
+pub const ANIMATION_DURATION: f32 = 0.12;
+/// How far into the target's tile an attack lunges.
+const LUNGE_REACH: f32 = 0.4;
+
+/// Where a body is drawn while it goes from one tile to another. Only the rendering is animated,
+/// the position of the entity changed right away.
+pub struct Animation {
+    pub from: Position,
+    pub to: Position,
+    /// From 0 to 1, the animation is over at 1.
+    pub progress: f32,
+    /// Only reach for `to` and come back, like an attack.
+    pub lunge: bool,
+}
+
+impl Animation {
+    pub fn slide(from: Position, to: Position) -> Self {
+        Animation {
+            from,
+            to,
+            progress: 0.,
+            lunge: false,
+        }
+    }
+
+    pub fn lunge(from: Position, towards: Position) -> Self {
+        Animation {
+            from,
+            to: towards,
+            progress: 0.,
+            lunge: true,
+        }
+    }
+
+    /// Where to draw the body at this point of the animation, in tiles.
+    pub fn current(&self) -> (f32, f32) {
+        let ratio = if self.lunge {
+            LUNGE_REACH * (1. - (2. * self.progress - 1.).abs())
+        } else {
+            self.progress
+        };
+        (
+            self.from.x as f32 + (self.to.x - self.from.x) as f32 * ratio,
+            self.from.y as f32 + (self.to.y - self.from.y) as f32 * ratio,
+        )
+    }
 }
 
 pub struct MoveAction {
@@ -29,11 +229,14 @@ pub struct AttackAction {
     pub target_entity: Entity,
 }
 
+#[derive(Clone)]
 pub struct CombatStats {
     pub max_hp: i32,
     pub hp: i32,
     pub defense: i32,
     pub attack: i32,
+    /// How many tiles a successful hit pushes the target back.
+    pub knockback: i32,
 }
 
 pub struct MagicStats {
@@ -42,6 +245,29 @@ pub struct MagicStats {
 }
 
 impl CombatStats {
+    /// Fresh stats, at full health and without knockback.
+    pub fn new(max_hp: i32, attack: i32, defense: i32) -> Self {
+        CombatStats {
+            max_hp,
+            hp: max_hp,
+            defense,
+            attack,
+            knockback: 0,
+        }
+    }
+
+    pub fn with_knockback(self, knockback: i32) -> Self {
+        CombatStats { knockback, ..self }
+    }
+
+    /// Stronger or weaker stats, back at full health. The defense is left alone, as it adds up fast.
+    pub fn scaled(&self, factor: f32) -> Self {
+        let scale = |value: i32| ((value as f32 * factor).round() as i32).max(1);
+        CombatStats::new(scale(self.max_hp), scale(self.attack), self.defense)
+            .with_knockback(self.knockback)
+    }
+
+    /// Never above `max_hp`.
     pub fn heal(&mut self, healing_amount: i32) {
         self.hp = (self.hp + healing_amount).max(0).min(self.max_hp);
     }
@@ -51,30 +277,120 @@ impl CombatStats {
     }
 }
 
+#[derive(Clone)]
 pub struct Item {}
 
+#[derive(Clone)]
 pub struct ProvidesHealing {
     pub heal_amount: i32,
 }
 
+#[derive(Clone)]
 pub struct Consumable {}
 
+/// The item the player is looking for. Picking it up wins the game.
+#[derive(Clone)]
+pub struct QuestItem {}
+
+/// Identical consumables carried together.
+#[derive(Clone)]
+pub struct Stack {
+    pub count: i32,
+}
+
+#[derive(Clone)]
 pub struct Ranged {
     pub range: i32,
 }
 
+#[derive(Clone)]
 pub struct Burst {
     pub radius: i32,
 }
 
+/// Lights the tiles around, in its own color. Lit tiles can be seen from afar.
+pub struct LightSource {
+    pub radius: i32,
+    pub color: Color,
+}
+
+/// Makes the user see further for a while.
+#[derive(Clone)]
+pub struct BoostsVision {
+    pub amount: i32,
+    pub turns: i32,
+}
+
+/// Sends the user somewhere out of sight.
+#[derive(Clone)]
+pub struct Teleports {}
+
+/// Calls a companion to fight by the side of the user.
+#[derive(Clone)]
+pub struct SummonsCompanion {}
+
+#[derive(Clone)]
 pub struct InflictsDamage {
     pub damage: i32,
 }
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum HazardKind {
+    Fire,
+    Gas,
+}
+
+/// Belongs to a level the player left, and waits in the level cache for them to come back.
+pub struct LeftBehind {}
+
+/// The remains of a creature, rotting away until nothing is left.
+pub struct Corpse {
+    pub turns_remaining: i32,
+}
+
+/// A tile that hurts whoever stands on it, until it fades away.
+pub struct Hazard {
+    pub kind: HazardKind,
+    pub damage: i32,
+    pub turns_remaining: i32,
+}
+
+/// An item leaving hazards on the area it affects.
+#[derive(Clone)]
+pub struct SpawnsHazard {
+    pub kind: HazardKind,
+    pub damage: i32,
+    pub turns: i32,
+}
+/// Something a monster does on its own every AI turn, on top of moving and attacking.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ability {
+    /// Heal back some hp, up to the max.
+    Regenerate { amount: i32 },
+    /// Sometimes call for help when the player is in sight.
+    Summon {
+        monster_type: MonsterType,
+        chance: f32,
+    },
+    /// Sometimes spit at the player from afar, when out of melee reach.
+    RangedSpit {
+        damage: i32,
+        range: i32,
+        chance: f32,
+    },
+}
+
+#[derive(Clone)]
+pub struct Abilities {
+    pub on_turn: Vec<Ability>,
+}
+
 pub struct SuffersDamage {
     pub entity: Entity,
     pub damage: i32,
 }
 
+#[derive(Clone)]
 pub struct InInventory {
     pub owner: Entity,
 }
@@ -93,6 +409,63 @@ pub struct DropItemIntent {
     pub item_entity: Entity,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Interactable {
     DownStairs,
+    UpStairs,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{describe, Animation, CombatStats, Monster};
+    use crate::map::Position;
+    use crate::spawner::{self, ItemType, MonsterType, PlayerTemplate};
+    use legion::{component, Entity, IntoQuery, World};
+
+    #[test]
+    fn describe_adds_article_and_health() {
+        let mut world = World::default();
+        let player = spawner::player(&mut world, 0, 0, &PlayerTemplate::default());
+        let potion = spawner::item(&mut world, ItemType::Potion, 1, 1);
+        let light = spawner::light_source(&mut world, 2, 2, 3, crate::colors::ORANGE);
+        spawner::monster(&mut world, MonsterType::Orc, 3, 3);
+        let orc = *<Entity>::query()
+            .filter(component::<Monster>())
+            .iter(&world)
+            .next()
+            .unwrap();
+
+        assert_eq!(describe(&world, player), "you (hp 30/30)");
+        assert_eq!(describe(&world, potion), "a potion");
+        assert_eq!(describe(&world, light), "something");
+        assert_eq!(describe(&world, orc), "an orc (hp 12/12)");
+    }
+
+    #[test]
+    fn slides_end_on_the_target_and_lunges_come_back() {
+        let mut slide = Animation::slide(Position::new(2, 2), Position::new(3, 2));
+        assert_eq!(slide.current(), (2., 2.));
+        slide.progress = 0.5;
+        assert_eq!(slide.current(), (2.5, 2.));
+        slide.progress = 1.;
+        assert_eq!(slide.current(), (3., 2.));
+
+        let mut lunge = Animation::lunge(Position::new(2, 2), Position::new(2, 1));
+        lunge.progress = 0.5;
+        assert!(lunge.current().1 < 2. && lunge.current().1 > 1.);
+        lunge.progress = 1.;
+        assert_eq!(lunge.current(), (2., 2.));
+    }
+
+    #[test]
+    fn new_stats_start_at_full_health_and_heal_up_to_it() {
+        let mut stats = CombatStats::new(10, 3, 1).with_knockback(2);
+        assert_eq!((stats.hp, stats.max_hp, stats.knockback), (10, 10, 2));
+
+        stats.take_damage(4);
+        stats.heal(100);
+        assert_eq!(stats.hp, 10);
+        stats.take_damage(100);
+        assert_eq!(stats.hp, 0);
+    }
 }
@@ -1,12 +1,25 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::{fs, io, path::Path};
 
-use crate::map::Map;
+use crate::map::{GenerationConfig, Map, RememberedTile};
+use crate::resources::{
+    AutoPilot, FovAlgorithm, GameEvent, GameEvents, GameRng, GameWon, LevelCache, ReplayLog,
+    Settings, SharedInfo, Turn, WizardMode,
+};
+use crate::score::{HighScore, Score};
+use crate::spawner::{self, PlayerTemplate};
+use crate::theme::Theme;
 use crate::{components::*, map::Position};
+use legion::world::Duplicate;
 use legion::Entity;
 use legion::IntoQuery;
 use legion::Resources;
 use legion::World;
 use legion::*;
+use serde::{Deserialize, Serialize};
+
+/// How many turns the player rests at most, when nothing interrupts them.
+const REST_TURNS: u32 = 50;
 
 pub struct State {
     pub world: World,
@@ -14,33 +27,357 @@ pub struct State {
     pub player_entity: Entity,
 }
 
+/// What the player does on their turn, as the piston engine would translate it from a key press.
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum Action {
+    Move { dx: i32, dy: i32 },
+    Grab,
+    Interact,
+    Wait,
+}
+
+impl Action {
+    /// Parse an action from the same keys used in the windowed game: `wasd` to move,
+    /// `g` to grab, `e` to interact and a space to wait.
+    pub fn from_char(character: char) -> Option<Action> {
+        match character {
+            'w' => Some(Action::Move { dx: 0, dy: -1 }),
+            'a' => Some(Action::Move { dx: -1, dy: 0 }),
+            's' => Some(Action::Move { dx: 0, dy: 1 }),
+            'd' => Some(Action::Move { dx: 1, dy: 0 }),
+            'g' => Some(Action::Grab),
+            'e' => Some(Action::Interact),
+            ' ' => Some(Action::Wait),
+            _ => None,
+        }
+    }
+}
+
 #[derive(PartialEq)]
 pub enum Ai {
     Basic,
+    /// Like basic, but roams around instead of waiting for the player.
+    Wander,
+    /// Fights whatever the player's side is hostile to, and otherwise follows the player around.
+    Companion,
 }
 
 impl State {
+    /// A brand new game, where everything random derives from the seed.
+    pub fn new(seed: u64) -> Self {
+        State::with_config(GenerationConfig {
+            seed,
+            ..GenerationConfig::default()
+        })
+    }
+
+    /// A brand new game, with the levels generated as configured.
+    pub fn with_config(generation_config: GenerationConfig) -> Self {
+        let seed = generation_config.seed;
+        let mut world = World::default();
+        let mut resources = Resources::default();
+        let mut template = PlayerTemplate::default();
+        let stats = &mut template.combat_stats;
+        stats.max_hp =
+            (stats.max_hp as f32 * generation_config.difficulty.player_health()).round() as i32;
+        stats.hp = stats.max_hp;
+        let player_entity = spawner::player(&mut world, -1, -1, &template);
+        let map = crate::map::make_map(&mut world, 1, &generation_config);
+        resources.insert(map);
+        resources.insert(GameRng::new(seed));
+        resources.insert(generation_config);
+        resources.insert(Journal::new());
+        resources.insert(GameEvents::new());
+        resources.insert(AutoPilot::new());
+        resources.insert(ReplayLog::new());
+        resources.insert(Turn(0));
+        resources.insert(GameWon(false));
+        resources.insert(Score::default());
+        resources.insert(FovAlgorithm::default());
+        resources.insert(Theme::default());
+        resources.insert(Settings::default());
+        resources.insert(WizardMode {
+            enabled: cfg!(debug_assertions),
+            see_all: false,
+            god_mode: false,
+            peaceful: false,
+        });
+        resources.insert(SharedInfo {
+            player_entity,
+            player_position: (-1, -1).into(),
+            player_moved: true,
+            alive: true,
+        });
+        let state = State {
+            world,
+            resources,
+            player_entity,
+        };
+        state.log_to(JournalSection::Story, "Welcome to Ambergris");
+        state
+    }
+
+    /// Start over with a brand new game, generated like the previous one but from another seed.
+    /// The journal, the theme and the settings carry over, the journal emptied of the previous game.
+    pub fn restart(&mut self, seed: u64) {
+        let mut journal = self
+            .resources
+            .remove::<Journal>()
+            .unwrap_or_else(Journal::new);
+        let theme = self.resources.remove::<Theme>();
+        let settings = self.resources.remove::<Settings>();
+        let generation_config = self.generation_config();
+        *self = State::with_config(GenerationConfig {
+            seed,
+            ..generation_config
+        });
+
+        journal.clear();
+        if let Some(fresh) = self.resources.remove::<Journal>() {
+            // What the new game already had to say, in the same sections.
+            for (index, entry) in fresh.get_entries().iter().enumerate().rev() {
+                match fresh.entry_section(index) {
+                    Some(section) => journal.log_to(section, entry.clone()),
+                    None => journal.log(entry.clone()),
+                }
+            }
+        }
+        self.resources.insert(journal);
+        if let Some(theme) = theme {
+            self.resources.insert(theme);
+        }
+        if let Some(settings) = settings {
+            self.resources.insert(settings);
+        }
+    }
+
+    /// Wait for a while, until something happens.
+    pub fn rest(&self) {
+        if let Some(mut auto_pilot) = self.resources.get_mut::<AutoPilot>() {
+            auto_pilot.start("resting", Action::Wait, None, REST_TURNS);
+        }
+    }
+
+    /// What the player does this turn on their own, if anything.
+    pub fn next_auto_action(&self) -> Option<Action> {
+        self.resources
+            .get_mut::<AutoPilot>()
+            .and_then(|mut auto_pilot| auto_pilot.next_action())
+    }
+
+    pub fn is_auto_piloting(&self) -> bool {
+        self.resources
+            .get::<AutoPilot>()
+            .map_or(false, |auto_pilot| auto_pilot.is_active())
+    }
+
+    pub fn stop_auto_pilot(&self) {
+        if let Some(mut auto_pilot) = self.resources.get_mut::<AutoPilot>() {
+            auto_pilot.stop();
+        }
+    }
+
+    /// Perform the action on behalf of the player, recording it for replays.
+    pub fn play(&mut self, action: Action) -> RunState {
+        if let Some(mut replay_log) = self.resources.get_mut::<ReplayLog>() {
+            replay_log.record(action);
+        }
+
+        let run_state = match action {
+            Action::Move { dx, dy } => {
+                self.move_player(dx, dy);
+                RunState::PlayerTurn
+            }
+            Action::Grab => {
+                if self.grab_item() {
+                    RunState::PlayerTurn
+                } else {
+                    RunState::WaitForPlayerInput
+                }
+            }
+            Action::Interact => match self.interact() {
+                Interact::Canceled => RunState::WaitForPlayerInput,
+                Interact::WentDownstairs => RunState::NextLevel,
+                Interact::WentUpstairs => RunState::PreviousLevel,
+            },
+            Action::Wait => RunState::PlayerTurn,
+        };
+
+        // Only the actions actually taking time move the clock forward.
+        if run_state != RunState::WaitForPlayerInput {
+            if let Some(mut replay_log) = self.resources.get_mut::<ReplayLog>() {
+                replay_log.next_turn();
+            }
+            if let Some(mut turn) = self.resources.get_mut::<Turn>() {
+                turn.0 += 1;
+            }
+        }
+        run_state
+    }
+
+    /// What comes after the monsters' turn: back to the player, unless the game is over.
+    pub fn after_ai_turn(&self) -> RunState {
+        let alive = self
+            .resources
+            .get::<SharedInfo>()
+            .map_or(false, |player_info| player_info.alive);
+        let won = self
+            .resources
+            .get::<GameWon>()
+            .map_or(false, |game_won| game_won.0);
+
+        if !alive {
+            RunState::Death
+        } else if won {
+            RunState::Victory
+        } else {
+            RunState::WaitForPlayerInput
+        }
+    }
+
+    /// Recalculate the player's field of view, if they moved, their sight changed or the map changed
+    /// since the last time. Returns whether it was recalculated.
+    pub fn update_player_fov(&mut self, force: bool) -> bool {
+        let algorithm = self
+            .resources
+            .get::<FovAlgorithm>()
+            .map_or(FovAlgorithm::default(), |algorithm| *algorithm);
+        let mut shared_info = self.resources.get_mut::<SharedInfo>().unwrap();
+        let mut map = self.resources.get_mut::<Map>().unwrap();
+
+        let (position, vision) =
+            match <(&Position, &Vision)>::query().get(&self.world, self.player_entity) {
+                Ok(player) => player,
+                Err(_) => return false,
+            };
+        let radius = vision.effective_radius(map.base_light_radius);
+        if !(force
+            || shared_info.player_moved
+            || map.transparency_changed
+            || radius != map.player_fov_radius)
+        {
+            return false;
+        }
+
+        map.calculate_player_fov(position.x, position.y, radius, algorithm);
+        map.transparency_changed = false;
+        shared_info.player_moved = false;
+        true
+    }
+
+    /// Remember where the monsters in the player's field of view are, and what lies on every tile in sight.
+    /// To be called once the player's field of view is up to date.
+    pub fn update_visibility(&mut self) {
+        let mut map = self.resources.get_mut::<Map>().unwrap();
+        let turn = self.resources.get::<Turn>().map_or(0, |turn| turn.0);
+
+        let seen: Vec<(Entity, Position)> = <(Entity, &Position)>::query()
+            .filter(component::<Monster>() & component::<CombatStats>())
+            .iter(&self.world)
+            .filter(|(_, position)| map.is_in_player_fov(position.x, position.y))
+            .map(|(entity, position)| (*entity, *position))
+            .collect();
+
+        for (entity, position) in seen {
+            if let Some(mut entry) = self.world.entry(entity) {
+                entry.add_component(LastSeen { position, turn });
+            }
+        }
+
+        // The player doesn't need to remember themselves.
+        let mut topmost: HashMap<Position, (Entity, &Body)> = HashMap::new();
+        for (entity, position, body) in <(Entity, &Position, &Body)>::query()
+            .filter(!component::<Player>())
+            .iter(&self.world)
+        {
+            if !map.is_in_player_fov(position.x, position.y) {
+                continue;
+            }
+            let is_on_top = topmost.get(position).map_or(true, |(_, other)| {
+                body.render_priority > other.render_priority
+            });
+            if is_on_top {
+                topmost.insert(*position, (*entity, body));
+            }
+        }
+
+        let in_sight = map.player_fov.clone();
+        for (x, y) in in_sight {
+            let position = Position::new(x, y);
+            match topmost.get(&position) {
+                Some(&(entity, body)) => {
+                    let remembered = RememberedTile {
+                        name: body.name.clone(),
+                        char: body.char,
+                        color: body.color,
+                        description: describe(&self.world, entity),
+                        creature: <&CombatStats>::query().get(&self.world, entity).is_ok(),
+                    };
+                    map.remembered.insert(position, remembered);
+                }
+                None => {
+                    map.remembered.remove(&position);
+                }
+            }
+        }
+    }
+
     pub fn move_player(&mut self, dx: i32, dy: i32) {
-        let position = <&Position>::query()
+        let from = <&Position>::query()
             .get(&self.world, self.player_entity)
+            .map(|position| *position)
             .unwrap();
         let position = Position {
-            x: position.x + dx,
-            y: position.y + dy,
+            x: from.x + dx,
+            y: from.y + dy,
         };
 
-        let map = self.resources.get::<Map>().unwrap();
+        let mut map = self.resources.get_mut::<Map>().unwrap();
 
-        let mut enemies = <(Entity, &Position, &Monster, &CombatStats)>::query();
+        let mut creatures = <(Entity, &Position, &Faction)>::query()
+            .filter(component::<Monster>() & component::<CombatStats>());
 
         let mut attack_action = None;
-        for (entity, enemy_position, _, _) in enemies.iter(&self.world) {
-            if *enemy_position == position {
+        let mut ally = None;
+        for (entity, creature_position, faction) in creatures.iter(&self.world) {
+            if *creature_position != position {
+                continue;
+            }
+            if Faction::Player.reaction_to(*faction) == Reaction::Friendly {
+                // Bumping into a friend doesn't hurt them, they make way.
+                ally = Some(*entity);
+            } else {
                 // We can attack a monster!
                 attack_action = Some(AttackAction {
-                    target_entity: entity.clone(),
+                    target_entity: *entity,
                 });
-                break;
+            }
+            break;
+        }
+
+        if let Some(ally) = ally {
+            map.set_blocked(position, false);
+            if !map.can_step(from, dx, dy) {
+                map.set_blocked(position, true);
+                return;
+            }
+            if let Ok(ally_position) = <&mut Position>::query().get_mut(&mut self.world, ally) {
+                ally_position.set_position(&from);
+            }
+            if let Ok(field_of_view) = <&mut FieldOfView>::query().get_mut(&mut self.world, ally) {
+                field_of_view.dirty = true;
+            }
+            if let Some(mut entry) = self.world.entry(ally) {
+                entry.add_component(Animation::slide(position, from));
+            }
+            let name = <&Body>::query()
+                .get(&self.world, ally)
+                .map_or_else(|_| "companion".to_string(), |body| body.name.clone());
+            if let Some(mut journal) = self.resources.get_mut::<Journal>() {
+                journal.log_to(
+                    JournalSection::Companion,
+                    format!("You swap places with the {}.", name),
+                );
             }
         }
 
@@ -122,7 +459,12 @@ impl State {
         }
     }
 
-    pub fn drop_item(&mut self, item_entity: Entity) -> RunState {
+    pub fn drop_item(&mut self, item_entity: Entity, whole_stack: bool) -> RunState {
+        let item_entity = if whole_stack {
+            item_entity
+        } else {
+            self.split_one(item_entity)
+        };
         let drop_item_intent = DropItemIntent { item_entity };
 
         if let Some(mut entry) = self.world.entry(self.player_entity) {
@@ -134,6 +476,31 @@ impl State {
         RunState::PlayerTurn
     }
 
+    /// Take a single item out of its stack, leaving the rest in the inventory.
+    fn split_one(&mut self, item_entity: Entity) -> Entity {
+        let count = <&Stack>::query()
+            .get(&self.world, item_entity)
+            .map_or(1, |stack| stack.count);
+        if count <= 1 {
+            return item_entity;
+        }
+
+        // Legion can't clone an entity within a world, so go through a scratch one.
+        let mut duplicate = item_duplicate();
+        let mut scratch = World::default();
+        let copy = scratch.clone_from_single(&self.world, item_entity, &mut duplicate);
+        let single = self.world.clone_from_single(&scratch, copy, &mut duplicate);
+
+        if let Ok(stack) = <&mut Stack>::query().get_mut(&mut self.world, item_entity) {
+            stack.count -= 1;
+        }
+        if let Ok(stack) = <&mut Stack>::query().get_mut(&mut self.world, single) {
+            stack.count = 1;
+        }
+
+        single
+    }
+
     pub fn use_range_item_with_targeting(
         &mut self,
         previous_state: RunState,
@@ -149,7 +516,10 @@ impl State {
 
         if radius == 0 {
             // We need to verify we could actually get a target.
-            for coordinates in <&Position>::query().iter(&self.world) {
+            for coordinates in <&Position>::query()
+                .filter(component::<Body>())
+                .iter(&self.world)
+            {
                 if target_position.0 == coordinates.x && target_position.1 == coordinates.y {
                     // We have a match!
                     let use_item_intent = UseItemIntent {
@@ -185,6 +555,154 @@ impl State {
         }
     }
 
+    pub fn log_to<T: Into<String>>(&self, section: JournalSection, text: T) {
+        if let Some(mut journal) = self.resources.get_mut::<Journal>() {
+            journal.log_to(section, text);
+        }
+    }
+
+    /// What the player knows about the monster at `position`: everything if they can see it,
+    /// only what it is if they just remember it being there. None if there's no monster they know of.
+    pub fn examine(&self, position: Position) -> Option<Examined> {
+        let map = self.resources.get::<Map>()?;
+        let see_all = self
+            .resources
+            .get::<WizardMode>()
+            .map_or(false, |wizard| wizard.see_all);
+
+        if see_all || map.is_in_player_fov(position.x, position.y) {
+            return <(&Position, &Body, &CombatStats)>::query()
+                .filter(component::<Monster>())
+                .iter(&self.world)
+                .find(|(monster_position, _, _)| **monster_position == position)
+                .map(|(_, body, combat_stats)| Examined {
+                    name: body.name.clone(),
+                    description: spawner::description(&body.name),
+                    stats: Some(combat_stats.clone()),
+                });
+        }
+
+        let turn = self.resources.get::<Turn>().map_or(0, |turn| turn.0);
+        <(&Body, &LastSeen)>::query()
+            .filter(component::<Monster>() & component::<CombatStats>())
+            .iter(&self.world)
+            .find(|(_, last_seen)| {
+                last_seen.position == position && turn - last_seen.turn <= GHOST_TURNS
+            })
+            .map(|(body, _)| Examined {
+                name: body.name.clone(),
+                description: spawner::description(&body.name),
+                stats: None,
+            })
+    }
+
+    /// Wizard command: walk around without getting hurt, and without the monsters bothering,
+    /// to look at the levels in peace. Returns false when wizard mode isn't available.
+    pub fn toggle_god_mode(&mut self) -> bool {
+        let god_mode = match self.resources.get_mut::<WizardMode>() {
+            Some(mut wizard) if wizard.enabled => {
+                wizard.god_mode = !wizard.god_mode;
+                wizard.peaceful = wizard.god_mode;
+                wizard.god_mode
+            }
+            _ => return false,
+        };
+
+        self.log_to(
+            JournalSection::System,
+            if god_mode {
+                "You feel invincible, the monsters ignore you."
+            } else {
+                "You feel mortal again."
+            },
+        );
+        true
+    }
+
+    /// Wizard command: explore the whole map and show everything on it.
+    /// Returns false when wizard mode isn't available.
+    pub fn reveal_map(&mut self) -> bool {
+        if let Some(mut wizard) = self.resources.get_mut::<WizardMode>() {
+            if !wizard.enabled {
+                return false;
+            }
+            wizard.see_all = true;
+        } else {
+            return false;
+        }
+
+        if let Some(mut map) = self.resources.get_mut::<Map>() {
+            for explored in map.explored_tiles.iter_mut() {
+                *explored = true;
+            }
+        }
+        self.log_to(JournalSection::System, "The map reveals itself.");
+        true
+    }
+
+    /// Switch to the other field of view algorithm, to compare how they look.
+    pub fn toggle_fov_algorithm(&mut self) {
+        let algorithm = match self.resources.get_mut::<FovAlgorithm>() {
+            Some(mut algorithm) => {
+                *algorithm = algorithm.toggled();
+                *algorithm
+            }
+            None => return,
+        };
+        self.log_to(
+            JournalSection::System,
+            format!("Field of view: {:?}.", algorithm),
+        );
+    }
+
+    /// Take the events pushed since the last call, keeping the score up to date along the way.
+    pub fn drain_events(&mut self) -> Vec<GameEvent> {
+        let events = match self.resources.get_mut::<GameEvents>() {
+            Some(mut events) => events.drain(),
+            None => return vec![],
+        };
+        if let Some(mut score) = self.resources.get_mut::<Score>() {
+            for event in events.iter() {
+                score.record(event, self.player_entity);
+            }
+        }
+        events
+    }
+
+    /// The game so far, as it would enter the high score table.
+    pub fn high_score(&self) -> HighScore {
+        let score = self
+            .resources
+            .get::<Score>()
+            .map_or_else(Score::default, |score| score.clone());
+        HighScore {
+            points: score.points(),
+            depth: score.deepest,
+            turns: self.resources.get::<Turn>().map_or(0, |turn| turn.0),
+            won: score.won,
+        }
+    }
+
+    /// Write the score and the journal so far to a text file, oldest entry first, so that a run
+    /// interrupted by closing the window leaves a trace.
+    pub fn save_journal<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let high_score = self.high_score();
+        let mut lines = vec![format!(
+            "Score: {} points, depth {}, {} turns",
+            high_score.points, high_score.depth, high_score.turns
+        )];
+        if let Some(journal) = self.resources.get::<Journal>() {
+            lines.extend(journal.get_entries().iter().rev().cloned());
+        }
+        fs::write(path, lines.join("\n"))
+    }
+
+    pub fn push_event(&self, event: GameEvent) {
+        if let Some(mut events) = self.resources.get_mut::<GameEvents>() {
+            events.push(event);
+        }
+    }
+
     pub fn interact(&self) -> Interact {
         let player_position = *<&Position>::query()
             .get(&self.world, self.player_entity)
@@ -199,6 +717,16 @@ impl State {
                     println!("Going downstairs...");
                     Interact::WentDownstairs
                 }
+                Interactable::UpStairs => {
+                    let depth = self.resources.get::<Map>().map_or(1, |map| map.depth);
+                    if depth <= 1 {
+                        self.log("You can't leave yet.");
+                        Interact::Canceled
+                    } else {
+                        self.log("You climb back up the stairs.");
+                        Interact::WentUpstairs
+                    }
+                }
             };
         }
 
@@ -206,20 +734,107 @@ impl State {
     }
 
     pub fn next_level(&mut self) {
-        let to_delete = self.find_entity_attached_to_map();
+        let level = if let Some(map) = self.resources.get::<Map>() {
+            map.depth + 1
+        } else {
+            0
+        };
 
-        for entity in to_delete {
-            self.world.remove(entity);
+        if self.change_level(level) {
+            // Back from above, the player should stand on the stairs going up.
+            self.move_player_to_stairs(|interactable| {
+                matches!(interactable, Interactable::UpStairs)
+            });
         }
+        self.push_event(GameEvent::LevelDescended { depth: level });
+    }
 
+    pub fn previous_level(&mut self) {
         let level = if let Some(map) = self.resources.get::<Map>() {
-            map.depth + 1
+            (map.depth - 1).max(1)
         } else {
-            0
+            1
+        };
+
+        self.change_level(level);
+        self.push_event(GameEvent::LevelAscended { depth: level });
+
+        // Coming from below, the player should stand on the stairs going down.
+        self.move_player_to_stairs(|interactable| matches!(interactable, Interactable::DownStairs));
+    }
+
+    /// Leave the current level in the level cache, and replace it with the one at `depth`: as the player
+    /// left it if they were already there, freshly generated otherwise. Returns whether it came from the cache.
+    fn change_level(&mut self, depth: i32) -> bool {
+        for entity in self.find_entity_attached_to_map() {
+            if let Some(mut entry) = self.world.entry(entity) {
+                entry.add_component(LeftBehind {});
+            }
+        }
+        let mut left_behind = World::default();
+        left_behind.move_from(&mut self.world, &component::<LeftBehind>());
+
+        let player_position = <&Position>::query()
+            .get(&self.world, self.player_entity)
+            .map(|position| *position);
+        let mut cache = self.resources.remove::<LevelCache>().unwrap_or_default();
+        if let Some(mut map) = self.resources.remove::<Map>() {
+            if let Ok(player_position) = player_position {
+                map.set_blocked(player_position, false);
+            }
+            cache.store(map, left_behind);
+        }
+
+        let restored = match cache.take(depth) {
+            Some((mut map, mut world)) => {
+                self.world.move_from(&mut world, &component::<LeftBehind>());
+                let returning: Vec<Entity> = <Entity>::query()
+                    .filter(component::<LeftBehind>())
+                    .iter(&self.world)
+                    .copied()
+                    .collect();
+                for entity in returning {
+                    if let Some(mut entry) = self.world.entry(entity) {
+                        entry.remove_component::<LeftBehind>();
+                    }
+                }
+                // Whatever the player saw before, they have to look again.
+                map.transparency_changed = true;
+                map.player_distances_from = None;
+                self.resources.insert(map);
+                true
+            }
+            None => {
+                let config = self.generation_config();
+                let map = crate::map::make_map(&mut self.world, depth, &config);
+                self.resources.insert(map);
+                false
+            }
         };
+        self.resources.insert(cache);
+        restored
+    }
+
+    /// Put the player on the first stairs matching `is_stairs`, if there are any.
+    fn move_player_to_stairs(&mut self, is_stairs: impl Fn(&Interactable) -> bool) {
+        let stairs = <(&Position, &Interactable)>::query()
+            .iter(&self.world)
+            .find(|(_, interactable)| is_stairs(interactable))
+            .map(|(&position, _)| position);
+        let position = <&mut Position>::query().get_mut(&mut self.world, self.player_entity);
+        if let (Some(stairs), Ok(position)) = (stairs, position) {
+            if let Some(mut map) = self.resources.get_mut::<Map>() {
+                map.set_blocked(*position, false);
+                map.set_blocked(stairs, true);
+            }
+            position.set_position(&stairs);
+        }
+    }
 
-        let map = crate::map::make_map(&mut self.world, level);
-        self.resources.insert(map);
+    fn generation_config(&self) -> GenerationConfig {
+        self.resources
+            .get::<GenerationConfig>()
+            .map_or_else(GenerationConfig::default, |config| *config)
     }
 
     pub fn find_entity_attached_to_map(&mut self) -> Vec<Entity> {
@@ -249,9 +864,66 @@ impl State {
     }
 }
 
+/// The monsters hostile to the player that they can see, closest first. Ties are broken in reading order, so that
+/// cycling through them is stable.
+pub fn visible_enemies(world: &World, map: &Map, player_position: Position) -> Vec<Entity> {
+    let mut enemies: Vec<(Position, Entity)> = <(Entity, &Position, &Faction)>::query()
+        .filter(component::<Monster>() & component::<CombatStats>())
+        .iter(world)
+        .filter(|(_, position, faction)| {
+            Faction::Player.is_hostile_to(**faction) && map.is_in_player_fov(position.x, position.y)
+        })
+        .map(|(entity, position, _)| (*position, *entity))
+        .collect();
+    enemies.sort_by(|(a, _), (b, _)| {
+        a.distance_to(player_position)
+            .partial_cmp(&b.distance_to(player_position))
+            .unwrap()
+            .then((a.y, a.x).cmp(&(b.y, b.x)))
+    });
+    enemies.into_iter().map(|(_, entity)| entity).collect()
+}
+
+/// The closest monster hostile to the player that they can see, None when there's no enemy in sight.
+pub fn closest_enemy_in_fov(world: &World, map: &Map, player_position: Position) -> Option<Entity> {
+    visible_enemies(world, map, player_position)
+        .first()
+        .copied()
+}
+
+/// The enemy in sight coming after `current`, going from the closest to the furthest and back.
+/// The closest one when `current` isn't in sight anymore, None when there's no enemy in sight.
+pub fn next_visible_enemy(
+    world: &World,
+    map: &Map,
+    player_position: Position,
+    current: Option<Entity>,
+) -> Option<Entity> {
+    let enemies = visible_enemies(world, map, player_position);
+    if enemies.is_empty() {
+        return None;
+    }
+    let next = current
+        .and_then(|current| enemies.iter().position(|&enemy| enemy == current))
+        .map_or(0, |index| index + 1);
+    Some(enemies[next % enemies.len()])
+}
+
 pub enum Interact {
     Canceled,
     WentDownstairs,
+    WentUpstairs,
+}
+
+/// For how many turns a monster that went out of sight is still remembered where it was last seen.
+pub const GHOST_TURNS: u32 = 10;
+
+/// What the player knows about a monster, for the examine screen.
+pub struct Examined {
+    pub name: String,
+    pub description: &'static str,
+    /// Only known while the monster is in sight, None for the remembered ones.
+    pub stats: Option<CombatStats>,
 }
 
 #[derive(PartialEq, Copy, Clone, Debug)]
@@ -262,8 +934,12 @@ pub enum RunState {
     AiTurn,
     Exit,
     Death,
+    Victory,
     NextLevel,
+    PreviousLevel,
     ShowInventory,
+    /// Looking closely at a monster.
+    ShowExamine,
     ShowTargeting {
         item: Entity,
         range: i32,
@@ -277,10 +953,30 @@ pub struct Targeting {
     pub burst: i32,
 }
 
+/// What a journal entry is about, for the entries to be looked up apart from the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JournalSection {
+    /// Messages about the game itself rather than the dungeon: wizard commands, settings...
+    System,
+    Combat,
+    /// How the adventure goes, like the win and lose messages.
+    Story,
+    /// What the companions of the player are up to.
+    Companion,
+}
+
 pub struct Journal {
     /// The maximum amount of entries to keep in memory.
     size: usize,
     entries: VecDeque<String>,
+    /// The section of each entry of `entries`, if it was logged to one.
+    entry_sections: VecDeque<Option<JournalSection>>,
+    /// The latest entries of each section, newest first. They are also part of `entries`.
+    sections: HashMap<JournalSection, VecDeque<String>>,
+    /// How many entries were logged since the creation of the journal.
+    logged: usize,
+    /// How many entries were logged when the current turn started.
+    turn_start: usize,
 }
 
 impl Journal {
@@ -288,17 +984,375 @@ impl Journal {
         Journal {
             size: 10,
             entries: VecDeque::with_capacity(12),
+            entry_sections: VecDeque::with_capacity(12),
+            sections: HashMap::new(),
+            logged: 0,
+            turn_start: 0,
         }
     }
 
     pub fn log<S: Into<String>>(&mut self, entry: S) {
         self.entries.push_front(entry.into());
-        while self.entries.len() > self.size {
+        self.entry_sections.push_front(None);
+        self.logged += 1;
+        // The entries of the current turn are kept, even past the size, so that none is missed.
+        while self.entries.len() > self.size.max(self.logged_this_turn()) {
             self.entries.pop_back();
+            self.entry_sections.pop_back();
+        }
+    }
+
+    /// Log an entry that can also be found in its section later on.
+    pub fn log_to<S: Into<String>>(&mut self, section: JournalSection, entry: S) {
+        let entry = entry.into();
+        let entries = self.sections.entry(section).or_insert_with(VecDeque::new);
+        entries.push_front(entry.clone());
+        entries.truncate(self.size);
+        self.log(entry);
+        if let Some(entry_section) = self.entry_sections.front_mut() {
+            *entry_section = Some(section);
         }
     }
 
+    /// The section the entry at `index` of `get_entries` was logged to, if any.
+    pub fn entry_section(&self, index: usize) -> Option<JournalSection> {
+        self.entry_sections.get(index).copied().flatten()
+    }
+
+    /// The latest entries of a section, newest first.
+    pub fn section(&self, section: JournalSection) -> impl Iterator<Item = &str> {
+        self.sections
+            .get(&section)
+            .into_iter()
+            .flat_map(|entries| entries.iter().map(String::as_str))
+    }
+
+    /// Forget every entry. The logged count keeps going, so that whoever keeps track of what
+    /// was already read doesn't end up ahead of the journal.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.entry_sections.clear();
+        self.sections.clear();
+        self.turn_start = self.logged;
+    }
+
+    pub fn start_turn(&mut self) {
+        self.turn_start = self.logged;
+    }
+
+    pub fn logged_this_turn(&self) -> usize {
+        self.logged - self.turn_start
+    }
+
     pub fn get_entries(&self) -> &VecDeque<String> {
         &self.entries
     }
+
+    pub fn logged_count(&self) -> usize {
+        self.logged
+    }
+}
+
+/// Knows how to copy every component an item can be made of.
+fn item_duplicate() -> Duplicate {
+    let mut duplicate = Duplicate::default();
+    duplicate.register_clone::<Item>();
+    duplicate.register_clone::<Body>();
+    duplicate.register_clone::<ProvidesHealing>();
+    duplicate.register_clone::<Consumable>();
+    duplicate.register_clone::<Stack>();
+    duplicate.register_clone::<Ranged>();
+    duplicate.register_clone::<Burst>();
+    duplicate.register_clone::<InflictsDamage>();
+    duplicate.register_clone::<SpawnsHazard>();
+    duplicate.register_clone::<Teleports>();
+    duplicate.register_clone::<SummonsCompanion>();
+    duplicate.register_clone::<BoostsVision>();
+    duplicate.register_clone::<InInventory>();
+    duplicate
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{
+        closest_enemy_in_fov, next_visible_enemy, visible_enemies, Journal, JournalSection, State,
+    };
+    use crate::components::{AttackAction, Interactable, Monster, MoveAction};
+    use crate::map::{Map, Position, Tile};
+    use crate::spawner::{self, ItemType, MonsterType};
+    use legion::{Entity, IntoQuery};
+
+    /// A game on an empty 20x20 map, the player standing on `player`.
+    pub(crate) fn test_state(player: Position) -> State {
+        let mut state = State::new(42);
+        state.resources.insert(Map::empty(20, 20));
+        *<&mut Position>::query()
+            .get_mut(&mut state.world, state.player_entity)
+            .unwrap() = player;
+        state
+    }
+
+    #[test]
+    fn journal_keeps_the_whole_turn() {
+        let mut journal = Journal::new();
+        for index in 0..5 {
+            journal.log(format!("Old entry {}", index));
+        }
+
+        journal.start_turn();
+        assert_eq!(journal.logged_this_turn(), 0);
+        for index in 0..15 {
+            journal.log(format!("Entry {}", index));
+        }
+
+        assert_eq!(journal.logged_this_turn(), 15);
+        assert_eq!(journal.get_entries().len(), 15);
+        assert_eq!(journal.get_entries()[14], "Entry 0");
+
+        journal.start_turn();
+        journal.log("Next turn");
+        assert_eq!(journal.get_entries().len(), 10);
+    }
+
+    #[test]
+    fn cleared_journal_keeps_counting() {
+        let mut journal = Journal::new();
+        journal.log_to(JournalSection::Story, "Once upon a time");
+        journal.log_to(JournalSection::Combat, "The orc is dead.");
+        journal.log("Nothing special");
+        assert_eq!(
+            journal.section(JournalSection::Story).collect::<Vec<_>>(),
+            vec!["Once upon a time"]
+        );
+        assert_eq!(journal.section(JournalSection::System).count(), 0);
+
+        journal.clear();
+        assert!(journal.get_entries().is_empty());
+        assert_eq!(journal.section(JournalSection::Combat).count(), 0);
+        assert_eq!(journal.logged_count(), 3);
+        assert_eq!(journal.logged_this_turn(), 0);
+    }
+
+    #[test]
+    fn restart_empties_the_journal() {
+        let mut state = State::new(42);
+        state.log("From the first game");
+        state.restart(43);
+
+        let journal = state.resources.get::<Journal>().unwrap();
+        assert_eq!(
+            journal.get_entries().iter().collect::<Vec<_>>(),
+            vec!["Welcome to Ambergris"]
+        );
+        assert_eq!(journal.logged_count(), 3);
+    }
+
+    #[test]
+    fn restart_keeps_the_journal_sections() {
+        let mut state = State::new(42);
+        state.restart(43);
+
+        let journal = state.resources.get::<Journal>().unwrap();
+        assert_eq!(journal.entry_section(0), Some(JournalSection::Story));
+        assert_eq!(
+            journal.section(JournalSection::Story).collect::<Vec<_>>(),
+            vec!["Welcome to Ambergris"]
+        );
+    }
+
+    #[test]
+    fn out_of_sight_tiles_are_remembered() {
+        let mut state = test_state(Position::new(2, 2));
+        let potion = spawner::item(&mut state.world, ItemType::Potion, 4, 2);
+
+        state.update_player_fov(true);
+        state.update_visibility();
+        let remembered = |state: &State| {
+            let map = state.resources.get::<Map>().unwrap();
+            map.remembered
+                .get(&Position::new(4, 2))
+                .map(|remembered| remembered.description.clone())
+        };
+        assert_eq!(remembered(&state), Some("a potion".to_string()));
+
+        // Gone while the player wasn't looking: the memory stays the same.
+        state.world.remove(potion);
+        state.resources.get_mut::<Map>().unwrap().player_fov.clear();
+        state.update_visibility();
+        assert_eq!(remembered(&state), Some("a potion".to_string()));
+
+        // Back in sight, the player sees it's not there anymore.
+        state.update_player_fov(true);
+        state.update_visibility();
+        assert_eq!(remembered(&state), None);
+    }
+
+    #[test]
+    fn journal_is_saved_oldest_first() {
+        let state = State::new(42);
+        state
+            .resources
+            .get_mut::<Journal>()
+            .unwrap()
+            .log("The last words.");
+
+        let path = std::env::temp_dir().join("ambergris_journal_test.txt");
+        state.save_journal(&path).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert!(saved.starts_with("Score: "));
+        assert!(saved.ends_with("The last words."));
+    }
+
+    #[test]
+    fn examine_only_tells_what_the_player_knows() {
+        let mut state = test_state(Position::new(8, 3));
+        spawner::monster(&mut state.world, MonsterType::Orc, 10, 3);
+        state.update_player_fov(true);
+        state.update_visibility();
+
+        let examined = state.examine(Position::new(10, 3)).unwrap();
+        assert_eq!(examined.name, "orc");
+        assert_eq!(examined.description, spawner::description("orc"));
+        assert_eq!(examined.stats.map(|stats| stats.max_hp), Some(12));
+        assert!(state.examine(Position::new(11, 3)).is_none());
+
+        // Out of sight, the orc is only remembered.
+        state.resources.get_mut::<Map>().unwrap().player_fov.clear();
+        let examined = state.examine(Position::new(10, 3)).unwrap();
+        assert_eq!(examined.name, "orc");
+        assert!(examined.stats.is_none());
+    }
+
+    #[test]
+    fn the_player_swaps_places_with_companions() {
+        let mut state = test_state(Position::new(3, 3));
+        let player = state.player_entity;
+        let dog = spawner::companion(&mut state.world, 4, 3);
+        state
+            .resources
+            .get_mut::<Map>()
+            .unwrap()
+            .set_blocked(Position::new(4, 3), true);
+
+        state.move_player(1, 0);
+
+        let position = |state: &State, entity| {
+            <&Position>::query()
+                .get(&state.world, entity)
+                .map(|position| *position)
+                .unwrap()
+        };
+        assert_eq!(position(&state, dog), Position::new(3, 3));
+        assert!(<&AttackAction>::query().get(&state.world, player).is_err());
+        let moves: Vec<(i32, i32)> = <&MoveAction>::query()
+            .iter(&state.world)
+            .filter(|move_action| move_action.entity == player)
+            .map(|move_action| (move_action.dx, move_action.dy))
+            .collect();
+        assert_eq!(moves, vec![(1, 0)]);
+        let journal = state.resources.get::<Journal>().unwrap();
+        assert_eq!(journal.entry_section(0), Some(JournalSection::Companion));
+    }
+
+    #[test]
+    fn enemies_in_sight_are_sorted_by_distance() {
+        let mut state = test_state(Position::new(8, 3));
+        state
+            .resources
+            .get_mut::<Map>()
+            .unwrap()
+            .tiles
+            .set(10, 3, Tile::wall());
+        spawner::monster(&mut state.world, MonsterType::Troll, 12, 3);
+        spawner::monster(&mut state.world, MonsterType::Orc, 4, 3);
+        spawner::monster(&mut state.world, MonsterType::Orc, 8, 5);
+        state.update_player_fov(true);
+
+        let map = state.resources.get::<Map>().unwrap();
+        let player_position = Position::new(8, 3);
+        let positions: Vec<Position> = visible_enemies(&state.world, &map, player_position)
+            .into_iter()
+            .map(|entity| *<&Position>::query().get(&state.world, entity).unwrap())
+            .collect();
+        // The troll hides behind the wall.
+        assert_eq!(positions, vec![Position::new(8, 5), Position::new(4, 3)]);
+
+        let closest = closest_enemy_in_fov(&state.world, &map, player_position).unwrap();
+        assert_eq!(
+            *<&Position>::query().get(&state.world, closest).unwrap(),
+            Position::new(8, 5)
+        );
+    }
+
+    #[test]
+    fn cycling_through_enemies_loops_around() {
+        let mut state = test_state(Position::new(8, 3));
+        let player = state.player_entity;
+        spawner::monster(&mut state.world, MonsterType::Orc, 4, 3);
+        spawner::monster(&mut state.world, MonsterType::Orc, 8, 5);
+        state.update_player_fov(true);
+
+        let map = state.resources.get::<Map>().unwrap();
+        let player_position = Position::new(8, 3);
+        let enemies = visible_enemies(&state.world, &map, player_position);
+        assert_eq!(enemies.len(), 2);
+        let next = |current| next_visible_enemy(&state.world, &map, player_position, current);
+
+        assert_eq!(next(None), Some(enemies[0]));
+        assert_eq!(next(Some(enemies[0])), Some(enemies[1]));
+        assert_eq!(next(Some(enemies[1])), Some(enemies[0]));
+        // Someone out of sight, back to the closest.
+        assert_eq!(next(Some(player)), Some(enemies[0]));
+    }
+
+    #[test]
+    fn levels_are_restored_as_the_player_left_them() {
+        let mut state = State::new(42);
+        let monsters = |state: &State| -> Vec<Entity> {
+            <(Entity, &Monster)>::query()
+                .iter(&state.world)
+                .map(|(entity, _)| *entity)
+                .collect()
+        };
+        let player_stairs = |state: &State| {
+            let player_position = *<&Position>::query()
+                .get(&state.world, state.player_entity)
+                .unwrap();
+            <(&Position, &Interactable)>::query()
+                .iter(&state.world)
+                .find(|(position, _)| **position == player_position)
+                .map(|(_, interactable)| *interactable)
+        };
+
+        let first_level = monsters(&state);
+        assert!(first_level.len() > 1);
+        state.world.remove(first_level[0]);
+        state.resources.get_mut::<Map>().unwrap().explored_tiles[0] = true;
+
+        state.next_level();
+        let second_level = monsters(&state);
+        assert!(second_level
+            .iter()
+            .all(|monster| !first_level.contains(monster)));
+
+        // No monster comes back from the dead, and nothing is forgotten.
+        state.previous_level();
+        let restored = monsters(&state);
+        assert_eq!(restored.len(), first_level.len() - 1);
+        assert!(restored
+            .iter()
+            .all(|monster| first_level[1..].contains(monster)));
+        assert!(state.resources.get::<Map>().unwrap().explored_tiles[0]);
+        assert_eq!(player_stairs(&state), Some(Interactable::DownStairs));
+
+        state.next_level();
+        let restored = monsters(&state);
+        assert_eq!(restored.len(), second_level.len());
+        assert!(restored
+            .iter()
+            .all(|monster| second_level.contains(monster)));
+        assert_eq!(player_stairs(&state), Some(Interactable::UpStairs));
+    }
 }
@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+
+use crate::{
+    game::{Action, Journal, RunState, State},
+    map::GenerationConfig,
+    resources::{GameEvent, ReplayLog},
+    systems,
+};
+
+/// Runs the game loop without a window, playing a scripted sequence of actions.
+/// Stops when the script runs out, or when the player dies.
+pub struct Engine {
+    actions: VecDeque<Action>,
+    journal: Vec<String>,
+    logged: usize,
+    events: Vec<GameEvent>,
+}
+
+impl Engine {
+    pub fn new<I: IntoIterator<Item = Action>>(actions: I) -> Self {
+        Engine {
+            actions: actions.into_iter().collect(),
+            journal: vec![],
+            logged: 0,
+            events: vec![],
+        }
+    }
+
+    pub fn run(&mut self, state: &mut State) {
+        let mut schedule = systems::game_schedule();
+
+        loop {
+            let previous_state = state.resources.get_or_insert(RunState::Init).clone();
+
+            let new_run_state = match previous_state {
+                RunState::Init => {
+                    schedule.execute(&mut state.world, &mut state.resources);
+                    RunState::WaitForPlayerInput
+                }
+                RunState::PlayerTurn => {
+                    schedule.execute(&mut state.world, &mut state.resources);
+                    RunState::AiTurn
+                }
+                RunState::AiTurn => {
+                    schedule.execute(&mut state.world, &mut state.resources);
+                    state.after_ai_turn()
+                }
+                RunState::WaitForPlayerInput => {
+                    match state
+                        .next_auto_action()
+                        .or_else(|| self.actions.pop_front())
+                    {
+                        Some(action) => state.play(action),
+                        None => RunState::Exit,
+                    }
+                }
+                RunState::NextLevel => {
+                    state.next_level();
+                    RunState::Init
+                }
+                RunState::PreviousLevel => {
+                    state.previous_level();
+                    RunState::Init
+                }
+                // Menus are never opened by a script.
+                RunState::ShowInventory
+                | RunState::ShowExamine
+                | RunState::ShowTargeting { .. } => RunState::WaitForPlayerInput,
+                RunState::Exit | RunState::Death | RunState::Victory => break,
+            };
+
+            state.resources.insert(new_run_state);
+
+            // Monsters only act on what the player can see, so the field of view must be kept
+            // up to date even without anything to render.
+            state.update_player_fov(false);
+            state.update_visibility();
+
+            self.record_journal(state);
+            self.events.append(&mut state.drain_events());
+        }
+    }
+
+    /// Every entry logged during the run, oldest first.
+    pub fn journal(&self) -> &[String] {
+        &self.journal
+    }
+
+    /// Every event pushed by the systems during the run, in order.
+    pub fn events(&self) -> &[GameEvent] {
+        &self.events
+    }
+
+    fn record_journal(&mut self, state: &State) {
+        if let Some(journal) = state.resources.get::<Journal>() {
+            let new_entries = journal.logged_count() - self.logged;
+            // Entries are stored newest first.
+            for entry in journal.get_entries().iter().take(new_entries).rev() {
+                self.journal.push(entry.clone());
+            }
+            self.logged = journal.logged_count();
+        }
+    }
+}
+
+/// Play a recorded game again, with the same generation config. With the same seed,
+/// hand-drawn level, difficulty and actions, the random streams are identical, so the
+/// game unfolds exactly the same way.
+pub fn replay(config: GenerationConfig, log: &ReplayLog) -> Engine {
+    let mut state = State::with_config(config);
+    let mut engine = Engine::new(log.actions());
+    engine.run(&mut state);
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Engine;
+    use crate::components::{Monster, Stealth};
+    use crate::game::{tests::test_state, Action, RunState, State};
+    use crate::map::Position;
+    use crate::spawner::{self, MonsterType};
+    use legion::{Entity, IntoQuery};
+
+    fn position_of(state: &State, entity: Entity) -> Position {
+        *<&Position>::query().get(&state.world, entity).unwrap()
+    }
+
+    #[test]
+    fn monsters_act_once_per_player_turn() {
+        let mut state = test_state(Position::new(15, 5));
+        let monsters: Vec<Entity> = <(Entity, &Monster)>::query()
+            .iter(&state.world)
+            .map(|(entity, _)| *entity)
+            .collect();
+        for monster in monsters {
+            state.world.remove(monster);
+        }
+        let player = state.player_entity;
+        // Spotted right away, this is only about who acts when.
+        state
+            .world
+            .entry(player)
+            .unwrap()
+            .remove_component::<Stealth>();
+        spawner::monster(&mut state.world, MonsterType::Orc, 11, 5);
+        spawner::monster(&mut state.world, MonsterType::Orc, 19, 5);
+        let orcs: Vec<Entity> = <(Entity, &Monster)>::query()
+            .iter(&state.world)
+            .map(|(entity, _)| *entity)
+            .collect();
+        assert_eq!(orcs.len(), 2);
+
+        for turn in 1..=5 {
+            let before: Vec<Position> = orcs.iter().map(|&orc| position_of(&state, orc)).collect();
+
+            // One scripted wait is exactly one player turn, followed by one monster turn.
+            Engine::new(vec![Action::Wait]).run(&mut state);
+            state.resources.insert(RunState::WaitForPlayerInput);
+
+            let after: Vec<Position> = orcs.iter().map(|&orc| position_of(&state, orc)).collect();
+            assert_ne!(after[0], after[1]);
+            for (from, to) in before.iter().zip(after.iter()) {
+                let steps = (to.x - from.x).abs() + (to.y - from.y).abs();
+                if turn <= 3 {
+                    // Closing in, one tile at a time.
+                    assert_eq!(steps, 1, "turn {}: {:?} to {:?}", turn, from, to);
+                } else {
+                    // Next to the player, attacking instead of moving.
+                    assert_eq!(steps, 0, "turn {}: {:?} to {:?}", turn, from, to);
+                }
+            }
+        }
+
+        assert_eq!(
+            position_of(&state, orcs[0]).distance_to(Position::new(15, 5)),
+            1.0
+        );
+        assert_eq!(
+            position_of(&state, orcs[1]).distance_to(Position::new(15, 5)),
+            1.0
+        );
+    }
+}
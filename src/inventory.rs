@@ -1,16 +1,23 @@
 use graphics::character::CharacterCache;
-use legion::{component, Entity, IntoQuery, Read};
+use legion::{component, Entity, IntoQuery, Read, TryRead};
 use piston_window::{Graphics, Key};
 use std::collections::BTreeMap;
 
 use crate::{
-    components::Body, components::InInventory, components::Item, game::State,
+    components::Body, components::BoostsVision, components::Burst, components::InInventory,
+    components::InflictsDamage, components::Item, components::ProvidesHealing, components::Ranged,
+    components::Stack, components::SummonsCompanion, components::Teleports, game::State,
     renderer::draw_window, renderer::RenderContext, renderer::Renderable,
 };
 
+/// Offset from the top of the window to the first item line.
+const FIRST_LINE_OFFSET: i32 = 3;
+
 struct InventoryLine {
     name: String,
+    description: String,
     entities: Vec<Entity>,
+    count: i32,
 }
 
 pub struct Inventory {
@@ -33,16 +40,21 @@ impl Inventory {
     pub fn list_items(&mut self, state: &State) {
         self.items.clear();
 
-        for (entity, _item, body) in <(Entity, Read<Item>, Read<Body>)>::query()
-            .filter(component::<InInventory>())
-            .iter(&state.world)
+        for (entity, _item, body, stack) in
+            <(Entity, Read<Item>, Read<Body>, TryRead<Stack>)>::query()
+                .filter(component::<InInventory>())
+                .iter(&state.world)
         {
+            let count = stack.map_or(1, |stack| stack.count);
             if let Some(inventory_line) = self.items.get_mut(&body.name) {
                 inventory_line.entities.push(entity.clone());
+                inventory_line.count += count;
             } else {
                 let inventory_line = InventoryLine {
                     name: body.name.clone(),
+                    description: describe(state, *entity),
                     entities: vec![entity.clone()],
+                    count,
                 };
                 self.items.insert(body.name.clone(), inventory_line);
             }
@@ -52,31 +64,41 @@ impl Inventory {
         }
     }
 
-    pub fn set_mouse(&mut self, _mouse_position: [i32; 2]) {}
+    /// Hovering an item line selects it.
+    pub fn set_mouse(&mut self, mouse_position: [i32; 2]) {
+        let [x, y] = mouse_position;
+        if x <= self.origin.0 || x >= self.origin.0 + self.size.0 - 1 {
+            return;
+        }
+
+        let line = y - self.origin.1 - FIRST_LINE_OFFSET;
+        if line >= 0 && line < self.items.len() as i32 {
+            self.selected_line = line;
+        }
+    }
 
     pub fn on_keyboard(&mut self, key: &Key) -> InventoryAction {
         match key {
-            Key::Up | Key::W => {
+            Key::Up => {
                 self.selected_line = (self.selected_line - 1).max(0);
                 InventoryAction::Selecting
             }
-            Key::Down | Key::S => {
+            Key::Down => {
                 self.selected_line = (self.selected_line + 1).min(self.items.len() as i32 - 1);
                 InventoryAction::Selecting
             }
             Key::Escape => InventoryAction::Close,
             Key::Return | Key::NumPadEnter => self.pick(self.selected_line),
-            Key::D => self.drop(self.selected_line),
-            Key::D1 | Key::NumPad1 => self.pick(0),
-            Key::D2 | Key::NumPad2 => self.pick(1),
-            Key::D3 | Key::NumPad3 => self.pick(2),
-            Key::D4 | Key::NumPad4 => self.pick(3),
-            Key::D5 | Key::NumPad5 => self.pick(4),
-            Key::D6 | Key::NumPad6 => self.pick(5),
-            Key::D7 | Key::NumPad7 => self.pick(6),
-            Key::D8 | Key::NumPad8 => self.pick(7),
-            Key::D9 | Key::NumPad9 => self.pick(8),
-            _ => InventoryAction::Selecting,
+            Key::Delete => self.drop(self.selected_line, false),
+            Key::Backspace => self.drop(self.selected_line, true),
+            key => {
+                if let Some(index) = letter_index(key) {
+                    if index < self.items.len() {
+                        self.selected_line = index as i32;
+                    }
+                }
+                InventoryAction::Selecting
+            }
         }
     }
 
@@ -100,7 +122,7 @@ impl Inventory {
         }
     }
 
-    fn drop(&mut self, index: i32) -> InventoryAction {
+    fn drop(&mut self, index: i32, whole_stack: bool) -> InventoryAction {
         if index >= 0 && index < self.items.len() as i32 {
             let key = self.items.keys().nth(index as usize).unwrap().clone();
             let item = self.items.get_mut(&key).unwrap();
@@ -111,7 +133,10 @@ impl Inventory {
                     self.items.remove(&key);
                 }
 
-                InventoryAction::Drop { entity }
+                InventoryAction::Drop {
+                    entity,
+                    whole_stack,
+                }
             } else {
                 InventoryAction::Selecting
             }
@@ -145,7 +170,7 @@ impl Renderable for Inventory {
             render_context.graphics,
         );
 
-        let mut y = 8;
+        let mut y = self.origin.1 + FIRST_LINE_OFFSET;
 
         for (index, (_key, item)) in self.items.iter().enumerate() {
             if self.selected_line == index as i32 {
@@ -159,21 +184,22 @@ impl Renderable for Inventory {
                 )
             }
 
-            let shortcut = if index < 9 {
-                format!("{}", index + 1)
-            } else {
-                String::from(" ")
-            };
+            let shortcut = letter(index).unwrap_or(' ');
 
-            let text = match item.entities.len() {
-                1 => format!("{shortcut}. {name}", shortcut = shortcut, name = item.name),
+            let text = match item.count {
+                1 => format!("{shortcut}) {name}", shortcut = shortcut, name = item.name),
                 _ => format!(
-                    "{shortcut}. {name} (x{quantity})",
+                    "{shortcut}) {name} (x{quantity})",
                     shortcut = shortcut,
                     name = item.name,
-                    quantity = item.entities.len()
+                    quantity = item.count
                 ),
             };
+            let text = if item.description.is_empty() {
+                text
+            } else {
+                format!("{} - {}", text, item.description)
+            };
 
             crate::renderer::draw_text(
                 self.origin.0 + 1,
@@ -189,12 +215,73 @@ impl Renderable for Inventory {
             .ok();
             y += 1;
         }
+
+        crate::renderer::draw_text(
+            self.origin.0 + 1,
+            self.origin.1 + self.size.1 - 2,
+            10,
+            crate::colors::WHITE.into(),
+            render_context.grid_size,
+            "[a-z] select  [enter] use  [del] drop one  [backspace] drop all  [esc] close",
+            render_context.character_cache,
+            render_context.context,
+            render_context.graphics,
+        )
+        .ok();
+    }
+}
+
+/// The shortcut of the item at the given line, from 'a' to 'z'.
+fn letter(index: usize) -> Option<char> {
+    if index < 26 {
+        Some((b'a' + index as u8) as char)
+    } else {
+        None
+    }
+}
+
+fn letter_index(key: &Key) -> Option<usize> {
+    let code = *key as u32;
+    if code >= Key::A as u32 && code <= Key::Z as u32 {
+        Some((code - Key::A as u32) as usize)
+    } else {
+        None
+    }
+}
+
+/// A short summary of what the item does.
+fn describe(state: &State, entity: Entity) -> String {
+    let mut effects = vec![];
+    if let Ok(healing) = <&ProvidesHealing>::query().get(&state.world, entity) {
+        effects.push(format!("heals {}", healing.heal_amount));
+    }
+    if let Ok(damage) = <&InflictsDamage>::query().get(&state.world, entity) {
+        effects.push(format!("{} damage", damage.damage));
+    }
+    if let Ok(ranged) = <&Ranged>::query().get(&state.world, entity) {
+        effects.push(format!("range {}", ranged.range));
+    }
+    if let Ok(burst) = <&Burst>::query().get(&state.world, entity) {
+        effects.push(format!("radius {}", burst.radius));
+    }
+    if let Ok(boost) = <&BoostsVision>::query().get(&state.world, entity) {
+        effects.push(format!("sight +{} for {} turns", boost.amount, boost.turns));
+    }
+    if <&Teleports>::query().get(&state.world, entity).is_ok() {
+        effects.push("teleports".to_string());
+    }
+    if <&SummonsCompanion>::query()
+        .get(&state.world, entity)
+        .is_ok()
+    {
+        effects.push("summons a companion".to_string());
     }
+    effects.join(", ")
 }
 
 pub enum InventoryAction {
     Close,
     Pick { entity: Entity },
     Selecting,
-    Drop { entity: Entity },
+    Drop { entity: Entity, whole_stack: bool },
 }
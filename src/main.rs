@@ -1,47 +1,95 @@
-use crate::game::State;
+use crate::game::{Action, State};
+use crate::headlessengine::{replay, Engine as HeadlessEngine};
+use crate::map::{Difficulty, GenerationConfig};
 use crate::pistonengine::Engine as PistonEngine;
-use crate::resources::SharedInfo;
-
-use game::Journal;
-use legion::{Resources, World};
+use crate::resources::ReplayLog;
 
 mod colors;
 mod components;
 mod game;
+mod headlessengine;
 mod inventory;
 mod map;
 mod palette;
 mod pistonengine;
+mod prefab;
 mod renderer;
 mod resources;
+mod score;
 mod spawner;
 mod systems;
+mod tables;
+mod theme;
 mod utils;
 
 // actual size of the window
 const SCREEN_WIDTH: i32 = 80;
 const SCREEN_HEIGHT: i32 = 50;
+/// Where the journal of the last game ends up when the window closes.
+const JOURNAL_FILE: &str = "journal.txt";
 
 fn main() {
-    let mut world = World::default();
-    let mut resources = Resources::default();
-    let player_entity = spawner::player(&mut world, -1, -1);
-    let map = crate::map::make_map(&mut world, 1);
-    let journal = Journal::new();
-    resources.insert(map);
-    resources.insert(journal);
-    resources.insert(SharedInfo {
-        player_entity: player_entity,
-        player_position: (-1, -1).into(),
-        alive: true,
-    });
-    let mut state = State {
-        world,
-        resources,
-        player_entity,
+    let seed = GenerationConfig::default().seed;
+    // Play a level drawn by hand, for instance `--level arena`, instead of the generated first floor.
+    let first_level = std::env::args()
+        .skip_while(|arg| arg != "--level")
+        .nth(1)
+        .and_then(|name| {
+            let level = prefab::level(&name);
+            if level.is_none() {
+                println!(
+                    "There is no level named {:?}, generating one instead.",
+                    name
+                );
+            }
+            level
+        });
+    // `--difficulty easy` or `--difficulty hard`, normal otherwise.
+    let difficulty = std::env::args()
+        .skip_while(|arg| arg != "--difficulty")
+        .nth(1)
+        .and_then(|name| Difficulty::from_name(&name))
+        .unwrap_or_default();
+    let config = GenerationConfig {
+        seed,
+        first_level,
+        difficulty,
+        ..GenerationConfig::default()
     };
-    state.log("Welcome to Ambergris");
+    let mut state = State::with_config(config);
+
+    let mut args = std::env::args().skip(1);
+    if let Some("--headless") = args.next().as_deref() {
+        // Play a scripted game, for instance `--headless ddddsg`, and print what happened.
+        let script = args.next().unwrap_or_default();
+        let mut engine = HeadlessEngine::new(script.chars().filter_map(Action::from_char));
+        engine.run(&mut state);
+        for entry in engine.journal() {
+            println!("{}", entry);
+        }
+        for event in engine.events() {
+            println!("{:?}", event);
+        }
+        println!("Score: {}", state.high_score().points);
+
+        // Check that the game can be reproduced from the seed and the recorded actions.
+        if let Some(replay_log) = state.resources.get::<ReplayLog>() {
+            for entry in replay_log.entries() {
+                println!("Turn {}: {:?}", entry.turn, entry.action);
+            }
+            if replay(config, &replay_log).journal() != engine.journal() {
+                println!("The replay diverged from the recorded game!");
+            }
+        }
+        return;
+    }
 
     let mut renderer = PistonEngine::new("Ambergris", SCREEN_WIDTH, SCREEN_HEIGHT);
+    renderer.set_unicode_walls(std::env::args().any(|arg| arg == "--unicode-walls"));
+    renderer.set_on_exit(|state| {
+        if let Err(error) = state.save_journal(JOURNAL_FILE) {
+            println!("Couldn't save the journal: {}", error);
+        }
+    });
     renderer.run(&mut state);
 }
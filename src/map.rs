@@ -1,14 +1,25 @@
 use crate::{
+    colors::{self, Color},
     components::*,
-    spawner::{self, MonsterType},
+    palette,
+    prefab::{self, Prefab, PrefabCell},
+    resources::{FovAlgorithm, GameRng},
+    spawner::{self, ItemType, MonsterType},
+    tables::WeightedTable,
 };
 
 use legion::component;
 use legion::IntoQuery;
 use legion::World;
+use rand::rngs::StdRng;
 use rand::Rng;
-use rand::{rngs::StdRng, SeedableRng};
-use torchbearer::fov::field_of_view;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Debug, Formatter};
+use torchbearer::bresenham::BresenhamLine;
+use torchbearer::fov::{
+    field_of_view, field_of_view_with_options, shadowcast_field_of_view, FovOptions,
+};
+use torchbearer::Grid;
 use torchbearer::Map as FieldOfVisionMap;
 
 const MAP_WIDTH: i32 = 80;
@@ -16,10 +27,160 @@ const MAP_HEIGHT: i32 = 40;
 const ROOM_MAX_SIZE: i32 = 10;
 const ROOM_MIN_SIZE: i32 = 6;
 const MAX_ROOM: i32 = 30;
-const MAX_ROOM_MONSTERS: i32 = 3;
-const MAX_ROOM_ITEMS: i32 = 3;
+/// Chances for a room to be replaced by a prefab.
+const PREFAB_CHANCE: f32 = 0.1;
+/// Chances for an orc to come with friends.
+const PACK_CHANCE: f32 = 0.3;
+const MONSTER_WEIGHTS: &[(MonsterType, u32)] = &[
+    (MonsterType::Orc, 70),
+    (MonsterType::Troll, 20),
+    (MonsterType::Shaman, 10),
+];
+const ITEM_WEIGHTS: &[(ItemType, u32)] = &[
+    (ItemType::Potion, 30),
+    (ItemType::ScrollOfLightningBolt, 15),
+    (ItemType::ScrollOfFireball, 15),
+    (ItemType::ScrollOfPoisonCloud, 15),
+    (ItemType::ScrollOfTeleport, 12),
+    (ItemType::ScrollOfCompanion, 5),
+    (ItemType::Torch, 13),
+];
+/// How far one can see on the first floor, it gets darker one step per floor.
+const SURFACE_LIGHT_RADIUS: i32 = 11;
+const MIN_LIGHT_RADIUS: i32 = 3;
+/// How lit the furthest visible tiles are, so that they still stand out from the explored ones.
+const EDGE_LIGHT: f32 = 0.25;
+/// Chances for a room to have a torch burning in its center.
+const LIT_ROOM_CHANCE: f32 = 0.2;
+
+pub const CONNECTS_NORTH: u8 = 1;
+pub const CONNECTS_EAST: u8 = 2;
+pub const CONNECTS_SOUTH: u8 = 4;
+pub const CONNECTS_WEST: u8 = 8;
+
+/// Tuning of the map generation. Densities grow linearly with the depth, so that
+/// shallow levels can stay sparse while deeper ones get crowded.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct GenerationConfig {
+    /// The seed of the game, each level derives its own from it.
+    pub seed: u64,
+    /// Maximum number of monsters per room on the first level.
+    pub max_room_monsters: i32,
+    /// Maximum number of items per room on the first level.
+    pub max_room_items: i32,
+    /// Extra monsters allowed per room for each level below the first.
+    pub monsters_per_depth: f32,
+    /// Extra items allowed per room for each level below the first.
+    pub items_per_depth: f32,
+    /// The deepest level, where the quest item lies instead of the stairs going down.
+    pub final_depth: i32,
+    pub diagonals: DiagonalRules,
+    /// A level drawn by hand to play instead of the generated first floor, see `Map::from_ascii`.
+    pub first_level: Option<&'static str>,
+    pub difficulty: Difficulty,
+}
 
+/// How hard the dungeon is, bundling the tuning of the monsters, the player and the light.
 #[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty::Normal
+    }
+}
+
+impl Difficulty {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+
+    /// Multiplies the health and attack of the monsters.
+    pub fn monster_strength(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.75,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.25,
+        }
+    }
+
+    /// Multiplies how many monsters a room can hold.
+    pub fn spawn_density(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.7,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.4,
+        }
+    }
+
+    /// Multiplies the health the player starts with.
+    pub fn player_health(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 1.5,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 0.75,
+        }
+    }
+
+    /// Added to the light radius of every floor, capping how far the player sees.
+    pub fn light_bonus(&self) -> i32 {
+        match self {
+            Difficulty::Easy => 2,
+            Difficulty::Normal => 0,
+            Difficulty::Hard => -2,
+        }
+    }
+}
+
+/// Whether the gaps between two walls touching by their corners let the sight and the creatures through,
+/// each on its own. See [`FovOptions`] for how the four combinations feel.
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct DiagonalRules {
+    pub blocks_sight: bool,
+    pub blocks_movement: bool,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        GenerationConfig {
+            seed: 42,
+            max_room_monsters: 3,
+            max_room_items: 3,
+            monsters_per_depth: 0.0,
+            items_per_depth: 0.0,
+            final_depth: 5,
+            diagonals: DiagonalRules::default(),
+            first_level: None,
+            difficulty: Difficulty::default(),
+        }
+    }
+}
+
+impl GenerationConfig {
+    pub fn max_room_monsters(&self, depth: i32) -> i32 {
+        let max = scale(self.max_room_monsters, self.monsters_per_depth, depth);
+        (max as f32 * self.difficulty.spawn_density()).round() as i32
+    }
+
+    pub fn max_room_items(&self, depth: i32) -> i32 {
+        scale(self.max_room_items, self.items_per_depth, depth)
+    }
+}
+
+fn scale(base: i32, per_depth: f32, depth: i32) -> i32 {
+    (base + (per_depth * (depth - 1).max(0) as f32) as i32).max(0)
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
@@ -46,36 +207,124 @@ impl Position {
         ((self.x - position.x).pow(2) as f32 + (self.y - position.y).pow(2) as f32).sqrt()
     }
 
+    /// The number of king moves between the two positions, cheaper than `distance_to` for rough checks.
+    pub fn chebyshev_distance_to(&self, position: Position) -> i32 {
+        (self.x - position.x).abs().max((self.y - position.y).abs())
+    }
+
     pub fn set_position(&mut self, position: &Position) {
         self.x = position.x;
         self.y = position.y;
     }
 }
 
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum DoorState {
+    Open,
+    Closed,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum TileKind {
+    Floor,
+    Wall,
+    Door(DoorState),
+    Water,
+}
+
+impl TileKind {
+    pub fn blocks_movement(&self) -> bool {
+        match self {
+            TileKind::Floor | TileKind::Door(DoorState::Open) => false,
+            TileKind::Wall | TileKind::Door(DoorState::Closed) | TileKind::Water => true,
+        }
+    }
+
+    pub fn blocks_sight(&self) -> bool {
+        match self {
+            TileKind::Floor | TileKind::Door(DoorState::Open) | TileKind::Water => false,
+            TileKind::Wall | TileKind::Door(DoorState::Closed) => true,
+        }
+    }
+}
+
+/// Why a map drawn with characters couldn't be read.
+#[derive(Debug, PartialEq)]
+pub enum AsciiError {
+    Empty,
+    UnknownCharacter { character: char, position: Position },
+}
+
+impl fmt::Display for AsciiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AsciiError::Empty => write!(f, "The map is empty"),
+            AsciiError::UnknownCharacter {
+                character,
+                position,
+            } => write!(
+                f,
+                "Unknown character {:?} at {}, {}",
+                character, position.x, position.y
+            ),
+        }
+    }
+}
+
+/// Every character of a map drawn with characters, with its position.
+fn ascii_cells(ascii: &str) -> impl Iterator<Item = (Position, char)> + '_ {
+    ascii.lines().enumerate().flat_map(|(y, row)| {
+        row.chars()
+            .enumerate()
+            .map(move |(x, character)| (Position::new(x as i32, y as i32), character))
+    })
+}
+
+/// What stands on a map drawn with characters, and where: monsters, items, the player and the stairs.
+pub fn spawn_markers(ascii: &str) -> Vec<(Position, PrefabCell)> {
+    ascii_cells(ascii)
+        .filter_map(|(position, character)| {
+            PrefabCell::from_char(character).map(|cell| (position, cell))
+        })
+        .filter(|(_, cell)| *cell != PrefabCell::Wall && *cell != PrefabCell::Floor)
+        .collect()
+}
+
 #[derive(Clone)]
 pub struct Tile {
-    pub blocking: bool,
-    pub block_sight: bool,
+    pub kind: TileKind,
 }
 
 impl Tile {
     pub fn empty() -> Self {
         Tile {
-            blocking: false,
-            block_sight: false,
+            kind: TileKind::Floor,
         }
     }
 
     pub fn wall() -> Self {
         Tile {
-            blocking: true,
-            block_sight: true,
+            kind: TileKind::Wall,
         }
     }
+
+    pub fn blocks_movement(&self) -> bool {
+        self.kind.blocks_movement()
+    }
+
+    pub fn blocks_sight(&self) -> bool {
+        self.kind.blocks_sight()
+    }
+}
+
+enum RoomShape {
+    Rectangle,
+    Circle,
+    Cross,
 }
 
 #[derive(Debug)]
-struct Rect {
+pub struct Rect {
     x1: i32,
     x2: i32,
     y1: i32,
@@ -99,6 +348,29 @@ impl Rect {
         (center_x, center_y)
     }
 
+    /// Whether the prefab fits inside the room, centered.
+    fn fits(&self, prefab: &Prefab) -> bool {
+        let (origin_x, origin_y) = self.prefab_origin(prefab);
+
+        origin_x > self.x1
+            && origin_y > self.y1
+            && origin_x + prefab.width <= self.x2
+            && origin_y + prefab.height <= self.y2
+    }
+
+    /// Where to place the top left corner of the prefab so that both centers match.
+    fn prefab_origin(&self, prefab: &Prefab) -> (i32, i32) {
+        let (center_x, center_y) = self.center();
+        let (prefab_center_x, prefab_center_y) = prefab.center();
+
+        (center_x - prefab_center_x, center_y - prefab_center_y)
+    }
+
+    /// Whether the position is inside the room, walls excluded.
+    fn is_inside(&self, position: Position) -> bool {
+        position.x > self.x1 && position.x < self.x2 && position.y > self.y1 && position.y < self.y2
+    }
+
     fn intersects_with(&self, other: &Rect) -> bool {
         (self.x1 <= other.x2)
             && (self.x2 >= other.x1)
@@ -106,45 +378,580 @@ impl Rect {
             && (self.y2 >= other.y1)
     }
 }
+/// What the player saw last on an explored tile, so that looking at it out of sight doesn't reveal
+/// what is there now.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RememberedTile {
+    /// The name of the body, for the theme to draw it.
+    pub name: String,
+    pub char: char,
+    pub color: Color,
+    pub description: String,
+    /// Creatures don't stay put, they are shown as fading ghosts rather than from memory.
+    pub creature: bool,
+}
+
+/// A mark left on the floor, drawn under whatever stands on the tile. Only for the looks,
+/// it doesn't change what can be seen or walked through.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decal {
+    pub char: char,
+    pub color: Color,
+}
+
+impl Decal {
+    /// Spilled by a creature taking a hit.
+    pub fn blood() -> Self {
+        Decal {
+            char: ',',
+            color: palette::BLOOD,
+        }
+    }
+
+    /// Left by the flames.
+    pub fn scorch() -> Self {
+        Decal {
+            char: '.',
+            color: palette::SCORCH,
+        }
+    }
+}
+
 pub struct Map {
     pub width: i32,
     pub height: i32,
-    pub tiles: Vec<Tile>,
+    pub tiles: Grid<Tile>,
     pub explored_tiles: Vec<bool>,
     pub blocked: Vec<bool>,
     pub player_fov: Vec<(i32, i32)>,
+    /// The radius used for the last calculation of the player's field of view.
+    pub player_fov_radius: i32,
+    /// Set when a tile starts or stops blocking the sight, as the player's field of view needs to be recalculated.
+    /// A new map starts with it set, having never been seen.
+    pub transparency_changed: bool,
+    /// How lit each tile is, from 0.0 (dark) to 1.0 (right next to the player), filled with the player's field of view.
+    pub light_levels: Vec<f32>,
+    /// How much light the light sources of the level bring to each tile, whether the player sees it or not.
+    pub ambient_light: Vec<f32>,
+    /// The blended color of the light sources reaching each tile.
+    pub ambient_colors: Vec<Color>,
+    /// Set when the light sources need to shine again, like when a wall stops blocking the sight.
+    pub lighting_dirty: bool,
     pub depth: i32,
+    /// How loud it is on each tile. Fades away with time.
+    pub noise_map: Vec<i32>,
+    /// When set, collects the tiles that become explored as the player's field of view is calculated,
+    /// so that the reveal can be animated. Left to `None` when nobody is watching.
+    pub on_explored: Option<Vec<(i32, i32)>>,
+    /// How far the light reaches on this floor, capping the player's sight.
+    pub base_light_radius: i32,
+    /// The topmost thing the player last saw on each explored tile. Tiles last seen empty are left out.
+    pub remembered: HashMap<Position, RememberedTile>,
+    pub diagonals: DiagonalRules,
+    /// How many steps away from the player each tile is, None when it can't be reached.
+    /// Shared by every monster rather than each of them looking for a path.
+    pub player_distances: Vec<Option<i32>>,
+    /// Where the player stood when the distances were calculated, None when they need an update.
+    pub player_distances_from: Option<Position>,
+    /// The marks left by the fights on each tile, the latest one covering the others.
+    pub decals: Vec<Option<Decal>>,
 }
 
 impl Map {
+    /// A map filled with `tile`, as dark as the floors at this depth.
+    fn new(width: i32, height: i32, tile: Tile, depth: i32) -> Self {
+        let map_size = (width * height) as usize;
+        Map {
+            width,
+            height,
+            tiles: Grid::new(width, height, tile),
+            explored_tiles: vec![false; map_size],
+            blocked: vec![false; map_size],
+            player_fov: vec![],
+            player_fov_radius: 0,
+            transparency_changed: true,
+            light_levels: vec![0.0; map_size],
+            ambient_light: vec![0.0; map_size],
+            ambient_colors: vec![colors::BLACK; map_size],
+            lighting_dirty: true,
+            depth,
+            noise_map: vec![0; map_size],
+            on_explored: None,
+            base_light_radius: (SURFACE_LIGHT_RADIUS - depth).max(MIN_LIGHT_RADIUS),
+            remembered: HashMap::new(),
+            diagonals: DiagonalRules::default(),
+            player_distances: vec![None; map_size],
+            player_distances_from: None,
+            decals: vec![None; map_size],
+        }
+    }
+
+    /// A map without any wall, for the tests.
+    #[cfg(test)]
+    pub fn empty(width: i32, height: i32) -> Self {
+        Map {
+            base_light_radius: 10,
+            ..Map::new(width, height, Tile::empty(), 1)
+        }
+    }
+
+    /// Read a map drawn with characters, one row per line: `#` for the walls, `.` for the floors,
+    /// `'` and `+` for open and closed doors, `~` for the water. The spawn markers of the prefabs
+    /// stand on floors, see `spawn_markers` to know what to spawn where.
+    /// The map is as wide as its longest row, shorter rows and spaces are filled with walls.
+    pub fn from_ascii(ascii: &str) -> Result<Self, AsciiError> {
+        let height = ascii.lines().count() as i32;
+        let width = ascii
+            .lines()
+            .map(|row| row.chars().count())
+            .max()
+            .unwrap_or(0) as i32;
+        if width == 0 {
+            return Err(AsciiError::Empty);
+        }
+
+        let mut map = Map::new(width, height, Tile::wall(), 1);
+        for (position, character) in ascii_cells(ascii) {
+            let kind = match character {
+                '#' | ' ' => TileKind::Wall,
+                '\'' => TileKind::Door(DoorState::Open),
+                '+' => TileKind::Door(DoorState::Closed),
+                '~' => TileKind::Water,
+                _ if PrefabCell::from_char(character).is_some() => TileKind::Floor,
+                _ => {
+                    return Err(AsciiError::UnknownCharacter {
+                        character,
+                        position,
+                    })
+                }
+            };
+            map.tiles.set(position.x, position.y, Tile { kind });
+        }
+        Ok(map)
+    }
+
+    /// Draw the tiles of the map with the characters of `from_ascii`. What stands on them is left out.
+    pub fn to_ascii(&self) -> String {
+        let rows: Vec<String> = (0..self.height)
+            .map(|y| {
+                (0..self.width)
+                    .map(|x| match self.tiles.get(x, y).map(|tile| tile.kind) {
+                        Some(TileKind::Floor) => '.',
+                        Some(TileKind::Door(DoorState::Open)) => '\'',
+                        Some(TileKind::Door(DoorState::Closed)) => '+',
+                        Some(TileKind::Water) => '~',
+                        Some(TileKind::Wall) | None => '#',
+                    })
+                    .collect()
+            })
+            .collect();
+        rows.join("\n")
+    }
+
+    /// Out of the map, everything is blocked.
     pub fn is_blocked(&self, position: Position) -> bool {
-        self.blocked[self.index(position)]
+        self.index(position)
+            .map_or(true, |index| self.blocked[index])
     }
 
+    /// Does nothing out of the map.
     pub fn set_blocked(&mut self, position: Position, is_blocked: bool) {
-        let index = self.index(position);
-        self.blocked[index] = is_blocked;
+        if let Some(index) = self.index(position) {
+            self.blocked[index] = is_blocked;
+        }
     }
 
-    pub fn index(&self, position: Position) -> usize {
-        let (x, y) = position.into();
-        if x < 0 || x >= self.width || y < 0 || y >= self.height {
-            0
-        } else {
-            (x + y * self.width) as usize
+    /// All the tiles of the map, row by row, with their position.
+    pub fn iter_tiles(&self) -> impl Iterator<Item = (Position, &Tile)> {
+        self.tiles
+            .iter()
+            .map(|(position, tile)| (position.into(), tile))
+    }
+
+    pub fn iter_tiles_mut(&mut self) -> impl Iterator<Item = (Position, &mut Tile)> {
+        self.tiles
+            .iter_mut()
+            .map(|(position, tile)| (position.into(), tile))
+    }
+
+    /// Whether a creature standing at `from` can step by `(dx, dy)`: staying on the map, on a free tile,
+    /// and not squeezing diagonally between two walls when the level forbids it.
+    pub fn can_step(&self, from: Position, dx: i32, dy: i32) -> bool {
+        let to = Position::new(from.x + dx, from.y + dy);
+        if !self.is_in_bounds(to.x, to.y) || self.is_blocked(to) {
+            return false;
         }
+
+        let squeezes = dx != 0
+            && dy != 0
+            && !self.is_walkable(from.x + dx, from.y)
+            && !self.is_walkable(from.x, from.y + dy);
+        !(squeezes && self.diagonals.blocks_movement)
+    }
+
+    /// Flood the map from the player's position, unless they didn't move since the last time.
+    /// Only walls get in the way, creatures move around.
+    pub fn update_player_distances(&mut self, player: Position) {
+        if self.player_distances_from == Some(player) {
+            return;
+        }
+
+        self.player_distances
+            .iter_mut()
+            .for_each(|distance| *distance = None);
+        let mut queue = VecDeque::new();
+        if let Some(index) = self.index(player) {
+            self.player_distances[index] = Some(0);
+            queue.push_back((player, 0));
+        }
+        while let Some((position, distance)) = queue.pop_front() {
+            for &(dx, dy) in &[(0, -1), (1, 0), (0, 1), (-1, 0)] {
+                let next = Position::new(position.x + dx, position.y + dy);
+                if !self.is_walkable(next.x, next.y) {
+                    continue;
+                }
+                if let Some(index) = self.index(next) {
+                    if self.player_distances[index].is_none() {
+                        self.player_distances[index] = Some(distance + 1);
+                        queue.push_back((next, distance + 1));
+                    }
+                }
+            }
+        }
+
+        self.player_distances_from = Some(player);
+    }
+
+    /// How many steps away from the player a tile is, as of the last update.
+    pub fn distance_to_player(&self, position: Position) -> Option<i32> {
+        self.index(position)
+            .and_then(|index| self.player_distances[index])
+    }
+
+    /// The free step bringing a creature closest to the player, if any gets it any closer.
+    pub fn step_towards_player(&self, position: Position) -> Option<(i32, i32)> {
+        let mut best = self.distance_to_player(position)?;
+        let mut step = None;
+        for &(dx, dy) in &[(0, -1), (1, 0), (0, 1), (-1, 0)] {
+            let next = Position::new(position.x + dx, position.y + dy);
+            if self.is_blocked(next) {
+                continue;
+            }
+            if let Some(distance) = self.distance_to_player(next) {
+                if distance < best {
+                    best = distance;
+                    step = Some((dx, dy));
+                }
+            }
+        }
+        step
+    }
+
+    /// A step getting closer to `target` as the crow flies, for the targets other than the player,
+    /// who have no distance map. None if every step closer is blocked.
+    pub fn step_towards(&self, position: Position, target: Position) -> Option<(i32, i32)> {
+        let mut best = position.distance_to(target);
+        let mut step = None;
+        for &(dx, dy) in &[(0, -1), (1, 0), (0, 1), (-1, 0)] {
+            let next = Position::new(position.x + dx, position.y + dy);
+            if !self.is_walkable(next.x, next.y) || self.is_blocked(next) {
+                continue;
+            }
+            let distance = next.distance_to(target);
+            if distance < best {
+                best = distance;
+                step = Some((dx, dy));
+            }
+        }
+        step
+    }
+
+    /// The index of the tile at `position` in the map's grids, None if it is out of the map.
+    pub fn index(&self, position: Position) -> Option<usize> {
+        self.tiles.index(position.x, position.y)
     }
 
     pub fn is_in_bounds(&self, x: i32, y: i32) -> bool {
-        x >= 0 && x < self.width && y >= 0 && y < self.height
+        self.tiles.in_bounds(x, y)
+    }
+
+    pub fn is_explored(&self, x: i32, y: i32) -> bool {
+        self.index((x, y).into())
+            .map_or(false, |index| self.explored_tiles[index])
+    }
+
+    /// How much of the walkable part of the map was explored, from 0.0 to 1.0.
+    /// A map without any walkable tile has nothing left to explore.
+    pub fn explored_ratio(&self) -> f32 {
+        let (walkable, explored) = self
+            .tiles
+            .iter()
+            .filter(|(_, tile)| !tile.blocks_movement())
+            .fold((0, 0), |(walkable, explored), ((x, y), _)| {
+                (walkable + 1, explored + self.is_explored(x, y) as i32)
+            });
+        if walkable == 0 {
+            1.0
+        } else {
+            explored as f32 / walkable as f32
+        }
     }
 
     pub fn is_in_player_fov(&self, x: i32, y: i32) -> bool {
         self.player_fov.contains(&(x, y))
     }
 
-    pub fn calculate_player_fov(&mut self, x: i32, y: i32, radius: i32) {
-        self.player_fov = field_of_view(self, (x, y), radius);
+    /// How much light reaches the tile at `(x, y)`, 0.0 if it is out of the player's sight.
+    pub fn light_at(&self, x: i32, y: i32) -> f32 {
+        if self.is_in_bounds(x, y) {
+            self.light_levels[(x + y * self.width) as usize]
+        } else {
+            0.0
+        }
+    }
+
+    /// The color of the light sources reaching the tile at `(x, y)`, and how strong it is.
+    pub fn ambient_light_at(&self, x: i32, y: i32) -> Option<(Color, f32)> {
+        if !self.is_in_bounds(x, y) {
+            return None;
+        }
+        let index = (x + y * self.width) as usize;
+        if self.ambient_light[index] > 0.0 {
+            Some((self.ambient_colors[index], self.ambient_light[index]))
+        } else {
+            None
+        }
+    }
+
+    pub fn clear_ambient_light(&mut self) {
+        for light in self.ambient_light.iter_mut() {
+            *light = 0.0;
+        }
+    }
+
+    /// Shine a light from `origin`, blending its color with the lights already reaching the tiles.
+    pub fn add_light(&mut self, origin: Position, radius: i32, color: Color) {
+        if !self.is_in_bounds(origin.x, origin.y) {
+            return;
+        }
+
+        for (x, y) in field_of_view(self, origin.into(), radius) {
+            let index = (x + y * self.width) as usize;
+            let light = light_falloff(x - origin.x, y - origin.y, radius);
+            let existing = self.ambient_light[index];
+
+            self.ambient_colors[index] = if existing > 0.0 {
+                self.ambient_colors[index].lerp(color, light / (existing + light))
+            } else {
+                color
+            };
+            self.ambient_light[index] = (existing + light).min(1.0);
+        }
+    }
+
+    /// Change a tile, flagging the player's field of view for recalculation if it changes what can be seen.
+    pub fn set_tile(&mut self, position: Position, tile: Tile) {
+        if let Some(index) = self.index(position) {
+            if self.tiles[index].blocks_sight() != tile.blocks_sight() {
+                self.transparency_changed = true;
+                self.lighting_dirty = true;
+            }
+            if self.tiles[index].blocks_movement() != tile.blocks_movement() {
+                self.player_distances_from = None;
+            }
+            self.tiles[index] = tile;
+        }
+    }
+
+    pub fn calculate_player_fov(&mut self, x: i32, y: i32, radius: i32, algorithm: FovAlgorithm) {
+        self.player_fov_radius = radius;
+        self.player_fov = match algorithm {
+            FovAlgorithm::Raycast => {
+                let options = FovOptions {
+                    diagonal_walls_block: self.diagonals.blocks_sight,
+                    ..FovOptions::default()
+                };
+                field_of_view_with_options(self, (x, y), radius, options)
+            }
+            FovAlgorithm::Shadowcast => shadowcast_field_of_view(self, (x, y), radius),
+        };
+
+        for light in self.light_levels.iter_mut() {
+            *light = 0.0;
+        }
+
+        let (origin_x, origin_y) = (x, y);
+        for &(x, y) in self.player_fov.iter() {
+            let index = (x + y * self.width) as usize;
+            self.light_levels[index] = light_falloff(x - origin_x, y - origin_y, radius);
+        }
+
+        // Lit tiles can be seen from afar, as long as nothing is in the way.
+        if self.ambient_light.iter().any(|&light| light > 0.0) {
+            let far_sight = self.width.max(self.height);
+            for (x, y) in field_of_view(self, (origin_x, origin_y), far_sight) {
+                let index = (x + y * self.width) as usize;
+                let ambient_light = self.ambient_light[index];
+                if ambient_light > 0.0 {
+                    if self.light_levels[index] == 0.0 {
+                        self.player_fov.push((x, y));
+                    }
+                    self.light_levels[index] = self.light_levels[index].max(ambient_light);
+                }
+            }
+        }
+
+        for &(x, y) in self.player_fov.iter() {
+            let index = (x + y * self.width) as usize;
+
+            let explored = &mut self.explored_tiles[index];
+            if !*explored {
+                *explored = true;
+                if let Some(on_explored) = &mut self.on_explored {
+                    on_explored.push((x, y));
+                }
+            }
+        }
+    }
+
+    /// Bitmask of the orthogonal neighboors of `(x, y)` that are explored walls, made of the `CONNECTS_*` flags.
+    /// Unexplored walls are left out, or the rock around the rooms would connect everything.
+    pub fn wall_connections(&self, x: i32, y: i32) -> u8 {
+        let mut connections = 0;
+        for &(dx, dy, flag) in &[
+            (0, -1, CONNECTS_NORTH),
+            (1, 0, CONNECTS_EAST),
+            (0, 1, CONNECTS_SOUTH),
+            (-1, 0, CONNECTS_WEST),
+        ] {
+            let (x, y) = (x + dx, y + dy);
+            if self.is_in_bounds(x, y) {
+                let index = (x + y * self.width) as usize;
+                if self.tiles[index].kind == TileKind::Wall && self.explored_tiles[index] {
+                    connections |= flag;
+                }
+            }
+        }
+
+        connections
+    }
+
+    /// Spread a noise from `origin` through the walkable tiles, getting quieter with each step.
+    pub fn make_noise(&mut self, origin: Position, loudness: i32) {
+        let origin_index = match self.index(origin) {
+            Some(index) => index,
+            None => return,
+        };
+
+        let mut visited = vec![false; self.tiles.len()];
+        let mut frontier = VecDeque::new();
+        visited[origin_index] = true;
+        frontier.push_back((origin, origin_index, loudness));
+
+        while let Some((position, index, noise)) = frontier.pop_front() {
+            self.noise_map[index] = self.noise_map[index].max(noise);
+            if noise <= 1 {
+                continue;
+            }
+
+            for &(dx, dy) in &[(0, -1), (1, 0), (0, 1), (-1, 0)] {
+                let neighboor = Position::new(position.x + dx, position.y + dy);
+                if let Some(index) = self.index(neighboor) {
+                    if !visited[index] && self.is_walkable(neighboor.x, neighboor.y) {
+                        visited[index] = true;
+                        frontier.push_back((neighboor, index, noise - 1));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn noise_at(&self, position: Position) -> i32 {
+        self.index(position)
+            .map_or(0, |index| self.noise_map[index])
+    }
+
+    pub fn add_decal(&mut self, position: Position, decal: Decal) {
+        if let Some(index) = self.index(position) {
+            self.decals[index] = Some(decal);
+        }
+    }
+
+    pub fn decal_at(&self, position: Position) -> Option<Decal> {
+        self.index(position).and_then(|index| self.decals[index])
+    }
+
+    /// Breadth first search around `from`, returning the closest tile that is in bounds,
+    /// not a wall and not occupied.
+    pub fn find_nearest_walkable(&self, from: Position) -> Option<Position> {
+        let from_index = self.index(from)?;
+
+        let mut visited = vec![false; self.tiles.len()];
+        let mut frontier = VecDeque::new();
+        visited[from_index] = true;
+        frontier.push_back(from);
+
+        while let Some(position) = frontier.pop_front() {
+            if self.is_walkable(position.x, position.y) && !self.is_blocked(position) {
+                return Some(position);
+            }
+
+            for &(dx, dy) in &[(0, -1), (1, 0), (0, 1), (-1, 0)] {
+                let neighboor = Position::new(position.x + dx, position.y + dy);
+                if let Some(index) = self.index(neighboor) {
+                    if !visited[index] {
+                        visited[index] = true;
+                        frontier.push_back(neighboor);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Walk the Bresenham line from `from` to `to`, and return the first tile blocking movement.
+    /// The movement counterpart of a sight ray, `from` itself is not checked.
+    pub fn first_blocking_on_line(&self, from: Position, to: Position) -> Option<Position> {
+        BresenhamLine::new(from.into(), to.into())
+            .skip(1)
+            .map(Position::from)
+            .find(|position| !self.is_walkable(position.x, position.y))
+    }
+
+    /// A random walkable and free tile that the player can't currently see.
+    pub fn random_tile_outside_player_fov<R: Rng>(&self, rng: &mut R) -> Option<Position> {
+        let mut candidates = vec![];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.is_walkable(x, y)
+                    && !self.is_blocked((x, y).into())
+                    && !self.is_in_player_fov(x, y)
+                {
+                    candidates.push(Position::new(x, y));
+                }
+            }
+        }
+
+        if candidates.is_empty() {
+            None
+        } else {
+            Some(candidates[rng.gen_range(0, candidates.len())])
+        }
+    }
+
+    /// Every tile inside the walls of the room where an object could be placed:
+    /// walkable, and not already blocked, by the stairs for instance.
+    pub fn spawn_points(&self, room: &Rect) -> Vec<Position> {
+        let mut spawn_points = vec![];
+        for y in (room.y1 + 1)..room.y2 {
+            for x in (room.x1 + 1)..room.x2 {
+                if self.is_walkable(x, y) && !self.is_blocked((x, y).into()) {
+                    spawn_points.push(Position::new(x, y));
+                }
+            }
+        }
+        spawn_points
     }
 }
 
@@ -154,27 +961,53 @@ impl FieldOfVisionMap for Map {
     }
 
     fn is_transparent(&self, x: i32, y: i32) -> bool {
-        !self.tiles[(x + y * self.width) as usize].block_sight
+        self.tiles
+            .get(x, y)
+            .map_or(false, |tile| !tile.blocks_sight())
     }
 
     fn is_walkable(&self, x: i32, y: i32) -> bool {
-        !self.tiles[(x + y * self.width) as usize].blocking
+        self.tiles
+            .get(x, y)
+            .map_or(false, |tile| !tile.blocks_movement())
+    }
+}
+
+impl Debug for Map {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.tiles
+            .fmt_ascii(f, |_, tile| if tile.blocks_movement() { '#' } else { '.' })
+    }
+}
+
+/// Light fades linearly with the distance, down to `EDGE_LIGHT` at the edge of the sight.
+fn light_falloff(dx: i32, dy: i32, radius: i32) -> f32 {
+    if radius <= 0 {
+        return 1.0;
     }
+    let distance = ((dx * dx + dy * dy) as f32).sqrt();
+    (1.0 - (1.0 - EDGE_LIGHT) * distance / radius as f32).max(EDGE_LIGHT)
 }
 
-pub fn make_map(world: &mut World, level: i32) -> Map {
-    let mut rng = StdRng::seed_from_u64(42 + level as u64);
-    let map_size = MAP_HEIGHT as usize * MAP_WIDTH as usize;
-    let mut map = Map {
-        width: MAP_WIDTH,
-        height: MAP_HEIGHT,
-        tiles: vec![Tile::wall(); map_size],
-        explored_tiles: vec![false; map_size],
-        blocked: vec![false; map_size],
-        player_fov: vec![],
-        depth: level,
+pub fn make_map(world: &mut World, level: i32, config: &GenerationConfig) -> Map {
+    let mut map = match (level, config.first_level) {
+        (1, Some(ascii)) => load_level(world, ascii).expect("The first level can't be read"),
+        _ => generate_map(world, level, config),
     };
+    map.diagonals = config.diagonals;
+    map.base_light_radius =
+        (map.base_light_radius + config.difficulty.light_bonus()).max(MIN_LIGHT_RADIUS);
+    // Everything fighting on this level was just spawned with it.
+    toughen_monsters(world, config.difficulty.monster_strength());
+
+    map
+}
+
+fn generate_map(world: &mut World, level: i32, config: &GenerationConfig) -> Map {
+    let mut rng = GameRng::stream(config.seed, level as u64);
+    let mut map = Map::new(MAP_WIDTH, MAP_HEIGHT, Tile::wall(), level);
 
+    let prefabs = prefab::prefabs();
     let mut rooms: Vec<Rect> = vec![];
 
     for _ in 0..MAX_ROOM {
@@ -192,14 +1025,43 @@ pub fn make_map(world: &mut World, level: i32) -> Map {
     }
 
     for (index, new_room) in rooms.iter().enumerate() {
-        create_room(&new_room, &mut map);
+        // No prefab where the player arrives or leaves.
+        let is_prefab_allowed = index > 0 && index < rooms.len() - 1;
+        let fitting_prefabs: Vec<&Prefab> = prefabs
+            .iter()
+            .filter(|prefab| new_room.fits(prefab))
+            .collect();
+        let prefab =
+            if is_prefab_allowed && !fitting_prefabs.is_empty() && rng.gen::<f32>() < PREFAB_CHANCE
+            {
+                Some(fitting_prefabs[rng.gen_range(0, fitting_prefabs.len())])
+            } else {
+                None
+            };
+
+        if let Some(prefab) = prefab {
+            stamp_prefab(world, &mut map, &new_room, prefab);
+        } else {
+            // Mostly rectangles, with a few fancier rooms for variety.
+            let shape = match rng.gen::<f32>() {
+                r if r < 0.7 => RoomShape::Rectangle,
+                r if r < 0.85 => RoomShape::Circle,
+                _ => RoomShape::Cross,
+            };
+            match shape {
+                RoomShape::Rectangle => create_room(&new_room, &mut map),
+                RoomShape::Circle => create_circular_room(&new_room, &mut map),
+                RoomShape::Cross => create_cross_room(&new_room, &mut map),
+            }
+        }
 
         let (new_x, new_y) = new_room.center();
         if index == 0 {
-            let mut query = <&mut Position>::query().filter(component::<Player>());
-            for coordinates in query.iter_mut(world) {
-                coordinates.x = new_x;
-                coordinates.y = new_y;
+            let spawn = place_player(world, &mut map, &new_room);
+
+            if level > 1 {
+                // The way back up is where the player arrives.
+                spawner::up_stairs(world, spawn.x, spawn.y);
             }
         } else {
             let (prev_x, prev_y) = rooms[index - 1].center();
@@ -214,13 +1076,24 @@ pub fn make_map(world: &mut World, level: i32) -> Map {
         }
 
         if index == rooms.len() - 1 {
-            // Last room, let's place the exit.
-            place_stairs(world, &mut map, &new_room);
-            println!("Placing stairs in room {:?}", new_room);
+            if level >= config.final_depth {
+                // Nowhere else to go, the amulet waits here.
+                let (x, y) = new_room.center();
+                spawner::macguffin(world, x, y);
+                map.set_blocked((x, y).into(), true);
+            } else {
+                // Last room, let's place the exit.
+                place_stairs(world, &mut map, &new_room);
+                println!("Placing stairs in room {:?}", new_room);
+            }
         }
-        if !rooms.is_empty() {
+        if prefab.is_none() {
             // Let's be cool and not put any monsters in the room.
-            place_objects(world, &mut rng, &map, &new_room);
+            place_objects(world, &mut rng, &mut map, &new_room, config);
+        }
+        if rng.gen::<f32>() < LIT_ROOM_CHANCE {
+            let radius = (new_room.x2 - new_room.x1).max(new_room.y2 - new_room.y1) / 2 + 1;
+            spawner::light_source(world, new_x, new_y, radius, colors::ORANGE);
         }
     }
 
@@ -228,62 +1101,504 @@ pub fn make_map(world: &mut World, level: i32) -> Map {
 }
 
 fn create_room(room: &Rect, map: &mut Map) {
-    for x in (room.x1 + 1)..room.x2 {
-        for y in (room.y1 + 1)..room.y2 {
-            map.tiles[x as usize + y as usize * map.width as usize] = Tile::empty();
+    for (_, tile) in map
+        .iter_tiles_mut()
+        .filter(|(position, _)| room.is_inside(*position))
+    {
+        *tile = Tile::empty();
+    }
+}
+
+/// A round room, as big as the smallest side of the rect allows.
+fn create_circular_room(room: &Rect, map: &mut Map) {
+    let (center_x, center_y) = room.center();
+    let radius = ((room.x2 - room.x1).min(room.y2 - room.y1) - 2) / 2;
+
+    for (position, tile) in map.iter_tiles_mut() {
+        let distance_square = (position.x - center_x).pow(2) + (position.y - center_y).pow(2);
+        // Adding the radius smooths the edges.
+        if room.is_inside(position) && distance_square <= radius * radius + radius {
+            *tile = Tile::empty();
+        }
+    }
+}
+
+/// A plus shaped room, made of two bars crossing at the center of the rect.
+fn create_cross_room(room: &Rect, map: &mut Map) {
+    let (center_x, center_y) = room.center();
+    let half_thickness_x = (room.x2 - room.x1) / 6;
+    let half_thickness_y = (room.y2 - room.y1) / 6;
+
+    for (position, tile) in map.iter_tiles_mut() {
+        if room.is_inside(position)
+            && ((position.x - center_x).abs() <= half_thickness_x
+                || (position.y - center_y).abs() <= half_thickness_y)
+        {
+            *tile = Tile::empty();
+        }
+    }
+}
+
+fn toughen_monsters(world: &mut World, strength: f32) {
+    if strength == 1.0 {
+        return;
+    }
+    let mut query = <&mut CombatStats>::query().filter(component::<Monster>());
+    for combat_stats in query.iter_mut(world) {
+        *combat_stats = combat_stats.scaled(strength);
+    }
+}
+
+/// Read a level drawn by hand and spawn what it's marked with. The player starts on `@`,
+/// or in the middle of the map when there is none.
+fn load_level(world: &mut World, ascii: &str) -> Result<Map, AsciiError> {
+    let mut map = Map::from_ascii(ascii)?;
+
+    let center = Position::new(map.width / 2, map.height / 2);
+    let mut player_spawn = map.find_nearest_walkable(center).unwrap_or(center);
+    for (position, cell) in spawn_markers(ascii) {
+        if cell == PrefabCell::Player {
+            player_spawn = position;
+        } else {
+            cell.spawn(world, position.x, position.y);
+        }
+        map.set_blocked(position, true);
+    }
+
+    let mut query = <&mut Position>::query().filter(component::<Player>());
+    for coordinates in query.iter_mut(world) {
+        coordinates.set_position(&player_spawn);
+    }
+    map.set_blocked(player_spawn, true);
+
+    Ok(map)
+}
+
+/// Stamp the prefab in the middle of the room. As the centers match, the tunnels
+/// reaching the center of the room connect the prefab to the rest of the dungeon.
+fn stamp_prefab(world: &mut World, map: &mut Map, room: &Rect, prefab: &Prefab) {
+    let (origin_x, origin_y) = room.prefab_origin(prefab);
+
+    for y in 0..prefab.height {
+        for x in 0..prefab.width {
+            let cell = prefab.cell(x, y);
+            if cell.is_walkable() {
+                let (map_x, map_y) = (origin_x + x, origin_y + y);
+                map.set_tile(Position::new(map_x, map_y), Tile::empty());
+                cell.spawn(world, map_x, map_y);
+            }
         }
     }
 }
 
 fn create_horizontal_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
     for x in x1.min(x2)..(x1.max(x2) + 1) {
-        map.tiles[x as usize + y as usize * map.width as usize] = Tile::empty();
+        map.set_tile(Position::new(x, y), Tile::empty());
     }
 }
 fn create_vertical_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
     for y in y1.min(y2)..(y1.max(y2) + 1) {
-        map.tiles[x as usize + y as usize * map.width as usize] = Tile::empty();
+        map.set_tile(Position::new(x, y), Tile::empty());
     }
 }
 
-fn place_objects(world: &mut World, rng: &mut StdRng, map: &Map, room: &Rect) {
-    let num_monsters = rng.gen_range(0, MAX_ROOM_MONSTERS);
+/// Fill the room with monsters and items. Every tile taken gets blocked, so that nothing else
+/// spawns there until the first turn sorts out what really blocks.
+fn place_objects(
+    world: &mut World,
+    rng: &mut StdRng,
+    map: &mut Map,
+    room: &Rect,
+    config: &GenerationConfig,
+) {
+    let mut spawn_points = map.spawn_points(room);
+    let monster_table = WeightedTable::from(MONSTER_WEIGHTS);
+    let item_table = WeightedTable::from(ITEM_WEIGHTS);
 
+    let num_monsters = rng.gen_range(0, config.max_room_monsters(map.depth).max(1));
     for _ in 0..num_monsters {
-        let x = rng.gen_range(room.x1 + 1, room.x2);
-        let y = rng.gen_range(room.y1 + 1, room.y2);
-
-        if !map.is_blocked((x, y).into()) {
-            let monster_type = if rng.gen::<f32>() < 0.8 {
-                MonsterType::Orc
+        if let Some(position) = take_random(rng, &mut spawn_points) {
+            let monster_type = *monster_table.sample(rng);
+            // Trolls are loners.
+            if monster_type == MonsterType::Orc && rng.gen::<f32>() < PACK_CHANCE {
+                let count = rng.gen_range(2, 5);
+                for placed in spawner::pack(world, monster_type, position, count, &mut spawn_points)
+                {
+                    map.set_blocked(placed, true);
+                }
             } else {
-                MonsterType::Troll
-            };
-            spawner::monster(world, monster_type, x, y);
+                spawner::monster(world, monster_type, position.x, position.y);
+                map.set_blocked(position, true);
+            }
         }
     }
 
-    let num_items = rng.gen_range(0, MAX_ROOM_ITEMS);
+    let num_items = rng.gen_range(0, config.max_room_items(map.depth).max(1));
     for _ in 0..num_items {
-        let x = rng.gen_range(room.x1 + 1, room.x2);
-        let y = rng.gen_range(room.y1 + 1, room.y2);
-
-        if !map.is_blocked((x, y).into()) {
-            match rng.gen::<f32>() {
-                r if r < 0.33 => {
-                    spawner::potion(world, x, y);
-                }
-                r if r < 0.66 => {
-                    spawner::scroll_of_lightning_bolt(world, x, y);
-                }
-                _ => spawner::scroll_of_fireball(world, x, y),
-            }
+        if let Some(position) = take_random(rng, &mut spawn_points) {
+            spawner::item(world, *item_table.sample(rng), position.x, position.y);
+            map.set_blocked(position, true);
         }
     }
 }
 
+/// Remove a random position from the list, so that it can't be picked twice.
+fn take_random(rng: &mut StdRng, positions: &mut Vec<Position>) -> Option<Position> {
+    if positions.is_empty() {
+        None
+    } else {
+        let index = rng.gen_range(0, positions.len());
+        Some(positions.swap_remove(index))
+    }
+}
+
+/// Put the player on the walkable tile closest to the center of the room, whatever the shape of the room.
+fn place_player(world: &mut World, map: &mut Map, room: &Rect) -> Position {
+    let center = room.center().into();
+    let spawn = map.find_nearest_walkable(center).unwrap_or(center);
+    let mut query = <&mut Position>::query().filter(component::<Player>());
+    for coordinates in query.iter_mut(world) {
+        coordinates.set_position(&spawn);
+    }
+    // Nothing else should spawn on top of the player.
+    map.set_blocked(spawn, true);
+
+    spawn
+}
+
 fn place_stairs(world: &mut World, map: &mut Map, room: &Rect) {
     let (x, y) = room.center();
     spawner::stairs(world, x, y);
     map.set_blocked((x, y).into(), true);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        make_map, place_player, spawn_markers, AsciiError, DiagonalRules, Difficulty,
+        GenerationConfig, Map, Position, Rect, Tile,
+    };
+    use crate::colors;
+    use crate::components::{Body, CombatStats, Interactable, Monster, Player};
+    use crate::prefab::{self, PrefabCell};
+    use crate::resources::FovAlgorithm;
+    use crate::spawner::{self, PlayerTemplate};
+    use legion::{IntoQuery, World};
+    use std::collections::HashSet;
+    use torchbearer::Map as FieldOfVisionMap;
+
+    #[test]
+    fn index_out_of_bounds() {
+        let mut map = Map::empty(10, 10);
+
+        assert_eq!(map.index(Position::new(3, 2)), Some(23));
+        assert_eq!(map.index(Position::new(-1, 0)), None);
+        assert_eq!(map.index(Position::new(0, 10)), None);
+
+        assert!(map.is_blocked(Position::new(10, 0)));
+        map.set_blocked(Position::new(-1, -1), true);
+        assert!(!map.is_blocked(Position::new(0, 0)));
+    }
+
+    #[test]
+    fn set_tile_flags_transparency_changes() {
+        let mut map = Map::empty(10, 10);
+        map.transparency_changed = false;
+
+        map.set_tile(Position::new(2, 2), Tile::empty());
+        assert!(!map.transparency_changed);
+
+        map.set_tile(Position::new(2, 2), Tile::wall());
+        assert!(map.transparency_changed);
+    }
+
+    #[test]
+    fn lit_tiles_are_seen_from_afar() {
+        let mut map = Map::empty(30, 10);
+        map.add_light(Position::new(25, 5), 2, colors::ORANGE);
+
+        assert_eq!(map.ambient_light_at(25, 5), Some((colors::ORANGE, 1.0)));
+        assert_eq!(map.ambient_light_at(20, 5), None);
+
+        map.calculate_player_fov(2, 5, 3, FovAlgorithm::Raycast);
+        assert!(map.is_in_player_fov(25, 5));
+        assert!(!map.is_in_player_fov(20, 5));
+        assert_eq!(map.light_at(25, 5), 1.0);
+
+        // Unless there's a wall in the way.
+        map.set_tile(Position::new(10, 5), Tile::wall());
+        map.calculate_player_fov(2, 5, 3, FovAlgorithm::Raycast);
+        assert!(!map.is_in_player_fov(25, 5));
+    }
+
+    #[test]
+    fn is_walkable_out_of_bounds() {
+        let map = Map::empty(10, 10);
+
+        assert!(!map.is_walkable(-1, -1));
+        assert!(!map.is_walkable(10, 0));
+        assert!(!map.is_walkable(0, 10));
+        assert!(map.is_walkable(0, 0));
+    }
+
+    #[test]
+    fn explored_ratio_only_counts_walkable_tiles() {
+        let mut map = Map::empty(4, 2);
+        assert_eq!(map.explored_ratio(), 0.0);
+
+        for x in 0..4 {
+            map.set_tile(Position::new(x, 0), Tile::wall());
+        }
+        map.explored_tiles = vec![true; 5].into_iter().chain(vec![false; 3]).collect();
+        assert_eq!(map.explored_ratio(), 0.25);
+
+        map.explored_tiles = vec![true; 8];
+        assert_eq!(map.explored_ratio(), 1.0);
+
+        let walls = Map::from_ascii("###").unwrap();
+        assert_eq!(walls.explored_ratio(), 1.0);
+    }
+
+    #[test]
+    fn iter_tiles_goes_row_by_row() {
+        let mut map = Map::empty(3, 2);
+        map.set_tile(Position::new(2, 0), Tile::wall());
+
+        let positions: Vec<Position> = map.iter_tiles().map(|(position, _)| position).collect();
+        assert_eq!(positions.len(), 6);
+        assert_eq!(positions[2], Position::new(2, 0));
+        assert_eq!(positions[3], Position::new(0, 1));
+
+        let walls: Vec<Position> = map
+            .iter_tiles()
+            .filter(|(_, tile)| tile.blocks_movement())
+            .map(|(position, _)| position)
+            .collect();
+        assert_eq!(walls, vec![Position::new(2, 0)]);
+
+        for (_, tile) in map.iter_tiles_mut() {
+            *tile = Tile::wall();
+        }
+        assert!(map.iter_tiles().all(|(_, tile)| tile.blocks_movement()));
+    }
+
+    #[test]
+    fn diagonal_rules_are_independent() {
+        let mut map = Map::empty(5, 5);
+        map.set_tile(Position::new(2, 1), Tile::wall());
+        map.set_tile(Position::new(1, 2), Tile::wall());
+        let from = Position::new(1, 1);
+
+        for &(blocks_sight, blocks_movement) in
+            &[(false, false), (true, false), (false, true), (true, true)]
+        {
+            map.diagonals = DiagonalRules {
+                blocks_sight,
+                blocks_movement,
+            };
+            map.calculate_player_fov(1, 1, 4, FovAlgorithm::Raycast);
+
+            assert_eq!(map.is_in_player_fov(2, 2), !blocks_sight);
+            assert_eq!(map.can_step(from, 1, 1), !blocks_movement);
+            assert!(!map.can_step(from, 1, 0));
+            assert!(map.can_step(from, -1, -1));
+        }
+    }
+
+    #[test]
+    fn first_blocking_on_line_finds_walls() {
+        let mut map = Map::empty(10, 10);
+        map.tiles.set(5, 2, Tile::wall());
+        map.tiles.set(7, 2, Tile::wall());
+
+        assert_eq!(
+            map.first_blocking_on_line(Position::new(1, 2), Position::new(9, 2)),
+            Some(Position::new(5, 2))
+        );
+        assert_eq!(
+            map.first_blocking_on_line(Position::new(1, 3), Position::new(9, 3)),
+            None
+        );
+    }
+
+    #[test]
+    fn player_is_placed_on_a_walkable_tile() {
+        let mut world = World::default();
+        let player = spawner::player(&mut world, -1, -1, &PlayerTemplate::default());
+        let mut map = Map::empty(10, 10);
+        let room = Rect::new(1, 1, 6, 6);
+        let (center_x, center_y) = room.center();
+        map.set_tile(Position::new(center_x, center_y), Tile::wall());
+
+        let spawn = place_player(&mut world, &mut map, &room);
+
+        assert_ne!(spawn, Position::new(center_x, center_y));
+        assert!(map.is_walkable(spawn.x, spawn.y));
+        assert!(map.is_blocked(spawn));
+        assert!(!map.spawn_points(&room).contains(&spawn));
+        assert_eq!(*<&Position>::query().get(&world, player).unwrap(), spawn);
+    }
+
+    #[test]
+    fn player_distances_go_around_walls() {
+        let mut map = Map::empty(5, 5);
+        for y in 0..4 {
+            map.tiles.set(2, y, Tile::wall());
+        }
+        map.update_player_distances(Position::new(0, 0));
+
+        assert_eq!(map.distance_to_player(Position::new(0, 0)), Some(0));
+        assert_eq!(map.distance_to_player(Position::new(2, 0)), None);
+        // Down, through the gap at the bottom, and back up.
+        assert_eq!(map.distance_to_player(Position::new(3, 0)), Some(11));
+        assert_eq!(map.step_towards_player(Position::new(3, 0)), Some((0, 1)));
+
+        map.set_blocked(Position::new(3, 1), true);
+        assert_eq!(map.step_towards_player(Position::new(3, 0)), None);
+    }
+
+    #[test]
+    fn crowded_levels_never_stack_spawns() {
+        for seed in 0..10 {
+            let mut world = World::default();
+            spawner::player(&mut world, 0, 0, &PlayerTemplate::default());
+            let config = GenerationConfig {
+                seed,
+                max_room_monsters: 30,
+                max_room_items: 30,
+                final_depth: 1,
+                ..GenerationConfig::default()
+            };
+            make_map(&mut world, 1, &config);
+
+            let mut taken = HashSet::new();
+            for (_, position) in <(&Body, &Position)>::query().iter(&world) {
+                assert!(taken.insert(*position), "Two bodies on {:?}", position);
+            }
+        }
+    }
+
+    #[test]
+    fn difficulty_tunes_the_whole_level() {
+        let level = |difficulty| {
+            let mut world = World::default();
+            spawner::player(&mut world, 0, 0, &PlayerTemplate::default());
+            let config = GenerationConfig {
+                difficulty,
+                ..GenerationConfig::default()
+            };
+            let map = make_map(&mut world, 1, &config);
+            let troll_hp: Vec<i32> = <(&Body, &CombatStats)>::query()
+                .iter(&world)
+                .filter(|(body, _)| body.name == "troll")
+                .map(|(_, combat_stats)| combat_stats.max_hp)
+                .collect();
+            (config.max_room_monsters(1), map.base_light_radius, troll_hp)
+        };
+
+        let (easy_monsters, easy_light, _) = level(Difficulty::Easy);
+        let (normal_monsters, normal_light, normal_trolls) = level(Difficulty::Normal);
+        let (hard_monsters, hard_light, hard_trolls) = level(Difficulty::Hard);
+
+        assert_eq!(
+            normal_monsters,
+            GenerationConfig::default().max_room_monsters
+        );
+        assert!(easy_monsters < normal_monsters && normal_monsters < hard_monsters);
+        assert!(easy_light > normal_light && normal_light > hard_light);
+        assert!(normal_trolls.iter().all(|&hp| hp == 20));
+        assert!(hard_trolls.iter().all(|&hp| hp == 25));
+    }
+
+    #[test]
+    fn ascii_maps_round_trip() {
+        let ascii = "######\n#.'~.#\n#+o..#\n######";
+        let map = Map::from_ascii(ascii).unwrap();
+
+        assert_eq!((map.width, map.height), (6, 4));
+        assert!(map.is_walkable(2, 2));
+        assert!(!map.is_walkable(1, 2));
+        assert_eq!(map.to_ascii(), ascii.replace('o', "."));
+        assert_eq!(
+            Map::from_ascii(&map.to_ascii()).unwrap().to_ascii(),
+            map.to_ascii()
+        );
+        assert_eq!(
+            spawn_markers(ascii),
+            vec![(Position::new(2, 2), PrefabCell::Orc)]
+        );
+    }
+
+    #[test]
+    fn ascii_maps_are_as_wide_as_their_longest_row() {
+        let map = Map::from_ascii("###\r\n#.\r\n#. \r\n####\r\n").unwrap();
+
+        assert_eq!((map.width, map.height), (4, 4));
+        assert_eq!(map.to_ascii(), "####\n#.##\n#.##\n####");
+
+        assert_eq!(Map::from_ascii("").err(), Some(AsciiError::Empty));
+        assert_eq!(
+            Map::from_ascii("##\n#x").err(),
+            Some(AsciiError::UnknownCharacter {
+                character: 'x',
+                position: Position::new(1, 1)
+            })
+        );
+    }
+
+    #[test]
+    fn hand_drawn_levels_replace_the_first_floor() {
+        let mut world = World::default();
+        let player = spawner::player(&mut world, -1, -1, &PlayerTemplate::default());
+        let config = GenerationConfig {
+            first_level: prefab::level("arena"),
+            ..GenerationConfig::default()
+        };
+
+        let map = make_map(&mut world, 1, &config);
+
+        assert_eq!((map.width, map.height), (20, 9));
+        assert_eq!(
+            *<&Position>::query().get(&world, player).unwrap(),
+            Position::new(1, 1)
+        );
+        assert!(map.is_blocked(Position::new(1, 1)));
+        let stairs: Vec<_> = <(&Position, &Interactable)>::query()
+            .iter(&world)
+            .map(|(position, _)| *position)
+            .collect();
+        assert_eq!(stairs, vec![Position::new(18, 6)]);
+        assert_eq!(<&Monster>::query().iter(&world).count(), 2);
+        assert_eq!(<&Player>::query().iter(&world).count(), 1);
+
+        // Further down, the levels are generated again.
+        let deeper = make_map(&mut world, 2, &config);
+        assert_ne!((deeper.width, deeper.height), (20, 9));
+    }
+
+    #[test]
+    fn spawn_points_skip_walls_and_blocked_tiles() {
+        let mut map = Map::empty(10, 10);
+        map.tiles.set(2, 2, Tile::wall());
+        map.set_blocked((3, 3).into(), true);
+
+        let spawn_points = map.spawn_points(&Rect::new(1, 1, 3, 3));
+
+        assert_eq!(spawn_points, vec![Position::new(3, 2), Position::new(2, 3)]);
+    }
+
+    #[test]
+    fn light_fades_away_from_the_player() {
+        let mut map = Map::empty(20, 20);
+        map.calculate_player_fov(10, 10, 4, FovAlgorithm::Raycast);
+
+        assert_eq!(map.light_at(10, 10), 1.0);
+        assert!(map.light_at(11, 10) > map.light_at(13, 10));
+        assert_eq!(map.light_at(14, 10), 0.25);
+        assert_eq!(map.light_at(16, 10), 0.0);
+        assert_eq!(map.light_at(-1, 10), 0.0);
+
+        map.calculate_player_fov(2, 2, 4, FovAlgorithm::Raycast);
+        assert_eq!(map.light_at(10, 10), 0.0);
+    }
+}
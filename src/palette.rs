@@ -3,8 +3,18 @@ use crate::colors::Color;
 pub const WINDOW_BACKGROUND: Color = Color::from_argb(0xaa000000);
 pub const SELECTED: Color = Color::from_argb(0x66ff0000);
 pub const OVERLAY: Color = Color::from_argb(0x3300ff00);
+pub const FIRE: Color = Color::from_argb(0x66ff6600);
+pub const GAS: Color = Color::from_argb(0x6699cc33);
+pub const NOISE_QUIET: Color = Color::from_argb(0x220000ff);
+pub const NOISE_LOUD: Color = Color::from_argb(0x99ff0000);
 pub const HEALTH: Color = Color::new(255, 191, 0, 0);
 pub const MANA: Color = Color::new(255, 0, 0, 191);
+/// The companions of the player, and what the journal says about them.
+pub const COMPANION: Color = Color::new(255, 120, 200, 255);
+pub const BLOOD: Color = Color::new(255, 120, 0, 0);
+pub const SCORCH: Color = Color::new(255, 60, 45, 35);
+/// The hud warning that the monsters in sight noticed the player.
+pub const SPOTTED: Color = Color::new(255, 255, 80, 80);
 pub const DARK_WALL: Color = Color::new(255, 0, 0, 100);
 pub const LIGHT_WALL: Color = Color {
     a: 255,
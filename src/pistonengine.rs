@@ -1,25 +1,46 @@
+use crate::systems;
 use crate::{
-    colors::{Color, BLACK, DARK_GREY, WHITE},
-    components::{Body, CombatStats, MagicStats, Player},
-    game::{Journal, RunState, State, Targeting},
+    colors::{Color, BLACK, DARK_GREY, WHITE, YELLOW},
+    components::{
+        describe, Animation, Body, CombatStats, Hazard, HazardKind, LastSeen, MagicStats, Monster,
+        Player, AWARENESS_THRESHOLD,
+    },
+    game::{
+        closest_enemy_in_fov, next_visible_enemy, visible_enemies, Action, Examined, Journal,
+        JournalSection, RunState, State, Targeting, GHOST_TURNS,
+    },
     inventory::InventoryAction,
     map::Map,
     map::Position,
+    map::{CONNECTS_EAST, CONNECTS_NORTH, CONNECTS_SOUTH, CONNECTS_WEST},
     palette,
-    renderer::RenderContext,
     renderer::Renderable,
+    renderer::{wrap_text, RenderContext},
     utils::field_of_view_no_walls,
 };
-use crate::{game::Interact, systems};
-use crate::{inventory::Inventory, resources::SharedInfo};
+use crate::{
+    inventory::Inventory,
+    resources::{FrameTime, Settings, SharedInfo, Turn, WizardMode},
+    score::{HighScores, HIGH_SCORES_FILE},
+    theme::Theme,
+};
 use graphics::character::CharacterCache;
 use graphics_buffer::BufferGlyphs;
 use legion::*;
 use piston_window::*;
 use std::{collections::VecDeque, time::Instant};
+use torchbearer::Map as FieldOfVisionMap;
 
 const GRID_SIZE: u32 = 16;
-const TORCH_RADIUS: i32 = 10;
+/// How many journal lines fit in the hud. Past that in a single turn, the game waits for the player to read them.
+const MAX_LOG: usize = 5;
+/// Below this light level, the edge of the sight gets dimmed, so that the light fades out instead of
+/// stopping on a hard circle.
+const VIGNETTE_LIGHT: f32 = 0.5;
+/// How dim the very edge of the sight gets.
+const VIGNETTE_MIN: f32 = 0.6;
+/// How much the color of the light sources shows on the tiles they light.
+const LIGHT_TINT: f32 = 0.3;
 const FONT_NAME: &str = "fonts/CourierPrime-Regular.ttf";
 
 pub struct Engine {
@@ -31,6 +52,25 @@ pub struct Engine {
     inventory: Option<Inventory>,
     mouse_position: [i32; 2],
     target_area: Option<Vec<(i32, i32)>>,
+    /// The tile aimed at while targeting, picked with the mouse or cycled through with Tab.
+    target: Option<(i32, i32)>,
+    /// Draw walls with box-drawing characters. Off by default, as not every font has them.
+    unicode_walls: bool,
+    /// Debug overlay showing how the noise spreads, toggled with F1.
+    show_noise: bool,
+    /// The final score and the high scores, once the game is over.
+    game_over: Option<Vec<String>>,
+    /// The monster looked at closely, picked by pressing X while hovering it.
+    examined: Option<Examined>,
+    /// The enemy in sight highlighted instead of the tile under the mouse, cycled through with Tab
+    /// and cleared with Escape.
+    highlighted: Option<Entity>,
+    /// Called once when the game stops, whether the player quit or closed the window.
+    on_exit: Option<Box<dyn FnMut(&mut State)>>,
+    /// Something changed since the last frame, the window needs a redraw.
+    dirty: bool,
+    /// Seconds since the last redraw, for the animations to catch up.
+    elapsed: f64,
 }
 
 impl Engine {
@@ -44,9 +84,32 @@ impl Engine {
             inventory: None,
             mouse_position: [0, 0],
             target_area: None,
+            target: None,
+            unicode_walls: false,
+            show_noise: false,
+            game_over: None,
+            examined: None,
+            highlighted: None,
+            on_exit: None,
+            dirty: true,
+            elapsed: 0.,
         }
     }
 
+    pub fn set_unicode_walls(&mut self, unicode_walls: bool) {
+        self.unicode_walls = unicode_walls;
+    }
+
+    /// How many characters fit on a line of the journal, longer entries are wrapped.
+    pub fn set_journal_width(&mut self, journal_width: usize) {
+        self.hud.set_journal_width(journal_width);
+    }
+
+    /// Let the game decide what to persist before the engine stops.
+    pub fn set_on_exit<F: FnMut(&mut State) + 'static>(&mut self, on_exit: F) {
+        self.on_exit = Some(Box::new(on_exit));
+    }
+
     pub fn run(&mut self, state: &mut State) {
         let mut window: PistonWindow = WindowSettings::new(
             &self.title,
@@ -62,7 +125,14 @@ impl Engine {
 
         println!("{:?}", window.size());
 
-        let mut events = Events::new(EventSettings::new().max_fps(30).ups(30));
+        let max_fps = state.resources.get::<Settings>().unwrap().max_fps;
+        // The buffers are only swapped after an actual redraw, the game is static most of the time.
+        let mut events = Events::new(
+            EventSettings::new()
+                .max_fps(max_fps)
+                .ups(max_fps)
+                .swap_buffers(false),
+        );
 
         let texture_settings = TextureSettings::new().filter(Filter::Nearest);
         let texture_context = window.create_texture_context();
@@ -70,26 +140,85 @@ impl Engine {
             .expect("Couldn't load the font.");
 
         let mut schedule = systems::game_schedule();
-
-        let mut previous_position = state.resources.get::<SharedInfo>().unwrap().player_position;
+        let mut animations = systems::animation_schedule();
 
         let mut pending_button = None;
 
         while let Some(event) = events.next(&mut window) {
+            if event.close_args().is_some() {
+                // The window is going away, there won't be another update.
+                self.exit(state);
+            }
+
+            if event.resize_args().is_some() || event.focus_args().is_some() {
+                self.dirty = true;
+            }
+
             if let Some(button) = event.press_args() {
                 pending_button = Some(button);
+                self.dirty = true;
 
                 if let Some(Button::Keyboard(Key::P)) = pending_button {
                     self.take_screenshot(state);
                 }
+
+                if let Some(Button::Keyboard(Key::F1)) = pending_button {
+                    self.show_noise = !self.show_noise;
+                    self.prepare_console(state, false);
+                }
+
+                if let Some(Button::Keyboard(Key::F3)) = pending_button {
+                    state.toggle_fov_algorithm();
+                    self.prepare_console(state, true);
+                    let journal = state.resources.get::<Journal>().unwrap();
+                    self.hud.update_journal(&journal);
+                }
+
+                if let Some(Button::Keyboard(Key::F4)) = pending_button {
+                    let theme = state.resources.get::<Theme>().unwrap().next();
+                    state.resources.insert(theme);
+                    self.prepare_console(state, false);
+                }
+
+                if let Some(Button::Keyboard(Key::F2)) = pending_button {
+                    if state.reveal_map() {
+                        self.prepare_console(state, false);
+                    }
+                }
+
+                if let Some(Button::Keyboard(Key::F5)) = pending_button {
+                    if state.toggle_god_mode() {
+                        let journal = state.resources.get::<Journal>().unwrap();
+                        self.hud.update_journal(&journal);
+                    }
+                }
             }
 
             event.mouse_cursor(|position| {
-                self.mouse_position[0] = (position[0] / GRID_SIZE as f64) as i32;
-                self.mouse_position[1] = (position[1] / GRID_SIZE as f64) as i32;
+                let mouse_position = [
+                    (position[0] / GRID_SIZE as f64) as i32,
+                    (position[1] / GRID_SIZE as f64) as i32,
+                ];
+                if mouse_position != self.mouse_position {
+                    // The tooltip or the targeting ring follow the mouse.
+                    self.mouse_position = mouse_position;
+                    self.target = Some((mouse_position[0], mouse_position[1] - 3));
+                    self.highlighted = None;
+                    self.dirty = true;
+                }
             });
 
-            if let Some(_args) = event.update_args() {
+            if let Some(args) = event.update_args() {
+                self.elapsed += args.dt;
+                if self.hud.has_more() {
+                    // The game waits until the player read everything.
+                    if pending_button.take().is_some() {
+                        let journal = state.resources.get::<Journal>().unwrap();
+                        self.hud.update_journal(&journal);
+                    }
+                    continue;
+                }
+
                 let previous_state = state.resources.get_or_insert(RunState::Init).clone();
 
                 let new_run_state = match previous_state {
@@ -103,24 +232,31 @@ impl Engine {
                     }
                     RunState::AiTurn => {
                         schedule.execute(&mut state.world, &mut state.resources);
-                        let alive = state
-                            .resources
-                            .get::<SharedInfo>()
-                            .map_or(false, |player_info| player_info.alive);
-                        if alive {
+                        state.after_ai_turn()
+                    }
+                    RunState::WaitForPlayerInput => {
+                        // Whatever gets logged from now on comes from the player's next turn.
+                        if let Some(mut journal) = state.resources.get_mut::<Journal>() {
+                            journal.start_turn();
+                        }
+                        if state.is_auto_piloting() && pending_button.take().is_some() {
+                            // Any key takes the control back.
+                            state.stop_auto_pilot();
                             RunState::WaitForPlayerInput
+                        } else if let Some(action) = state.next_auto_action() {
+                            state.play(action)
                         } else {
-                            RunState::Death
+                            self.consume_player_button(pending_button.take(), state)
                         }
                     }
-                    RunState::WaitForPlayerInput => {
-                        self.consume_player_button(pending_button.take(), state)
-                    }
                     RunState::Exit => break,
-                    RunState::Death => self.consume_death_button(pending_button.take()),
+                    RunState::Death | RunState::Victory => {
+                        self.consume_game_over_button(pending_button.take(), state, previous_state)
+                    }
                     RunState::ShowInventory => {
                         self.consume_inventory_button(pending_button.take(), state)
                     }
+                    RunState::ShowExamine => self.consume_examine_button(pending_button.take()),
                     RunState::ShowTargeting { item, range, burst } => self.consume_targeting(
                         state,
                         Targeting { item, range, burst },
@@ -130,11 +266,26 @@ impl Engine {
                         state.next_level();
                         RunState::Init
                     }
+                    RunState::PreviousLevel => {
+                        state.previous_level();
+                        RunState::Init
+                    }
                 };
 
                 state.resources.insert(new_run_state);
 
+                // Only the score reacts to the events in the window, but they shouldn't pile up.
+                state.drain_events();
+
+                if previous_state != new_run_state || schedule_ran(previous_state) {
+                    self.dirty = true;
+                }
+
                 if previous_state != new_run_state {
+                    if let RunState::Death | RunState::Victory = new_run_state {
+                        self.game_over = Some(record_high_score(state));
+                    }
+
                     if new_run_state == RunState::ShowInventory {
                         let mut inventory =
                             Inventory::new((5, 5), (self.width - 10, self.height - 10));
@@ -144,10 +295,13 @@ impl Engine {
                         self.inventory = None;
                     }
 
-                    let updated_position =
-                        state.resources.get::<SharedInfo>().unwrap().player_position;
+                    if let Some(map) = state.resources.get::<Map>() {
+                        self.hud
+                            .set_status(map.depth, map.base_light_radius, map.explored_ratio());
+                        self.hud.set_awareness(highest_awareness(state, &map));
+                    }
 
-                    self.prepare_console(state, previous_position != updated_position);
+                    self.prepare_console(state, false);
 
                     let (current, max) = current_player_life(state).unwrap_or((0, 0));
                     self.hud.health_bar.update(current, max);
@@ -166,9 +320,8 @@ impl Engine {
                     } = new_run_state
                     {
                         self.show_targeting_overlay_on_console(state, range);
+                        self.target = self.preselected_target(state);
                     }
-
-                    previous_position = updated_position;
                 }
 
                 // Mouse stuff.
@@ -191,29 +344,117 @@ impl Engine {
             };
 
             if let Some(_args) = event.render_args() {
-                window.draw_2d(&event, |context, graphics, device| {
-                    self.render(state, graphics, context, &mut glyphs);
+                if self.animate(state, &mut animations) {
+                    self.dirty = true;
+                }
+
+                if self.dirty {
+                    window.draw_2d(&event, |context, graphics, device| {
+                        self.render(state, graphics, context, &mut glyphs);
 
-                    glyphs.factory.encoder.flush(device);
-                });
+                        glyphs.factory.encoder.flush(device);
+                    });
+                    Window::swap_buffers(&mut window);
+                    self.dirty = false;
+                    self.elapsed = 0.;
+                }
             };
         }
+
+        // Quitting from the game, or anything that stopped the loop before a close event.
+        self.exit(state);
     }
 
-    pub fn prepare_console(&mut self, state: &mut State, compute_fov: bool) {
+    pub fn prepare_console(&mut self, state: &mut State, force_fov: bool) {
         self.console.clear();
-        self.prepare_map(state, compute_fov);
+        self.prepare_map(state, force_fov);
+        state.update_visibility();
 
         let map = state.resources.get::<Map>().unwrap();
+        let theme = state.resources.get::<Theme>().unwrap();
+        let see_all = sees_everything(state);
+
+        if !see_all {
+            // Out of sight, things are drawn as the player remembers them.
+            for (position, remembered) in map.remembered.iter() {
+                if !remembered.creature && !map.is_in_player_fov(position.x, position.y) {
+                    let (glyph, color) =
+                        theme.glyph(&remembered.name, remembered.char, remembered.color);
+                    self.console.set_foreground(
+                        position.x,
+                        position.y,
+                        glyph,
+                        color.darker().darker(),
+                    );
+                }
+            }
 
-        let mut query = <(&Body, &Position)>::query();
+            let turn = state.resources.get::<Turn>().map_or(0, |turn| turn.0);
+            let mut ghosts = <(&Body, &LastSeen)>::query()
+                .filter(component::<Monster>() & component::<CombatStats>());
+            for (body, last_seen) in ghosts.iter(&state.world) {
+                let LastSeen {
+                    position,
+                    turn: seen_turn,
+                } = *last_seen;
+                // Once the spot is back in sight, the player can tell the monster isn't there anymore.
+                if turn - seen_turn <= GHOST_TURNS && !map.is_in_player_fov(position.x, position.y)
+                {
+                    let (glyph, color) = theme.glyph(&body.name, body.char, body.color);
+                    self.console.set_foreground(
+                        position.x,
+                        position.y,
+                        glyph,
+                        color.darker().darker(),
+                    );
+                }
+            }
+        }
+
+        let mut query = <(&Body, &Position, TryRead<Animation>)>::query();
         let mut bodies: Vec<_> = query.iter(&state.world).collect();
-        bodies.sort_by(|&(body_0, _), &(body_1, _)| body_0.blocking.cmp(&body_1.blocking));
+        // Draw the highest priorities last, so they end up on top.
+        bodies.sort_by_key(|&(body, _, _)| body.render_priority);
+
+        for (body, coordinates, animation) in bodies {
+            if see_all || map.is_in_player_fov(coordinates.x, coordinates.y) {
+                let (glyph, color) = theme.glyph(&body.name, body.char, body.color);
+                let color = color.darken(vignette(&map, coordinates.x, coordinates.y));
+                match animation {
+                    Some(animation) => {
+                        let (x, y) = animation.current();
+                        self.console.set_floating(x, y, glyph, color);
+                    }
+                    None => self
+                        .console
+                        .set_foreground(coordinates.x, coordinates.y, glyph, color),
+                }
+            }
+        }
 
-        for (body, coordinates) in bodies {
-            if map.is_in_player_fov(coordinates.x, coordinates.y) {
-                self.console
-                    .set_foreground(coordinates.x, coordinates.y, body.char, body.color);
+        for (coordinates, hazard) in <(&Position, &Hazard)>::query().iter(&state.world) {
+            if see_all || map.is_in_player_fov(coordinates.x, coordinates.y) {
+                let color = match hazard.kind {
+                    HazardKind::Fire => palette::FIRE,
+                    HazardKind::Gas => palette::GAS,
+                };
+                self.console.tint(coordinates.x, coordinates.y, color);
+            }
+        }
+
+        if self.show_noise {
+            let loudest = map.noise_map.iter().cloned().max().unwrap_or(0);
+            if loudest > 0 {
+                for y in 0..map.height {
+                    for x in 0..map.width {
+                        let noise = map.noise_at(Position { x, y });
+                        if noise > 0 && map.is_walkable(x, y) {
+                            let ratio = noise as f32 / loudest as f32;
+                            let color = palette::NOISE_QUIET.lerp(palette::NOISE_LOUD, ratio);
+                            self.console.tint(x, y, color);
+                        }
+                    }
+                }
             }
         }
     }
@@ -233,7 +474,10 @@ impl Engine {
     pub fn show_targeting_ring_on_console(&mut self, state: &mut State, burst: i32) {
         let map = state.resources.get::<Map>().unwrap();
 
-        let (x, y) = (self.mouse_position[0], self.mouse_position[1] - 3);
+        let (x, y) = match self.target {
+            Some(target) => target,
+            None => return,
+        };
         if let Some(target_area) = &self.target_area {
             if target_area.contains(&(x, y)) {
                 if burst <= 0 {
@@ -244,14 +488,7 @@ impl Engine {
                 }
 
                 // Let's also display the tooltip, because why not.
-                self.hud.set_tooltip::<String>(None);
-                let target_coordinates = Position { x, y };
-                for (position, body) in <(&Position, &Body)>::query().iter(&state.world) {
-                    if target_coordinates == *position {
-                        self.hud.set_tooltip(Some(body.name.clone()));
-                        break;
-                    }
-                }
+                self.hud.set_tooltip(describe_at(state, Position { x, y }));
             }
         }
     }
@@ -259,113 +496,180 @@ impl Engine {
     pub fn prepare_tooltip(&mut self, state: &mut State) {
         self.hud.set_tooltip::<String>(None);
 
+        if self.prepare_highlight(state) {
+            return;
+        }
+
         let x = self.mouse_position[0];
         let y = self.mouse_position[1] - 3;
 
         let map = state.resources.get::<Map>().unwrap();
-        if !map.is_in_bounds(x, y) || !map.is_in_player_fov(x, y) {
-            // No tooltip for stuff we can't see!
+        if !map.is_in_bounds(x, y) {
             return;
         }
 
-        self.console.select(x, y);
-        let target_coordinates = Position { x, y };
-        for (position, body) in <(&Position, &Body)>::query().iter(&state.world) {
-            if target_coordinates == *position {
-                self.hud.set_tooltip(Some(body.name.clone()));
-                break;
+        if sees_everything(state) || map.is_in_player_fov(x, y) {
+            self.console.select(x, y);
+            self.hud.set_tooltip(describe_at(state, Position { x, y }));
+        } else if map.is_explored(x, y) {
+            // No live data for stuff we can't see, only what the player remembers.
+            self.console.select(x, y);
+            self.hud.set_tooltip(
+                map.remembered
+                    .get(&Position { x, y })
+                    .map(|remembered| format!("{} (remembered)", remembered.description)),
+            );
+        }
+    }
+
+    /// Select the highlighted enemy, and tell about it in the status line. Returns false when
+    /// there's nothing highlighted, or when the enemy went out of sight.
+    fn prepare_highlight(&mut self, state: &State) -> bool {
+        let highlighted = match self.highlighted {
+            Some(highlighted) => highlighted,
+            None => return false,
+        };
+        let map = state.resources.get::<Map>().unwrap();
+        let player_position = state.resources.get::<SharedInfo>().unwrap().player_position;
+        let enemies = visible_enemies(&state.world, &map, player_position);
+        let index = match enemies.iter().position(|&enemy| enemy == highlighted) {
+            Some(index) => index,
+            None => {
+                self.highlighted = None;
+                return false;
             }
+        };
+
+        if let Ok(position) = <&Position>::query().get(&state.world, highlighted) {
+            self.console.select(position.x, position.y);
         }
+        self.hud.set_tooltip(Some(format!(
+            "{}/{}: {}",
+            index + 1,
+            enemies.len(),
+            describe(&state.world, highlighted)
+        )));
+        true
     }
 
-    fn prepare_map(&mut self, state: &mut State, fov_recompute: bool) {
-        let mut map = state.resources.get_mut::<Map>().unwrap();
+    fn prepare_map(&mut self, state: &mut State, force_fov: bool) {
+        state.update_player_fov(force_fov);
+        let map = state.resources.get::<Map>().unwrap();
+        let theme = state.resources.get::<Theme>().unwrap();
 
         if self.console.width() != map.width || self.console.height() != map.height {
             self.console = Console::new(0, 3, map.width, map.height);
         }
 
-        if fov_recompute {
-            let mut query = <&Position>::query().filter(component::<Player>());
-            for coordinates in query.iter(&state.world) {
-                map.calculate_player_fov(coordinates.x, coordinates.y, TORCH_RADIUS);
-            }
-        }
-
         let map_width = map.width;
         let map_height = map.height;
         for y in 0..map_height {
             for x in 0..map_width {
                 let visible = map.is_in_player_fov(x, y);
-                let wall = map.tiles[x as usize + y as usize * map_width as usize].block_sight;
-                let color = match (visible, wall) {
-                    (false, true) => palette::DARK_WALL,
-                    (false, false) => palette::DARK_GROUND,
-                    (true, true) => palette::LIGHT_WALL,
-                    (true, false) => palette::LIGHT_GROUND,
+                let wall = map.tiles.get(x, y).map_or(true, |tile| tile.blocks_sight());
+                let (dark, light) = if wall {
+                    (theme.dark_wall, theme.light_wall)
+                } else {
+                    (theme.dark_ground, theme.light_ground)
+                };
+                let color = if visible {
+                    // Fade towards the dark color as the light falls off.
+                    let color = dark.lerp(light, map.light_at(x, y));
+                    let color = match map.ambient_light_at(x, y) {
+                        Some((tint, strength)) => color.lerp(tint, strength * LIGHT_TINT),
+                        None => color,
+                    };
+                    // The edge blends into the explored tiles around.
+                    dark.lerp(color, vignette(&map, x, y))
+                } else {
+                    dark
                 };
 
-                let explored =
-                    &mut map.explored_tiles[x as usize + y as usize * map_width as usize];
-                if visible {
-                    *explored = true;
-                }
+                let explored = map.explored_tiles[x as usize + y as usize * map_width as usize];
 
-                if *explored {
+                if explored {
                     self.console.set_background(x, y, color);
+
+                    // Bodies are drawn later, on top of the marks on the floor.
+                    if let Some(decal) = map.decal_at(Position { x, y }) {
+                        let decal_color = if visible {
+                            decal.color.darken(vignette(&map, x, y))
+                        } else {
+                            decal.color.darker().darker()
+                        };
+                        self.console.set_foreground(x, y, decal.char, decal_color);
+                    }
+
+                    if wall && self.unicode_walls {
+                        let glyph = wall_glyph(map.wall_connections(x, y));
+                        self.console.set_foreground(x, y, glyph, color.darker());
+                    }
                 }
             }
         }
     }
 
-    fn consume_death_button(&self, button: Option<Button>) -> RunState {
-        if let Some(button) = button {
-            match button {
-                Button::Keyboard(key) => match key {
-                    Key::Escape => RunState::Exit,
-                    _ => RunState::Death,
-                },
-                _ => RunState::Death,
+    /// Once dead or victorious, escape leaves the game and enter starts a new one.
+    fn consume_game_over_button(
+        &mut self,
+        button: Option<Button>,
+        state: &mut State,
+        run_state: RunState,
+    ) -> RunState {
+        match button {
+            Some(Button::Keyboard(Key::Escape)) => RunState::Exit,
+            Some(Button::Keyboard(Key::Return)) => {
+                state.restart(rand::random());
+                self.game_over = None;
+                RunState::Init
             }
-        } else {
-            RunState::Death
+            _ => run_state,
         }
     }
 
-    fn consume_player_button(&self, button: Option<Button>, state: &mut State) -> RunState {
+    fn consume_player_button(&mut self, button: Option<Button>, state: &mut State) -> RunState {
         if let Some(button) = button {
             match button {
                 Button::Keyboard(key) => match key {
-                    Key::W | Key::Up => {
-                        state.move_player(0, -1);
-                        RunState::PlayerTurn
-                    }
-                    Key::A | Key::Left => {
-                        state.move_player(-1, 0);
-                        RunState::PlayerTurn
-                    }
-                    Key::S | Key::Down => {
-                        state.move_player(0, 1);
-                        RunState::PlayerTurn
-                    }
-                    Key::D | Key::Right => {
-                        state.move_player(1, 0);
-                        RunState::PlayerTurn
-                    }
-                    Key::G => {
-                        if state.grab_item() {
-                            RunState::PlayerTurn
+                    Key::W | Key::Up => state.play(Action::Move { dx: 0, dy: -1 }),
+                    Key::A | Key::Left => state.play(Action::Move { dx: -1, dy: 0 }),
+                    Key::S | Key::Down => state.play(Action::Move { dx: 0, dy: 1 }),
+                    Key::D | Key::Right => state.play(Action::Move { dx: 1, dy: 0 }),
+                    Key::G => state.play(Action::Grab),
+                    Key::I => RunState::ShowInventory,
+                    Key::X => {
+                        // Look closer at whatever the tooltip describes.
+                        let position = (self.mouse_position[0], self.mouse_position[1] - 3).into();
+                        self.examined = state.examine(position);
+                        if self.examined.is_some() {
+                            RunState::ShowExamine
                         } else {
                             RunState::WaitForPlayerInput
                         }
                     }
-                    Key::I => RunState::ShowInventory,
-                    Key::E => match state.interact() {
-                        Interact::Canceled => RunState::WaitForPlayerInput,
-                        Interact::WentDownstairs => RunState::NextLevel,
-                    },
+                    Key::E => state.play(Action::Interact),
+                    Key::Tab => {
+                        let map = state.resources.get::<Map>().unwrap();
+                        let player_position =
+                            state.resources.get::<SharedInfo>().unwrap().player_position;
+                        self.highlighted = next_visible_enemy(
+                            &state.world,
+                            &map,
+                            player_position,
+                            self.highlighted,
+                        );
+                        RunState::WaitForPlayerInput
+                    }
+                    Key::Escape if self.highlighted.is_some() => {
+                        self.highlighted = None;
+                        RunState::WaitForPlayerInput
+                    }
                     Key::Escape => RunState::Exit,
-                    Key::Space => RunState::PlayerTurn,
+                    Key::Space => state.play(Action::Wait),
+                    Key::R => {
+                        state.rest();
+                        RunState::WaitForPlayerInput
+                    }
                     _ => RunState::WaitForPlayerInput,
                 },
                 _ => RunState::WaitForPlayerInput,
@@ -375,14 +679,27 @@ impl Engine {
         }
     }
 
+    fn consume_examine_button(&mut self, button: Option<Button>) -> RunState {
+        match button {
+            Some(_) => {
+                self.examined = None;
+                RunState::WaitForPlayerInput
+            }
+            None => RunState::ShowExamine,
+        }
+    }
+
     fn consume_inventory_button(&mut self, button: Option<Button>, state: &mut State) -> RunState {
         if let Some(Button::Keyboard(key)) = button {
             if let Some(inventory) = &mut self.inventory {
                 match inventory.on_keyboard(&key) {
                     InventoryAction::Selecting => RunState::ShowInventory,
                     InventoryAction::Pick { entity } => state.use_item(entity),
-                    InventoryAction::Close => RunState::PlayerTurn,
-                    InventoryAction::Drop { entity } => state.drop_item(entity),
+                    InventoryAction::Close => RunState::WaitForPlayerInput,
+                    InventoryAction::Drop {
+                        entity,
+                        whole_stack,
+                    } => state.drop_item(entity, whole_stack),
                 }
             } else {
                 RunState::ShowInventory
@@ -411,6 +728,35 @@ impl Engine {
                     (self.mouse_position[0], self.mouse_position[1] - 3),
                 )
             }
+            Some(Button::Keyboard(Key::Return)) | Some(Button::Keyboard(Key::NumPadEnter)) => {
+                let current_state = RunState::ShowTargeting {
+                    item: targeting.item,
+                    range: targeting.range,
+                    burst: targeting.burst,
+                };
+                match self.target {
+                    Some(target) if self.is_in_target_area(target) => {
+                        state.use_range_item_with_targeting(current_state, targeting.item, target)
+                    }
+                    _ => current_state,
+                }
+            }
+            Some(Button::Keyboard(Key::Tab)) => {
+                let enemies = self.targetable_enemies(state);
+                // The next one after the current target, or the closest if there is none.
+                let next = self
+                    .target
+                    .and_then(|target| enemies.iter().position(|&enemy| enemy == target))
+                    .map_or(0, |index| index + 1);
+                if !enemies.is_empty() {
+                    self.target = Some(enemies[next % enemies.len()]);
+                }
+                RunState::ShowTargeting {
+                    item: targeting.item,
+                    range: targeting.range,
+                    burst: targeting.burst,
+                }
+            }
             Some(Button::Keyboard(key)) if key == Key::Escape => RunState::WaitForPlayerInput,
             _ => RunState::ShowTargeting {
                 item: targeting.item,
@@ -420,6 +766,44 @@ impl Engine {
         }
     }
 
+    fn is_in_target_area(&self, target: (i32, i32)) -> bool {
+        self.target_area
+            .as_ref()
+            .map_or(false, |target_area| target_area.contains(&target))
+    }
+
+    /// Where the enemies in range stand, closest first.
+    fn targetable_enemies(&self, state: &State) -> Vec<(i32, i32)> {
+        let map = state.resources.get::<Map>().unwrap();
+        let player_position = state.resources.get::<SharedInfo>().unwrap().player_position;
+
+        visible_enemies(&state.world, &map, player_position)
+            .into_iter()
+            .filter_map(|entity| {
+                <&Position>::query()
+                    .get(&state.world, entity)
+                    .map(|position| (position.x, position.y))
+                    .ok()
+            })
+            .filter(|&position| self.is_in_target_area(position))
+            .collect()
+    }
+
+    /// Aim at the closest enemy right away, so that a single key fires. When it's out of range,
+    /// the closest one in range will do.
+    fn preselected_target(&self, state: &State) -> Option<(i32, i32)> {
+        let closest = {
+            let map = state.resources.get::<Map>().unwrap();
+            let player_position = state.resources.get::<SharedInfo>().unwrap().player_position;
+            closest_enemy_in_fov(&state.world, &map, player_position)
+        };
+        closest
+            .and_then(|enemy| <&Position>::query().get(&state.world, enemy).ok())
+            .map(|position| (position.x, position.y))
+            .filter(|&target| self.is_in_target_area(target))
+            .or_else(|| self.targetable_enemies(state).into_iter().next())
+    }
+
     fn take_screenshot(&self, state: &mut State) {
         let now = Instant::now();
         let mut glyph_cache = BufferGlyphs::new(
@@ -463,6 +847,14 @@ impl Engine {
                 self.render_map_and_hud(&mut render_context);
                 self.render_inventory(&mut render_context);
             }
+            RunState::Death | RunState::Victory => {
+                self.render_map_and_hud(&mut render_context);
+                self.render_game_over(&mut render_context);
+            }
+            RunState::ShowExamine => {
+                self.render_map_and_hud(&mut render_context);
+                self.render_examine(&mut render_context);
+            }
             _ => {
                 self.render_map_and_hud(&mut render_context);
             }
@@ -489,6 +881,215 @@ impl Engine {
             inventory.render(render_context);
         }
     }
+
+    fn render_game_over<C, G>(&self, render_context: &mut RenderContext<C, G>)
+    where
+        C: CharacterCache,
+        G: Graphics<Texture = <C as CharacterCache>::Texture>,
+    {
+        let lines = match &self.game_over {
+            Some(lines) => lines,
+            None => return,
+        };
+
+        let origin = (10, 5);
+        let size = (self.width - 20, lines.len() as i32 + 4);
+        crate::renderer::draw_window(
+            origin,
+            size,
+            "Game over",
+            render_context.grid_size,
+            render_context.character_cache,
+            render_context.context,
+            render_context.graphics,
+        );
+        for (index, line) in lines.iter().enumerate() {
+            crate::renderer::draw_text(
+                origin.0 + 1,
+                origin.1 + 2 + index as i32,
+                10,
+                WHITE.into(),
+                render_context.grid_size,
+                line,
+                render_context.character_cache,
+                render_context.context,
+                render_context.graphics,
+            )
+            .ok();
+        }
+    }
+
+    /// The examined monster, in a window over the map.
+    fn render_examine<C, G>(&self, render_context: &mut RenderContext<C, G>)
+    where
+        C: CharacterCache,
+        G: Graphics<Texture = <C as CharacterCache>::Texture>,
+    {
+        let examined = match &self.examined {
+            Some(examined) => examined,
+            None => return,
+        };
+
+        let origin = (10, 5);
+        let width = self.width - 20;
+        let mut lines = match &examined.stats {
+            Some(stats) => vec![
+                format!("HP: {}/{}", stats.hp, stats.max_hp),
+                format!("Attack: {}", stats.attack),
+                format!("Defense: {}", stats.defense),
+            ],
+            None => vec!["Out of sight, last seen here.".to_string()],
+        };
+        lines.push(String::new());
+        lines.extend(wrap_text(examined.description, (width - 2).max(1) as usize));
+        lines.push(String::new());
+        lines.push("Press any key to close.".to_string());
+
+        crate::renderer::draw_window(
+            origin,
+            (width, lines.len() as i32 + 4),
+            &examined.name,
+            render_context.grid_size,
+            render_context.character_cache,
+            render_context.context,
+            render_context.graphics,
+        );
+        for (index, line) in lines.iter().enumerate() {
+            crate::renderer::draw_text(
+                origin.0 + 1,
+                origin.1 + 2 + index as i32,
+                10,
+                WHITE.into(),
+                render_context.grid_size,
+                line,
+                render_context.character_cache,
+                render_context.context,
+                render_context.graphics,
+            )
+            .ok();
+        }
+    }
+
+    /// Move the animations forward by the time elapsed since the last frame.
+    /// Returns whether any was running, and so needs to be drawn.
+    fn animate(&mut self, state: &mut State, animations: &mut Schedule) -> bool {
+        if <&Animation>::query().iter(&state.world).next().is_none() {
+            return false;
+        }
+
+        state.resources.insert(FrameTime(self.elapsed as f32));
+        animations.execute(&mut state.world, &mut state.resources);
+        self.prepare_console(state, false);
+        true
+    }
+
+    fn exit(&mut self, state: &mut State) {
+        if let Some(mut on_exit) = self.on_exit.take() {
+            on_exit(state);
+        }
+    }
+}
+
+/// Whether the game moved forward during this run state, so what's on screen might be outdated.
+fn schedule_ran(run_state: RunState) -> bool {
+    matches!(
+        run_state,
+        RunState::Init | RunState::PlayerTurn | RunState::AiTurn
+    )
+}
+
+/// How many entries, taken in order, fit in the journal lines of the hud. At least one, if any.
+fn fitting_entries<'a, I: Iterator<Item = &'a Vec<String>>>(entries: I) -> usize {
+    let mut lines = 0;
+    let mut count = 0;
+    for entry in entries {
+        lines += entry.len();
+        if lines > MAX_LOG && count > 0 {
+            break;
+        }
+        count += 1;
+    }
+    count
+}
+
+/// Add the game to the high score table on disk, and describe how it went.
+fn record_high_score(state: &State) -> Vec<String> {
+    let high_score = state.high_score();
+    let mut high_scores = HighScores::load(HIGH_SCORES_FILE);
+    let rank = high_scores.add(high_score.clone());
+    if let Err(error) = high_scores.save(HIGH_SCORES_FILE) {
+        println!("Couldn't save the high scores: {}", error);
+    }
+
+    let mut lines = vec![];
+    if let Some(journal) = state.resources.get::<Journal>() {
+        // How the story ended, kept apart from the noise of the last fight.
+        if let Some(ending) = journal.section(JournalSection::Story).next() {
+            lines.push(ending.to_string());
+        }
+    }
+    lines.push(format!("You scored {} points.", high_score.points));
+    lines.push(String::new());
+    for (index, entry) in high_scores.entries().iter().enumerate() {
+        lines.push(format!(
+            "{}{:>2}. {:>6} points, depth {}, {} turns{}",
+            if rank == Some(index) { "> " } else { "  " },
+            index + 1,
+            entry.points,
+            entry.depth,
+            entry.turns,
+            if entry.won { ", won" } else { "" }
+        ));
+    }
+    lines.push(String::new());
+    lines.push("Press enter to play again, escape to quit.".to_string());
+    lines
+}
+
+/// What the tooltip says about a tile: the description of the body drawn on top, if any.
+fn describe_at(state: &State, target: Position) -> Option<String> {
+    <(Entity, &Position, &Body)>::query()
+        .iter(&state.world)
+        .filter(|(_, position, _)| **position == target)
+        .max_by_key(|(_, _, body)| body.render_priority)
+        .map(|(entity, _, _)| describe(&state.world, *entity))
+}
+
+/// How much what's on a tile is dimmed, fading out towards the edge of the player's sight.
+/// Tiles out of sight are left alone, they have their own way to look remembered.
+fn vignette(map: &Map, x: i32, y: i32) -> f32 {
+    if !map.is_in_player_fov(x, y) {
+        return 1.0;
+    }
+    let light = map.light_at(x, y);
+    VIGNETTE_MIN + (1.0 - VIGNETTE_MIN) * (light / VIGNETTE_LIGHT).min(1.0)
+}
+
+fn sees_everything(state: &State) -> bool {
+    state
+        .resources
+        .get::<WizardMode>()
+        .map_or(false, |wizard| wizard.see_all)
+}
+
+fn wall_glyph(connections: u8) -> char {
+    const NORTH_SOUTH: u8 = CONNECTS_NORTH | CONNECTS_SOUTH;
+    const EAST_WEST: u8 = CONNECTS_EAST | CONNECTS_WEST;
+
+    match connections {
+        0 => '■',
+        CONNECTS_NORTH | CONNECTS_SOUTH | NORTH_SOUTH => '│',
+        CONNECTS_EAST | CONNECTS_WEST | EAST_WEST => '─',
+        c if c == CONNECTS_SOUTH | CONNECTS_EAST => '┌',
+        c if c == CONNECTS_SOUTH | CONNECTS_WEST => '┐',
+        c if c == CONNECTS_NORTH | CONNECTS_EAST => '└',
+        c if c == CONNECTS_NORTH | CONNECTS_WEST => '┘',
+        c if c == NORTH_SOUTH | CONNECTS_EAST => '├',
+        c if c == NORTH_SOUTH | CONNECTS_WEST => '┤',
+        c if c == EAST_WEST | CONNECTS_SOUTH => '┬',
+        c if c == EAST_WEST | CONNECTS_NORTH => '┴',
+        _ => '┼',
+    }
 }
 
 fn current_player_life(state: &State) -> Option<(i32, i32)> {
@@ -498,6 +1099,15 @@ fn current_player_life(state: &State) -> Option<(i32, i32)> {
     })
 }
 
+/// How close the monsters in sight are to noticing the player, `AWARENESS_THRESHOLD` meaning they did.
+fn highest_awareness(state: &State, map: &Map) -> f32 {
+    <(&Monster, &Position)>::query()
+        .iter(&state.world)
+        .filter(|(_, position)| map.is_in_player_fov(position.x, position.y))
+        .map(|(monster, _)| monster.awareness)
+        .fold(0.0, f32::max)
+}
+
 fn current_player_mana(state: &State) -> Option<(i32, i32)> {
     <&MagicStats>::query()
         .get(&state.world, state.player_entity)
@@ -512,6 +1122,8 @@ struct Console {
     height: i32,
     background: Vec<Option<Color>>,
     foreground: Vec<Option<(char, Color)>>,
+    /// Glyphs in between cells, drawn over the others.
+    floating: Vec<(f32, f32, char, Color)>,
     overlay: Vec<(i32, i32, Color)>,
     selection: Vec<(i32, i32, Color)>,
 }
@@ -524,6 +1136,7 @@ impl Console {
             height,
             background: vec![None; (width * height) as usize],
             foreground: vec![None; (width * height) as usize],
+            floating: vec![],
             overlay: vec![],
             selection: vec![],
         }
@@ -536,6 +1149,7 @@ impl Console {
         for foreground in self.foreground.iter_mut() {
             *foreground = None;
         }
+        self.floating.clear();
         self.overlay.clear();
         self.selection.clear();
     }
@@ -556,6 +1170,10 @@ impl Console {
         self.foreground[(x + y * self.width) as usize] = Some((glyph, color.into()));
     }
 
+    fn set_floating(&mut self, x: f32, y: f32, glyph: char, color: Color) {
+        self.floating.push((x, y, glyph, color));
+    }
+
     fn select(&mut self, x: i32, y: i32) {
         self.selection.clear();
         if x >= 0 && x < self.width && y >= 0 && y < self.height {
@@ -570,8 +1188,11 @@ impl Console {
         }
     }
 
+    fn tint(&mut self, x: i32, y: i32, color: Color) {
+        self.overlay.push((x, y, color));
+    }
+
     fn overlay(&mut self, overlay: &[(i32, i32)]) {
-        self.overlay.clear();
         for position in overlay.iter().map(|&(x, y)| (x, y, palette::OVERLAY)) {
             self.overlay.push(position);
         }
@@ -624,6 +1245,20 @@ impl Renderable for Console {
             }
         }
 
+        for &(x, y, glyph, color) in self.floating.iter() {
+            crate::renderer::draw_char_at(
+                x as f64 + dx as f64,
+                y as f64 + dy as f64,
+                color.into(),
+                GRID_SIZE,
+                glyph,
+                render_context.character_cache,
+                render_context.context,
+                render_context.graphics,
+            )
+            .ok();
+        }
+
         for (x, y, color) in self.overlay.iter() {
             crate::renderer::draw_square(
                 x + dx,
@@ -719,7 +1354,20 @@ struct Hud {
     health_bar: StatBar,
     mana_bar: StatBar,
     tooltip: Option<String>,
-    journal_entries: VecDeque<String>,
+    /// Depth and light of the current floor.
+    status: String,
+    /// How much of the current floor was explored.
+    explored: String,
+    /// How close the monsters in sight are to noticing the player.
+    awareness: (String, Color),
+    /// The journal lines shown, already wrapped, with the color of the entry they belong to.
+    journal_lines: VecDeque<(String, Color)>,
+    /// How many characters fit on a journal line.
+    journal_width: usize,
+    /// How many journal entries the player already had the chance to read.
+    read: usize,
+    /// There are more entries to read, shown with a "--more--" prompt.
+    more: bool,
 }
 
 impl Hud {
@@ -740,19 +1388,92 @@ impl Hud {
                 max: 0,
             },
             tooltip: None,
-            journal_entries: VecDeque::new(),
+            status: String::new(),
+            explored: String::new(),
+            awareness: (String::new(), WHITE),
+            journal_lines: VecDeque::new(),
+            // What's left of the bottom panel once the status is drawn.
+            journal_width: (width - 22).max(1) as usize,
+            read: 0,
+            more: false,
         }
     }
 
+    pub fn set_status(&mut self, depth: i32, light_radius: i32, explored_ratio: f32) {
+        self.status = format!("Depth {}  Light {}", depth, light_radius);
+        self.explored = format!("Explored: {}%", (explored_ratio * 100.0).floor() as i32);
+    }
+
+    pub fn set_awareness(&mut self, awareness: f32) {
+        self.awareness = if awareness >= AWARENESS_THRESHOLD {
+            (String::from("Spotted!"), palette::SPOTTED)
+        } else if awareness > 0.0 {
+            (
+                format!("Noticed: {}%", (awareness * 100.0).floor() as i32),
+                YELLOW,
+            )
+        } else {
+            (String::from("Hidden"), WHITE)
+        };
+    }
+
     pub fn set_tooltip<S: Into<String>>(&mut self, tooltip: Option<S>) {
         self.tooltip = tooltip.map(|tooltip| tooltip.into());
     }
 
+    /// Show the latest entries of the journal, or the next page of them if there are more
+    /// unread entries than lines in the hud.
     pub fn update_journal(&mut self, journal: &Journal) {
-        self.journal_entries.clear();
-        for entry in journal.get_entries().iter().take(5) {
-            self.journal_entries.push_front(entry.clone());
+        self.journal_lines.clear();
+
+        // Entries are stored newest first.
+        let entries = journal.get_entries();
+        let wrapped: Vec<Vec<String>> = entries
+            .iter()
+            .map(|entry| wrap_text(entry, self.journal_width))
+            .collect();
+        let unread = journal
+            .logged_count()
+            .saturating_sub(self.read)
+            .min(entries.len());
+
+        let newest_fitting = fitting_entries(wrapped.iter());
+        let (skipped, shown) = if unread <= newest_fitting {
+            (0, newest_fitting)
+        } else {
+            // Page through the unread entries, oldest first.
+            let shown = fitting_entries(wrapped[..unread].iter().rev());
+            (unread - shown, shown)
+        };
+
+        for (index, lines) in wrapped.into_iter().enumerate().skip(skipped).take(shown) {
+            let color = match journal.entry_section(index) {
+                Some(JournalSection::Companion) => palette::COMPANION,
+                _ => WHITE,
+            };
+            // Entries of the previous turns fade a bit.
+            let color = if index < journal.logged_this_turn() {
+                color
+            } else {
+                color.darker()
+            };
+            for line in lines.into_iter().rev() {
+                self.journal_lines.push_front((line, color));
+            }
         }
+        // Only happens with a single entry too long for the whole hud.
+        self.journal_lines.truncate(MAX_LOG);
+
+        self.more = skipped > 0;
+        self.read = journal.logged_count() - skipped;
+    }
+
+    pub fn set_journal_width(&mut self, journal_width: usize) {
+        self.journal_width = journal_width.max(1);
+    }
+
+    pub fn has_more(&self) -> bool {
+        self.more
     }
 }
 
@@ -817,17 +1538,55 @@ impl Renderable for Hud {
             .ok();
         }
 
-        let max_log = 5;
-        let mut y = self.height as i32 - max_log - 1;
+        crate::renderer::draw_text(
+            self.width - 20,
+            self.height - 6,
+            10,
+            WHITE.into(),
+            GRID_SIZE,
+            self.status.as_str(),
+            render_context.character_cache,
+            render_context.context,
+            render_context.graphics,
+        )
+        .ok();
+
+        crate::renderer::draw_text(
+            self.width - 20,
+            self.height - 5,
+            10,
+            WHITE.into(),
+            GRID_SIZE,
+            self.explored.as_str(),
+            render_context.character_cache,
+            render_context.context,
+            render_context.graphics,
+        )
+        .ok();
+
+        crate::renderer::draw_text(
+            self.width - 20,
+            self.height - 4,
+            10,
+            self.awareness.1.into(),
+            GRID_SIZE,
+            self.awareness.0.as_str(),
+            render_context.character_cache,
+            render_context.context,
+            render_context.graphics,
+        )
+        .ok();
+
+        let mut y = self.height as i32 - MAX_LOG as i32 - 1;
 
-        for log in self.journal_entries.iter() {
+        for (line, color) in self.journal_lines.iter() {
             crate::renderer::draw_text(
                 1,
                 y,
-                50,
-                WHITE.into(),
+                self.journal_width as u32,
+                (*color).into(),
                 GRID_SIZE,
-                log.as_str(),
+                line.as_str(),
                 render_context.character_cache,
                 render_context.context,
                 render_context.graphics,
@@ -836,5 +1595,20 @@ impl Renderable for Hud {
 
             y += 1;
         }
+
+        if self.more {
+            crate::renderer::draw_text(
+                1,
+                y,
+                10,
+                YELLOW.into(),
+                GRID_SIZE,
+                "--more--",
+                render_context.character_cache,
+                render_context.context,
+                render_context.graphics,
+            )
+            .ok();
+        }
     }
 }
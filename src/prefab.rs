@@ -0,0 +1,196 @@
+use legion::World;
+
+use crate::spawner::{self, MonsterType};
+
+/// Hand designed set-pieces, stamped in place of a regular room.
+/// The center of a prefab must be walkable, as that's where the tunnels will connect.
+const TEMPLATES: [&str; 3] = [
+    // Shrine.
+    "\
+.....
+.#!#.
+..o..
+.#?#.
+.....",
+    // Troll den.
+    "\
+#######
+#.....#
+#.#.#.#
+#..T..#
+#.#.#.#
+#.!.?.#
+###.###",
+    // Barracks.
+    "\
+#########
+#o.o.o.o#
+#.......#
+....!....
+#.......#
+#o.o.o.o#
+#########",
+];
+
+/// Whole levels drawn by hand, by name. They use the same characters as the prefabs,
+/// plus `@` where the player starts and `>` for the stairs. See `Map::from_ascii`.
+const LEVELS: [(&str, &str); 1] = [(
+    "arena",
+    "\
+####################
+#@.......#.........#
+#........'....o....#
+#........#.........#
+####'#####....T....#
+#..~~~...#.........#
+#..~~~...'....!...>#
+#........#.........#
+####################",
+)];
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum PrefabCell {
+    Wall,
+    Floor,
+    Orc,
+    Troll,
+    Potion,
+    Scroll,
+    /// Where the player starts, only in whole levels.
+    Player,
+    Stairs,
+}
+
+impl PrefabCell {
+    pub fn from_char(character: char) -> Option<Self> {
+        match character {
+            '#' => Some(PrefabCell::Wall),
+            '.' => Some(PrefabCell::Floor),
+            'o' => Some(PrefabCell::Orc),
+            'T' => Some(PrefabCell::Troll),
+            '!' => Some(PrefabCell::Potion),
+            '?' => Some(PrefabCell::Scroll),
+            '@' => Some(PrefabCell::Player),
+            '>' => Some(PrefabCell::Stairs),
+            _ => None,
+        }
+    }
+
+    pub fn is_walkable(&self) -> bool {
+        *self != PrefabCell::Wall
+    }
+
+    /// Spawn whatever the cell is marked with. The player already exists, it's up to the caller to move them.
+    pub fn spawn(&self, world: &mut World, x: i32, y: i32) {
+        match self {
+            PrefabCell::Wall | PrefabCell::Floor | PrefabCell::Player => {}
+            PrefabCell::Stairs => spawner::stairs(world, x, y),
+            PrefabCell::Orc => spawner::monster(world, MonsterType::Orc, x, y),
+            PrefabCell::Troll => spawner::monster(world, MonsterType::Troll, x, y),
+            PrefabCell::Potion => {
+                spawner::potion(world, x, y);
+            }
+            PrefabCell::Scroll => {
+                spawner::scroll_of_fireball(world, x, y);
+            }
+        }
+    }
+}
+
+pub struct Prefab {
+    pub width: i32,
+    pub height: i32,
+    cells: Vec<PrefabCell>,
+}
+
+impl Prefab {
+    /// Parse a prefab from a template, one line per row.
+    pub fn parse(template: &str) -> Self {
+        let rows: Vec<&str> = template.lines().collect();
+        let height = rows.len() as i32;
+        let width = rows.first().map_or(0, |row| row.chars().count()) as i32;
+
+        let mut cells = Vec::with_capacity((width * height) as usize);
+        for row in rows {
+            if row.chars().count() as i32 != width {
+                panic!(
+                    "All the rows of a prefab should be {} wide: {:?}",
+                    width, row
+                );
+            }
+            for character in row.chars() {
+                match PrefabCell::from_char(character) {
+                    Some(cell) => cells.push(cell),
+                    None => panic!("Unknown prefab character {:?}", character),
+                }
+            }
+        }
+
+        Prefab {
+            width,
+            height,
+            cells,
+        }
+    }
+
+    pub fn cell(&self, x: i32, y: i32) -> PrefabCell {
+        self.cells[(x + y * self.width) as usize]
+    }
+
+    pub fn center(&self) -> (i32, i32) {
+        (self.width / 2, self.height / 2)
+    }
+}
+
+pub fn prefabs() -> Vec<Prefab> {
+    TEMPLATES
+        .iter()
+        .map(|template| Prefab::parse(template))
+        .collect()
+}
+
+/// The hand drawn level with this name, if there is one.
+pub fn level(name: &str) -> Option<&'static str> {
+    LEVELS
+        .iter()
+        .find(|(level_name, _)| *level_name == name)
+        .map(|(_, level)| *level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prefabs;
+
+    #[test]
+    fn prefabs_are_connected_to_their_center() {
+        for prefab in prefabs() {
+            let (center_x, center_y) = prefab.center();
+            assert!(prefab.cell(center_x, center_y).is_walkable());
+
+            let mut reached = vec![false; (prefab.width * prefab.height) as usize];
+            let mut frontier = vec![(center_x, center_y)];
+            reached[(center_x + center_y * prefab.width) as usize] = true;
+            while let Some((x, y)) = frontier.pop() {
+                for &(dx, dy) in &[(0, -1), (1, 0), (0, 1), (-1, 0)] {
+                    let (x, y) = (x + dx, y + dy);
+                    if x < 0 || y < 0 || x >= prefab.width || y >= prefab.height {
+                        continue;
+                    }
+                    let index = (x + y * prefab.width) as usize;
+                    if !reached[index] && prefab.cell(x, y).is_walkable() {
+                        reached[index] = true;
+                        frontier.push((x, y));
+                    }
+                }
+            }
+
+            for y in 0..prefab.height {
+                for x in 0..prefab.width {
+                    if prefab.cell(x, y).is_walkable() {
+                        assert!(reached[(x + y * prefab.width) as usize]);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -38,6 +38,33 @@ pub fn draw_char<C, G>(
     context: Context,
     graphics: &mut G,
 ) -> Result<(), C::Error>
+where
+    C: CharacterCache,
+    G: Graphics<Texture = <C as CharacterCache>::Texture>,
+{
+    draw_char_at(
+        x as f64,
+        y as f64,
+        color,
+        grid_size,
+        character,
+        glyph_cache,
+        context,
+        graphics,
+    )
+}
+
+/// Draw a character anywhere, even between two cells of the grid, like while it moves.
+pub fn draw_char_at<C, G>(
+    x: f64,
+    y: f64,
+    color: [ColorComponent; 4],
+    grid_size: u32,
+    character: char,
+    glyph_cache: &mut C,
+    context: Context,
+    graphics: &mut G,
+) -> Result<(), C::Error>
 where
     C: CharacterCache,
     G: Graphics<Texture = <C as CharacterCache>::Texture>,
@@ -58,8 +85,8 @@ where
         character.texture,
         &Default::default(),
         context.transform.trans(
-            x as f64 * grid_size as f64 + font_adjust_x,
-            y as f64 * grid_size as f64 + font_adjust_y,
+            x * grid_size as f64 + font_adjust_x,
+            y * grid_size as f64 + font_adjust_y,
         ),
         graphics,
     );
@@ -122,6 +149,46 @@ where
     Ok(())
 }
 
+/// Break a text into lines of at most `width` characters, between words when possible.
+/// Words longer than a whole line are split wherever they have to.
+pub fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = vec![];
+    let mut line = String::new();
+    let mut line_len = 0;
+
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        if line_len > 0 && line_len + 1 + word.len() > width {
+            lines.push(std::mem::take(&mut line));
+            line_len = 0;
+        }
+        while word.len() > width {
+            if line_len > 0 {
+                lines.push(std::mem::take(&mut line));
+                line_len = 0;
+            }
+            let rest = word.split_off(width);
+            lines.push(word.into_iter().collect());
+            word = rest;
+        }
+        if word.is_empty() {
+            continue;
+        }
+        if line_len > 0 {
+            line.push(' ');
+            line_len += 1;
+        }
+        line_len += word.len();
+        line.extend(word);
+    }
+    if line_len > 0 || lines.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
 pub fn draw_square<G>(
     x: i32,
     y: i32,
@@ -252,3 +319,25 @@ pub fn draw_border<G>(
         graphics,
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::wrap_text;
+
+    #[test]
+    fn text_wraps_between_words() {
+        assert_eq!(
+            wrap_text("The fireball hits the orc for 8 damage.", 16),
+            vec!["The fireball", "hits the orc for", "8 damage."]
+        );
+        assert_eq!(wrap_text("", 16), vec![""]);
+    }
+
+    #[test]
+    fn long_words_are_split() {
+        assert_eq!(
+            wrap_text("A Wooooooooooooord", 6),
+            vec!["A", "Wooooo", "oooooo", "oord"]
+        );
+    }
+}
@@ -1,9 +1,309 @@
-use legion::Entity;
+use legion::{Entity, World};
+use rand::{rngs::StdRng, SeedableRng};
+use std::collections::HashMap;
 
-use crate::map::Position;
+use crate::game::Action;
+use crate::map::{Map, Position};
+use serde::{Deserialize, Serialize};
 
 pub struct SharedInfo {
     pub player_entity: Entity,
     pub player_position: Position,
+    /// Set when the player changes position, until their field of view is recalculated.
+    pub player_moved: bool,
     pub alive: bool,
 }
+
+/// Cheats to help testing the level generation. Only enabled in debug builds.
+#[derive(Default)]
+pub struct WizardMode {
+    pub enabled: bool,
+    /// Show every entity of the map, ignoring the player's field of view.
+    pub see_all: bool,
+    /// Nothing hurts the player.
+    pub god_mode: bool,
+    /// The monsters leave the player alone, they don't even move.
+    pub peaceful: bool,
+}
+
+/// Something noteworthy that happened during a turn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GameEvent {
+    EntityDied { entity: Entity },
+    ItemPickedUp { collected_by: Entity, item: Entity },
+    LevelDescended { depth: i32 },
+    LevelAscended { depth: i32 },
+    PlayerDamaged { damage: i32 },
+    GameWon,
+}
+
+/// Events pushed by the systems, waiting to be drained by whoever is interested:
+/// sounds, achievements, animations...
+pub struct GameEvents {
+    events: Vec<GameEvent>,
+}
+
+impl GameEvents {
+    pub fn new() -> Self {
+        GameEvents { events: vec![] }
+    }
+
+    pub fn push(&mut self, event: GameEvent) {
+        self.events.push(event);
+    }
+
+    pub fn drain(&mut self) -> Vec<GameEvent> {
+        self.events.drain(..).collect()
+    }
+
+    /// The events pushed so far, without draining them.
+    pub fn iter(&self) -> impl Iterator<Item = &GameEvent> {
+        self.events.iter()
+    }
+}
+
+/// Why an automatic action stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InterruptReason {
+    MonsterSighted,
+    TookDamage,
+    HpLow,
+    ReachedGoal,
+}
+
+/// Multi-turn automation, like resting, repeating an action on behalf of the player until
+/// the interrupt system finds a reason to stop.
+pub struct AutoPilot {
+    /// What the player is doing, for the journal: "resting"...
+    activity: &'static str,
+    action: Option<Action>,
+    /// Where the player is heading, if anywhere.
+    goal: Option<Position>,
+    turns_left: u32,
+    interrupted: Option<InterruptReason>,
+}
+
+impl AutoPilot {
+    pub fn new() -> Self {
+        AutoPilot {
+            activity: "",
+            action: None,
+            goal: None,
+            turns_left: 0,
+            interrupted: None,
+        }
+    }
+
+    pub fn start(
+        &mut self,
+        activity: &'static str,
+        action: Action,
+        goal: Option<Position>,
+        turns: u32,
+    ) {
+        *self = AutoPilot {
+            activity,
+            action: Some(action),
+            goal,
+            turns_left: turns,
+            interrupted: None,
+        };
+    }
+
+    /// Hand the control back to the player.
+    pub fn stop(&mut self) {
+        self.action = None;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.action.is_some()
+    }
+
+    pub fn activity(&self) -> &'static str {
+        self.activity
+    }
+
+    pub fn goal(&self) -> Option<Position> {
+        self.goal
+    }
+
+    /// Whether all the planned turns went by.
+    pub fn is_done(&self) -> bool {
+        self.turns_left == 0
+    }
+
+    /// Stop for the given reason.
+    pub fn interrupt(&mut self, reason: InterruptReason) {
+        self.interrupted = Some(reason);
+        self.action = None;
+    }
+
+    /// Why the last automatic action stopped, if it was interrupted.
+    pub fn interrupted(&self) -> Option<InterruptReason> {
+        self.interrupted
+    }
+
+    /// The action to play this turn, or None when the player is back in control.
+    pub fn next_action(&mut self) -> Option<Action> {
+        let action = self.action?;
+        if self.turns_left == 0 {
+            self.action = None;
+            return None;
+        }
+        self.turns_left -= 1;
+        Some(action)
+    }
+}
+
+/// How the player's field of view is calculated, can be switched during the game.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FovAlgorithm {
+    Raycast,
+    Shadowcast,
+}
+
+impl Default for FovAlgorithm {
+    fn default() -> Self {
+        FovAlgorithm::Raycast
+    }
+}
+
+impl FovAlgorithm {
+    pub fn toggled(self) -> Self {
+        match self {
+            FovAlgorithm::Raycast => FovAlgorithm::Shadowcast,
+            FovAlgorithm::Shadowcast => FovAlgorithm::Raycast,
+        }
+    }
+}
+
+/// How the game is displayed. Not part of the game itself, they survive a restart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// How many times per second the window may redraw, and only when something changed.
+    pub max_fps: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings { max_fps: 30 }
+    }
+}
+
+/// Seconds since the last frame, for the animations.
+pub struct FrameTime(pub f32);
+
+/// Set once the player got hold of the quest item.
+pub struct GameWon(pub bool);
+
+/// How many turns went by since the beginning of the game.
+pub struct Turn(pub u32);
+
+/// The randomness of the game, seeded once from the game seed. Systems draw from it for item
+/// effects and monster decisions, so that a game can be reproduced from its seed and actions.
+pub struct GameRng(pub StdRng);
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        GameRng(GameRng::stream(seed, 0))
+    }
+
+    /// A dedicated sub-stream of the game seed. Each level is generated from its own, so that
+    /// it comes out the same whatever happened in the game before reaching it.
+    pub fn stream(seed: u64, index: u64) -> StdRng {
+        // Mixing the seed before adding the index, so that (seed, 1) and (seed + 1, 0) don't
+        // end up on the same stream.
+        StdRng::seed_from_u64(split_mix(split_mix(seed) ^ index))
+    }
+}
+
+/// The SplitMix64 finalizer, spreading every bit of the input over the whole output.
+fn split_mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReplayEntry {
+    pub turn: u32,
+    pub action: Action,
+}
+
+/// Every action of the player, in order, to reproduce a game from its seed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplayLog {
+    turn: u32,
+    entries: Vec<ReplayEntry>,
+}
+
+impl ReplayLog {
+    pub fn new() -> Self {
+        ReplayLog::default()
+    }
+
+    pub fn record(&mut self, action: Action) {
+        self.entries.push(ReplayEntry {
+            turn: self.turn,
+            action,
+        });
+    }
+
+    pub fn next_turn(&mut self) {
+        self.turn += 1;
+    }
+
+    pub fn entries(&self) -> &[ReplayEntry] {
+        &self.entries
+    }
+
+    pub fn actions(&self) -> Vec<Action> {
+        self.entries.iter().map(|entry| entry.action).collect()
+    }
+}
+
+/// The levels the player left, as they left them, so that coming back doesn't generate them anew.
+#[derive(Default)]
+pub struct LevelCache {
+    levels: HashMap<i32, (Map, World)>,
+}
+
+impl LevelCache {
+    /// Keep a level for later, with everything that was on it.
+    pub fn store(&mut self, map: Map, world: World) {
+        self.levels.insert(map.depth, (map, world));
+    }
+
+    /// The level at this depth, if the player was already there.
+    pub fn take(&mut self, depth: i32) -> Option<(Map, World)> {
+        self.levels.remove(&depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GameRng;
+    use rand::Rng;
+
+    fn first_values(seed: u64, index: u64) -> Vec<u64> {
+        let mut rng = GameRng::stream(seed, index);
+        (0..4).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn neighbouring_streams_differ() {
+        for seed in [0, 1, 41, 42, u64::MAX].iter().copied() {
+            let stream = first_values(seed, 1);
+            assert_ne!(stream, first_values(seed.wrapping_add(1), 0));
+            assert_ne!(stream, first_values(seed, 0));
+            assert_ne!(stream, first_values(seed, 2));
+            assert_ne!(stream, first_values(seed.wrapping_add(1), 1));
+        }
+    }
+
+    #[test]
+    fn streams_are_reproducible() {
+        assert_eq!(first_values(42, 3), first_values(42, 3));
+    }
+}
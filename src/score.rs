@@ -0,0 +1,150 @@
+use std::{fs, io, path::Path};
+
+use legion::Entity;
+use serde::{Deserialize, Serialize};
+
+use crate::resources::GameEvent;
+
+// Scoring weights.
+const POINTS_PER_KILL: u32 = 10;
+const POINTS_PER_DEPTH: u32 = 50;
+const POINTS_PER_ITEM: u32 = 5;
+const VICTORY_BONUS: u32 = 1000;
+
+/// How many games the high score table remembers.
+const MAX_HIGH_SCORES: usize = 10;
+pub const HIGH_SCORES_FILE: &str = "highscores.json";
+
+/// What the player achieved so far, fed by the game events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Score {
+    pub kills: u32,
+    pub deepest: i32,
+    pub items: u32,
+    pub won: bool,
+}
+
+impl Default for Score {
+    fn default() -> Self {
+        Score {
+            kills: 0,
+            deepest: 1,
+            items: 0,
+            won: false,
+        }
+    }
+}
+
+impl Score {
+    pub fn record(&mut self, event: &GameEvent, player: Entity) {
+        match *event {
+            GameEvent::EntityDied { entity } if entity != player => self.kills += 1,
+            GameEvent::ItemPickedUp { collected_by, .. } if collected_by == player => {
+                self.items += 1
+            }
+            GameEvent::LevelDescended { depth } => self.deepest = self.deepest.max(depth),
+            GameEvent::GameWon => self.won = true,
+            _ => {}
+        }
+    }
+
+    pub fn points(&self) -> u32 {
+        let depth_points = (self.deepest.max(1) - 1) as u32 * POINTS_PER_DEPTH;
+        let victory_points = if self.won { VICTORY_BONUS } else { 0 };
+
+        self.kills * POINTS_PER_KILL + self.items * POINTS_PER_ITEM + depth_points + victory_points
+    }
+}
+
+/// A finished game, as stored in the high score table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HighScore {
+    pub points: u32,
+    pub depth: i32,
+    pub turns: u32,
+    pub won: bool,
+}
+
+/// The best games so far, best first.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HighScores {
+    entries: Vec<HighScore>,
+}
+
+impl HighScores {
+    /// Read the table from disk. A missing or unreadable file is an empty table, nobody wants
+    /// a crash on the game over screen.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)
+    }
+
+    /// Insert a game in the table. Returns its rank, or None if it didn't make the cut.
+    pub fn add(&mut self, high_score: HighScore) -> Option<usize> {
+        // Ties go to the older game.
+        let rank = self
+            .entries
+            .iter()
+            .position(|entry| entry.points < high_score.points)
+            .unwrap_or_else(|| self.entries.len());
+        if rank >= MAX_HIGH_SCORES {
+            return None;
+        }
+
+        self.entries.insert(rank, high_score);
+        self.entries.truncate(MAX_HIGH_SCORES);
+        Some(rank)
+    }
+
+    pub fn entries(&self) -> &[HighScore] {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HighScore, HighScores, MAX_HIGH_SCORES};
+
+    fn high_score(points: u32) -> HighScore {
+        HighScore {
+            points,
+            depth: 1,
+            turns: 100,
+            won: false,
+        }
+    }
+
+    #[test]
+    fn high_scores_stay_sorted_and_capped() {
+        let mut high_scores = HighScores::default();
+        for points in 0..MAX_HIGH_SCORES as u32 {
+            high_scores.add(high_score(points * 10));
+        }
+
+        assert_eq!(high_scores.add(high_score(0)), None);
+        assert_eq!(high_scores.add(high_score(55)), Some(4));
+        assert_eq!(high_scores.entries().len(), MAX_HIGH_SCORES);
+        assert_eq!(high_scores.entries()[0].points, 90);
+        assert_eq!(high_scores.entries()[MAX_HIGH_SCORES - 1].points, 10);
+    }
+
+    #[test]
+    fn high_scores_survive_a_round_trip() {
+        let path = std::env::temp_dir().join("ambergris_highscores_test.json");
+        let mut high_scores = HighScores::default();
+        high_scores.add(high_score(42));
+
+        high_scores.save(&path).unwrap();
+        assert_eq!(HighScores::load(&path), high_scores);
+
+        std::fs::remove_file(&path).ok();
+        assert_eq!(HighScores::load(&path), HighScores::default());
+    }
+}
@@ -1,71 +1,247 @@
 use legion::{Entity, World};
 
-use crate::{colors, components::*, game::Ai, map::Position, palette};
+use crate::{colors, colors::Color, components::*, game::Ai, map::Position, palette};
 
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum MonsterType {
     Orc,
     Troll,
+    Shaman,
+}
+
+impl MonsterType {
+    /// How every monster of this type looks, defined in one place.
+    pub fn glyph_and_color(&self) -> (char, Color) {
+        match self {
+            MonsterType::Orc => ('o', colors::DESATURATED_GREEN),
+            MonsterType::Troll => ('T', colors::DARKER_GREEN),
+            MonsterType::Shaman => ('s', colors::PURPLE),
+        }
+    }
+}
+
+/// What the player knows about each creature, by name, for the examine screen.
+const DESCRIPTIONS: &[(&str, &str)] = &[
+    (
+        "orc",
+        "A brute of the deep tunnels. Not clever, but there are always more where this one came from.",
+    ),
+    (
+        "troll",
+        "A hulking mass of muscle and warts. Its wounds close as you watch, and its blows send you flying.",
+    ),
+    (
+        "orc shaman",
+        "Frail for an orc, but it spits venom from afar and its calls never go unanswered.",
+    ),
+    (
+        "dog",
+        "A loyal companion, happy to bite whatever gets too close to you.",
+    ),
+];
+
+/// The flavor text of a creature, from its name.
+pub fn description(name: &str) -> &'static str {
+    DESCRIPTIONS
+        .iter()
+        .find(|(creature, _)| *creature == name)
+        .map_or(
+            "Nobody knows much about this creature.",
+            |(_, description)| description,
+        )
 }
 
 pub fn monster(world: &mut World, monster_type: MonsterType, x: i32, y: i32) {
     match monster_type {
         MonsterType::Orc => orc(world, x, y),
         MonsterType::Troll => troll(world, x, y),
+        MonsterType::Shaman => shaman(world, x, y),
     };
 }
 
+/// Spawn a group of monsters of the same type, the first one at the center and the others
+/// on the closest spawn points. Used spawn points are removed, so that nothing overlaps.
+/// Returns where the monsters were placed.
+pub fn pack(
+    world: &mut World,
+    monster_type: MonsterType,
+    center: Position,
+    count: i32,
+    spawn_points: &mut Vec<Position>,
+) -> Vec<Position> {
+    monster(world, monster_type, center.x, center.y);
+    let mut placed = vec![center];
+
+    // Furthest first, so that the closest ones can be popped.
+    spawn_points.sort_by(|a, b| {
+        b.distance_to(center)
+            .partial_cmp(&a.distance_to(center))
+            .unwrap()
+    });
+    for _ in 1..count {
+        if let Some(position) = spawn_points.pop() {
+            monster(world, monster_type, position.x, position.y);
+            placed.push(position);
+        }
+    }
+    placed
+}
+
 fn orc(world: &mut World, x: i32, y: i32) {
+    let (char, color) = MonsterType::Orc.glyph_and_color();
     let body = Body {
         name: "orc".into(),
         blocking: true,
-        char: 'o',
-        color: colors::DESATURATED_GREEN,
-    };
-    let combat_stats = CombatStats {
-        max_hp: 12,
-        hp: 12,
-        defense: 0,
-        attack: 3,
+        char,
+        color,
+        render_priority: RENDER_PRIORITY_MONSTER,
     };
+    let combat_stats = CombatStats::new(12, 3, 0);
     world.push((
         Monster {
-            ai: Ai::Basic,
+            ai: Ai::Wander,
             speed: 900,
             tick: 0,
+            last_move: (0, 0),
+            flee_threshold: 0.25,
+            fleeing: false,
+            awareness: 0.0,
+            aggression_range: 8,
         },
         Position::new(x, y),
         body,
         combat_stats,
+        FieldOfView::new(8),
+        Faction::Monsters,
+        Hearing { threshold: 3 },
     ));
 }
 
 fn troll(world: &mut World, x: i32, y: i32) {
+    let (char, color) = MonsterType::Troll.glyph_and_color();
     let body = Body {
         name: "troll".into(),
         blocking: true,
-        char: 'T',
-        color: colors::DARKER_GREEN,
-    };
-    let combat_stats = CombatStats {
-        max_hp: 20,
-        hp: 20,
-        defense: 1,
-        attack: 4,
+        char,
+        color,
+        render_priority: RENDER_PRIORITY_MONSTER,
     };
+    let combat_stats = CombatStats::new(20, 4, 1).with_knockback(1);
     world.push((
         Monster {
             ai: Ai::Basic,
             speed: 1100,
             tick: 0,
+            last_move: (0, 0),
+            flee_threshold: 0.0,
+            fleeing: false,
+            awareness: 0.0,
+            aggression_range: 4,
         },
         Position { x, y },
         body,
         combat_stats,
+        FieldOfView::new(8),
+        Faction::Monsters,
+        Hearing { threshold: 5 },
+        Abilities {
+            on_turn: vec![Ability::Regenerate { amount: 1 }],
+        },
+    ));
+}
+
+fn shaman(world: &mut World, x: i32, y: i32) {
+    let (char, color) = MonsterType::Shaman.glyph_and_color();
+    let body = Body {
+        name: "orc shaman".into(),
+        blocking: true,
+        char,
+        color,
+        render_priority: RENDER_PRIORITY_MONSTER,
+    };
+    let combat_stats = CombatStats::new(8, 2, 0);
+    world.push((
+        Monster {
+            ai: Ai::Basic,
+            speed: 1000,
+            tick: 0,
+            last_move: (0, 0),
+            flee_threshold: 0.5,
+            fleeing: false,
+            awareness: 0.0,
+            aggression_range: 8,
+        },
+        Position::new(x, y),
+        body,
+        combat_stats,
+        FieldOfView::new(8),
+        Faction::Monsters,
+        Hearing { threshold: 3 },
+        Abilities {
+            on_turn: vec![
+                Ability::RangedSpit {
+                    damage: 3,
+                    range: 6,
+                    chance: 0.5,
+                },
+                Ability::Summon {
+                    monster_type: MonsterType::Orc,
+                    chance: 0.05,
+                },
+            ],
+        },
     ));
 }
 
-pub fn player(world: &mut World, x: i32, y: i32) -> Entity {
+/// A loyal dog, fighting on the side of the player.
+pub fn companion(world: &mut World, x: i32, y: i32) -> Entity {
     world.push((
+        Monster {
+            ai: Ai::Companion,
+            speed: 1000,
+            tick: 0,
+            last_move: (0, 0),
+            flee_threshold: 0.0,
+            fleeing: false,
+            awareness: 0.0,
+            aggression_range: 8,
+        },
+        Position::new(x, y),
+        Body {
+            name: "dog".into(),
+            blocking: true,
+            char: 'd',
+            color: palette::COMPANION,
+            render_priority: RENDER_PRIORITY_MONSTER,
+        },
+        CombatStats::new(15, 3, 1),
+        FieldOfView::new(8),
+        Faction::Player,
+    ))
+}
+
+/// Everything the player starts the game with, to try out classes or specific scenarios.
+pub struct PlayerTemplate {
+    pub combat_stats: CombatStats,
+    /// Starting items, and how many of each.
+    pub inventory: Vec<(ItemType, i32)>,
+    pub fov_radius: i32,
+    pub stealth: i32,
+}
+
+impl Default for PlayerTemplate {
+    fn default() -> Self {
+        PlayerTemplate {
+            combat_stats: CombatStats::new(30, 5, 2),
+            inventory: vec![],
+            fov_radius: 10,
+            stealth: 0,
+        }
+    }
+}
+
+pub fn player(world: &mut World, x: i32, y: i32, template: &PlayerTemplate) -> Entity {
+    let player = world.push((
         Player { speed: 1000 },
         Position { x, y },
         Body {
@@ -73,21 +249,57 @@ pub fn player(world: &mut World, x: i32, y: i32) -> Entity {
             blocking: true,
             char: '@',
             color: colors::YELLOW,
+            render_priority: RENDER_PRIORITY_PLAYER,
         },
-        CombatStats {
-            max_hp: 30,
-            hp: 30,
-            attack: 5,
-            defense: 2,
-        },
+        template.combat_stats.clone(),
         MagicStats {
             max_mana: 10,
             mana: 10,
         },
-    ))
+        Vision::new(template.fov_radius),
+        Faction::Player,
+        Stealth {
+            level: template.stealth,
+            last_position: Position { x, y },
+        },
+    ));
+
+    for &(item_type, count) in template.inventory.iter() {
+        let item_entity = item(world, item_type, x, y);
+        if let Some(mut entry) = world.entry(item_entity) {
+            entry.remove_component::<Position>();
+            entry.add_component(InInventory { owner: player });
+            entry.add_component(Stack { count });
+        }
+    }
+
+    player
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ItemType {
+    Potion,
+    ScrollOfLightningBolt,
+    ScrollOfFireball,
+    ScrollOfPoisonCloud,
+    ScrollOfTeleport,
+    ScrollOfCompanion,
+    Torch,
+}
+
+pub fn item(world: &mut World, item_type: ItemType, x: i32, y: i32) -> Entity {
+    match item_type {
+        ItemType::Potion => potion(world, x, y),
+        ItemType::ScrollOfLightningBolt => scroll_of_lightning_bolt(world, x, y),
+        ItemType::ScrollOfFireball => scroll_of_fireball(world, x, y),
+        ItemType::ScrollOfPoisonCloud => scroll_of_poison_cloud(world, x, y),
+        ItemType::ScrollOfTeleport => scroll_of_teleport(world, x, y),
+        ItemType::ScrollOfCompanion => scroll_of_companion(world, x, y),
+        ItemType::Torch => torch(world, x, y),
+    }
 }
 
-pub fn potion(world: &mut World, x: i32, y: i32) {
+pub fn potion(world: &mut World, x: i32, y: i32) -> Entity {
     world.push((
         Item {},
         Position { x, y },
@@ -96,13 +308,14 @@ pub fn potion(world: &mut World, x: i32, y: i32) {
             blocking: false,
             char: 'i',
             color: palette::HEALTH,
+            render_priority: RENDER_PRIORITY_ITEM,
         },
         ProvidesHealing { heal_amount: 5 },
         Consumable {},
-    ));
+    ))
 }
 
-pub fn scroll_of_lightning_bolt(world: &mut World, x: i32, y: i32) {
+pub fn scroll_of_lightning_bolt(world: &mut World, x: i32, y: i32) -> Entity {
     world.push((
         Item {},
         Position { x, y },
@@ -111,14 +324,15 @@ pub fn scroll_of_lightning_bolt(world: &mut World, x: i32, y: i32) {
             blocking: false,
             char: '#',
             color: colors::CYAN,
+            render_priority: RENDER_PRIORITY_ITEM,
         },
         InflictsDamage { damage: 10 },
         Ranged { range: 7 },
         Consumable {},
-    ));
+    ))
 }
 
-pub fn scroll_of_fireball(world: &mut World, x: i32, y: i32) {
+pub fn scroll_of_fireball(world: &mut World, x: i32, y: i32) -> Entity {
     world.push((
         Item {},
         Position { x, y },
@@ -127,12 +341,111 @@ pub fn scroll_of_fireball(world: &mut World, x: i32, y: i32) {
             blocking: false,
             char: '#',
             color: colors::ORANGE,
+            render_priority: RENDER_PRIORITY_ITEM,
         },
         InflictsDamage { damage: 8 },
         Ranged { range: 6 },
         Burst { radius: 1 },
+        SpawnsHazard {
+            kind: HazardKind::Fire,
+            damage: 2,
+            turns: 3,
+        },
         Consumable {},
-    ));
+    ))
+}
+
+pub fn scroll_of_poison_cloud(world: &mut World, x: i32, y: i32) -> Entity {
+    world.push((
+        Item {},
+        Position { x, y },
+        Body {
+            name: "scroll of poison cloud".to_string(),
+            blocking: false,
+            char: '#',
+            color: colors::DESATURATED_GREEN,
+            render_priority: RENDER_PRIORITY_ITEM,
+        },
+        Ranged { range: 6 },
+        Burst { radius: 2 },
+        SpawnsHazard {
+            kind: HazardKind::Gas,
+            damage: 1,
+            turns: 5,
+        },
+        Consumable {},
+    ))
+}
+
+pub fn scroll_of_teleport(world: &mut World, x: i32, y: i32) -> Entity {
+    world.push((
+        Item {},
+        Position { x, y },
+        Body {
+            name: "scroll of teleport".to_string(),
+            blocking: false,
+            char: '#',
+            color: colors::MAGENTA,
+            render_priority: RENDER_PRIORITY_ITEM,
+        },
+        Teleports {},
+        Consumable {},
+    ))
+}
+
+pub fn scroll_of_companion(world: &mut World, x: i32, y: i32) -> Entity {
+    world.push((
+        Item {},
+        Position { x, y },
+        Body {
+            name: "scroll of companionship".to_string(),
+            blocking: false,
+            char: '#',
+            color: palette::COMPANION,
+            render_priority: RENDER_PRIORITY_ITEM,
+        },
+        SummonsCompanion {},
+        Consumable {},
+    ))
+}
+
+pub fn torch(world: &mut World, x: i32, y: i32) -> Entity {
+    world.push((
+        Item {},
+        Position { x, y },
+        Body {
+            name: "torch".to_string(),
+            blocking: false,
+            char: '/',
+            color: colors::ORANGE,
+            render_priority: RENDER_PRIORITY_ITEM,
+        },
+        BoostsVision {
+            amount: 4,
+            turns: 50,
+        },
+        Consumable {},
+    ))
+}
+
+/// The amulet of Ambergris, waiting at the bottom of the dungeon.
+pub fn macguffin(world: &mut World, x: i32, y: i32) -> Entity {
+    world.push((
+        Item {},
+        Position { x, y },
+        Body {
+            name: "amulet of Ambergris".to_string(),
+            blocking: false,
+            char: '"',
+            color: colors::YELLOW,
+            render_priority: RENDER_PRIORITY_ITEM,
+        },
+        QuestItem {},
+    ))
+}
+
+pub fn light_source(world: &mut World, x: i32, y: i32, radius: i32, color: Color) -> Entity {
+    world.push((Position { x, y }, LightSource { radius, color }))
 }
 
 pub fn stairs(world: &mut World, x: i32, y: i32) {
@@ -141,9 +454,24 @@ pub fn stairs(world: &mut World, x: i32, y: i32) {
         Body {
             name: "stairs".to_string(),
             blocking: false,
-            char: '<',
+            char: '>',
             color: palette::LIGHT_WALL,
+            render_priority: RENDER_PRIORITY_FURNITURE,
         },
         Interactable::DownStairs,
     ));
 }
+
+pub fn up_stairs(world: &mut World, x: i32, y: i32) {
+    world.push((
+        Position { x, y },
+        Body {
+            name: "stairs up".to_string(),
+            blocking: false,
+            char: '<',
+            color: palette::LIGHT_WALL,
+            render_priority: RENDER_PRIORITY_FURNITURE,
+        },
+        Interactable::UpStairs,
+    ));
+}
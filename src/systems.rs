@@ -1,7 +1,14 @@
-use crate::map::Map;
-use crate::resources::SharedInfo;
+use crate::map::{Decal, Map};
+use crate::resources::{
+    AutoPilot, FrameTime, GameEvent, GameEvents, GameRng, GameWon, InterruptReason, SharedInfo,
+    WizardMode,
+};
+use crate::spawner;
 use crate::utils::field_of_view_no_walls;
-use crate::{colors::DARK_RED, game::Journal};
+use crate::{
+    colors::DARK_RED,
+    game::{Journal, JournalSection},
+};
 use crate::{components::*, game::Ai};
 use crate::{game::RunState, map::Position};
 use legion::system;
@@ -10,12 +17,44 @@ use legion::world::SubWorld;
 use legion::Entity;
 use legion::IntoQuery;
 use legion::Schedule;
-use legion::{component, Write};
+use legion::{component, TryRead, Write};
+use rand::Rng;
 use torchbearer::path::astar_path_fourwaygrid;
+use torchbearer::Map as FieldOfVisionMap;
+
+/// How loud a melee attack is, in tiles.
+const MELEE_NOISE: i32 = 10;
+/// How much quieter noises get at the end of each turn.
+const NOISE_FADE: i32 = 4;
+/// Chances for a wandering monster to keep going in the same direction.
+const WANDER_MOMENTUM: f32 = 0.8;
+/// Below this fraction of their max hp, the player is too hurt to keep going on their own.
+const LOW_HP_RATIO: f32 = 0.3;
+/// Monsters further away from the player than their sight plus this margin can't notice them,
+/// so they don't bother looking around.
+const IDLE_MARGIN: i32 = 2;
+/// How far companions let the player go before catching up.
+const FOLLOW_DISTANCE: i32 = 2;
+/// For how many turns the body of a dead creature lies around before rotting away.
+const CORPSE_DECAY_TURNS: i32 = 100;
+/// Monsters stay aware of the player up to this, so that losing track of them takes a while.
+const MAX_AWARENESS: f32 = 2.0;
+/// How much awareness a monster loses on each turn it doesn't see the player.
+const AWARENESS_DECAY: f32 = 0.05;
+/// How much awareness a monster loses on each turn the player rests in the shadows.
+const SHADOW_RELIEF: f32 = 0.1;
+/// How noticeable a player standing still is, compared to one moving around.
+const WAITING_PACE: f32 = 0.5;
 
 pub fn game_schedule() -> Schedule {
     Schedule::builder()
+        .add_system(compute_fov_system())
+        .add_system(player_distances_system())
         .add_system(monster_action_system())
+        .add_system(abilities_system())
+        .add_system(hazards_system())
+        .add_system(fade_light_system())
+        .add_system(decay_corpses_system())
         .add_system(use_item_system())
         .add_system(drop_item_system())
         .flush()
@@ -27,57 +66,535 @@ pub fn game_schedule() -> Schedule {
         .flush()
         .add_system(cleanup_deads_system())
         .add_system(update_map_and_position_system())
+        .add_system(lighting_system())
         .add_system(update_game_state_system())
+        .add_system(check_victory_system())
+        .add_system(interrupt_system())
+        .add_system(fade_noise_system())
         .build()
 }
 
+/// What happens between frames rather than between turns.
+pub fn animation_schedule() -> Schedule {
+    Schedule::builder().add_system(animate_system()).build()
+}
+
 #[system(for_each)]
-#[filter(!component::<Player>())]
+pub fn animate(
+    cmd: &mut CommandBuffer,
+    entity: &Entity,
+    animation: &mut Animation,
+    #[resource] frame_time: &FrameTime,
+) {
+    animation.progress = (animation.progress + frame_time.0 / ANIMATION_DURATION).min(1.);
+    if animation.progress >= 1. {
+        cmd.remove_component::<Animation>(*entity);
+    }
+}
+
+#[system]
+pub fn player_distances(#[resource] map: &mut Map, #[resource] shared_info: &SharedInfo) {
+    map.update_player_distances(shared_info.player_position);
+}
+
+#[system]
 #[read_component(Player)]
+#[read_component(Position)]
+#[write_component(Monster)]
+#[read_component(CombatStats)]
+#[read_component(Body)]
+#[read_component(FieldOfView)]
+#[read_component(Hearing)]
+#[read_component(Faction)]
+#[write_component(Stealth)]
 pub fn monster_action(
     cmd: &mut CommandBuffer,
-    coordinates: &Position,
-    monster: &Monster,
-    _: &CombatStats,
-    entity: &Entity,
+    world: &mut SubWorld,
     #[resource] shared_info: &SharedInfo,
     #[resource] run_state: &RunState,
     #[resource] map: &Map,
+    #[resource] journal: &mut Journal,
+    #[resource] game_rng: &mut GameRng,
+    #[resource] wizard: &WizardMode,
 ) {
-    if *run_state != RunState::AiTurn {
+    if *run_state != RunState::AiTurn || wizard.peaceful {
         return;
     }
 
-    if monster.ai == Ai::Basic {
-        let player_position = shared_info.player_position;
-        let distance = coordinates.distance_to(player_position);
-        if map.is_in_player_fov(coordinates.x, coordinates.y) {
-            if distance >= 2.0 {
-                if let Some(path) = astar_path_fourwaygrid(
-                    map,
-                    (coordinates.x, coordinates.y),
-                    player_position.into(),
-                ) {
-                    let next_step = path[1];
-
-                    let dx = next_step.0 - coordinates.x;
-                    let dy = next_step.1 - coordinates.y;
-
-                    cmd.push((MoveAction {
-                        entity: *entity,
-                        dx,
-                        dy,
-                    },));
+    let mut acting: Vec<(Position, Entity)> = <(Entity, &Position)>::query()
+        .filter(component::<Monster>() & !component::<Player>())
+        .iter(world)
+        .map(|(entity, position)| (*position, *entity))
+        .collect();
+    // The storage order depends on the ECS internals, while monsters must always act in the same order,
+    // for replays and for fairness when several of them go for the same tile: let's go in reading order.
+    acting.sort_by_key(|&(position, _)| (position.y, position.x));
+
+    // Everyone who could be fought, the player included.
+    let creatures: Vec<(Entity, Position, Faction)> =
+        <(Entity, &Position, &Faction, &CombatStats)>::query()
+            .iter(world)
+            .filter(|(_, _, _, combat_stats)| combat_stats.hp > 0)
+            .map(|(entity, position, faction, _)| (*entity, *position, *faction))
+            .collect();
+
+    let player_position = shared_info.player_position;
+    let sneaking = <&Stealth>::query()
+        .get(world, shared_info.player_entity)
+        .ok()
+        .map(|stealth| Sneaking {
+            level: stealth.level,
+            moved: stealth.last_position != player_position,
+            in_shadow: map
+                .ambient_light_at(player_position.x, player_position.y)
+                .is_none(),
+        });
+
+    let mut query = <(
+        &Position,
+        &mut Monster,
+        &CombatStats,
+        &Body,
+        &FieldOfView,
+        &Faction,
+        TryRead<Hearing>,
+    )>::query();
+    for (_, entity) in acting {
+        let (coordinates, monster, combat_stats, body, field_of_view, faction, hearing) =
+            match query.get_mut(world, entity) {
+                Ok(components) => components,
+                Err(_) => continue,
+            };
+
+        // Far away monsters are left with listening and wandering, no need to look for a target.
+        // Companions have to keep up with the player anyway.
+        let is_idle = monster.ai != Ai::Companion
+            && coordinates.chebyshev_distance_to(player_position)
+                > field_of_view.radius + IDLE_MARGIN;
+
+        if monster.ai != Ai::Companion {
+            let sees_player = !is_idle && field_of_view.visible.contains(&player_position.into());
+            let proximity = (1.0
+                - coordinates.distance_to(player_position) / (field_of_view.radius + 1) as f32)
+                .max(0.1);
+            let was_aware = monster.is_aware();
+            update_awareness(monster, sees_player, proximity, sneaking, &mut game_rng.0);
+            if sneaking.is_some()
+                && !was_aware
+                && monster.is_aware()
+                && map.is_in_player_fov(coordinates.x, coordinates.y)
+            {
+                journal.log(format!("The {} notices you!", body.name));
+            }
+        }
+
+        let target = if is_idle {
+            None
+        } else {
+            let unnoticed = ignored_player(monster, *coordinates, shared_info);
+            closest_hostile(*coordinates, *faction, field_of_view, &creatures, unnoticed)
+        };
+
+        if let Some((dx, dy)) = flee_when_hurt(
+            map,
+            journal,
+            monster,
+            combat_stats,
+            body,
+            *coordinates,
+            target.map(|(_, target_position)| target_position),
+        ) {
+            cmd.push((MoveAction { entity, dx, dy },));
+            continue;
+        }
+
+        if let Some((target_entity, target_position)) = target {
+            if coordinates.distance_to(target_position) >= 2.0 {
+                let step = if target_entity == shared_info.player_entity {
+                    map.step_towards_player(*coordinates)
+                } else {
+                    map.step_towards(*coordinates, target_position)
+                };
+                if let Some((dx, dy)) = step {
+                    cmd.push((MoveAction { entity, dx, dy },));
                 }
             } else {
                 // Attack!
-                let attack_action = AttackAction {
-                    target_entity: shared_info.player_entity.clone(),
-                };
-                cmd.add_component(*entity, attack_action);
+                cmd.add_component(entity, AttackAction { target_entity });
             }
+        } else if let Some((dx, dy)) = idle_step(
+            map,
+            monster,
+            *coordinates,
+            hearing,
+            player_position,
+            &mut game_rng.0,
+        ) {
+            cmd.push((MoveAction { entity, dx, dy },));
         }
     }
+
+    if let Ok(stealth) = <&mut Stealth>::query().get_mut(world, shared_info.player_entity) {
+        stealth.last_position = player_position;
+    }
+}
+
+#[system]
+#[read_component(Position)]
+#[read_component(Body)]
+#[read_component(FieldOfView)]
+#[read_component(Abilities)]
+#[read_component(Monster)]
+#[write_component(CombatStats)]
+pub fn abilities(
+    cmd: &mut CommandBuffer,
+    world: &mut SubWorld,
+    #[resource] shared_info: &SharedInfo,
+    #[resource] run_state: &RunState,
+    #[resource] map: &Map,
+    #[resource] journal: &mut Journal,
+    #[resource] game_rng: &mut GameRng,
+    #[resource] wizard: &WizardMode,
+) {
+    if *run_state != RunState::AiTurn || wizard.peaceful {
+        return;
+    }
+
+    let mut acting: Vec<(Position, Entity)> = <(Entity, &Position)>::query()
+        .filter(component::<Abilities>() & component::<CombatStats>())
+        .iter(world)
+        .map(|(entity, position)| (*position, *entity))
+        .collect();
+    // Same reading order as monster_action, for replays.
+    acting.sort_by_key(|&(position, _)| (position.y, position.x));
+
+    let player_position = shared_info.player_position;
+    // Summoned monsters only show up on the next flush, make sure they don't pile up on a tile.
+    let mut claimed: Vec<Position> = vec![];
+    let mut query = <(
+        &Position,
+        &Body,
+        &Abilities,
+        &mut CombatStats,
+        TryRead<FieldOfView>,
+        TryRead<Monster>,
+    )>::query();
+    for (_, entity) in acting {
+        let (position, body, abilities, combat_stats, field_of_view, monster) =
+            match query.get_mut(world, entity) {
+                Ok(components) => components,
+                Err(_) => continue,
+            };
+        // Seeing the player isn't enough, the monster also has to have noticed them.
+        let sees_player = field_of_view
+            .map(|field_of_view| field_of_view.visible.contains(&player_position.into()))
+            .unwrap_or(false)
+            && monster.map_or(true, |monster| monster.is_aware());
+        let in_sight = map.is_in_player_fov(position.x, position.y);
+
+        for ability in abilities.on_turn.iter() {
+            match *ability {
+                Ability::Regenerate { amount } => {
+                    if combat_stats.hp < combat_stats.max_hp {
+                        combat_stats.heal(amount);
+                        if in_sight {
+                            journal.log(format!("The {} regenerates.", body.name));
+                        }
+                    }
+                }
+                Ability::Summon {
+                    monster_type,
+                    chance,
+                } => {
+                    if !sees_player || game_rng.0.gen::<f32>() >= chance {
+                        continue;
+                    }
+                    let free_tile = neighbors(*position).find(|&tile| {
+                        map.is_walkable(tile.x, tile.y)
+                            && !map.is_blocked(tile)
+                            && !claimed.contains(&tile)
+                    });
+                    if let Some(Position { x, y }) = free_tile {
+                        claimed.push(Position::new(x, y));
+                        cmd.exec_mut(move |world| spawner::monster(world, monster_type, x, y));
+                        journal.log(format!("The {} calls for help!", body.name));
+                    }
+                }
+                Ability::RangedSpit {
+                    damage,
+                    range,
+                    chance,
+                } => {
+                    let distance = position.distance_to(player_position);
+                    // Up close, a good old melee attack does the job.
+                    if !sees_player
+                        || distance < 2.0
+                        || distance > range as f32
+                        || game_rng.0.gen::<f32>() >= chance
+                    {
+                        continue;
+                    }
+                    journal.log(format!(
+                        "The {} spits at you for {} damage.",
+                        body.name, damage
+                    ));
+                    cmd.push((SuffersDamage {
+                        entity: shared_info.player_entity,
+                        damage,
+                    },));
+                }
+            }
+        }
+    }
+}
+
+/// How well the player hides from the monsters this turn.
+#[derive(Clone, Copy)]
+struct Sneaking {
+    level: i32,
+    /// Whether the player moved since the last monster turn.
+    moved: bool,
+    /// Whether the player stands out of any light.
+    in_shadow: bool,
+}
+
+/// A monster seeing the player slowly notices them, quicker when they are close and moving around,
+/// and forgets about them otherwise. `proximity` goes from 1 next to the monster, down to 0.1 at the
+/// edge of its sight.
+fn update_awareness<R: Rng>(
+    monster: &mut Monster,
+    sees_player: bool,
+    proximity: f32,
+    sneaking: Option<Sneaking>,
+    rng: &mut R,
+) {
+    monster.awareness = match sneaking {
+        _ if !sees_player => (monster.awareness - AWARENESS_DECAY).max(0.0),
+        // Nothing to hide the player, they are noticed at first sight.
+        None => MAX_AWARENESS,
+        Some(Sneaking {
+            moved: false,
+            in_shadow: true,
+            ..
+        }) => (monster.awareness - SHADOW_RELIEF).max(0.0),
+        Some(Sneaking { level, moved, .. }) => {
+            let pace = if moved { 1.0 } else { WAITING_PACE };
+            let roll = rng.gen_range(0.5, 1.5);
+            let gain = proximity * pace * roll / (1 + level.max(0)) as f32;
+            (monster.awareness + gain).min(MAX_AWARENESS)
+        }
+    };
+}
+
+/// The player, as long as the monster leaves them alone: until it is aware enough of them,
+/// and they come within its aggression range. None once it goes after them.
+fn ignored_player(
+    monster: &Monster,
+    position: Position,
+    shared_info: &SharedInfo,
+) -> Option<Entity> {
+    if monster.is_aware()
+        && position.distance_to(shared_info.player_position) <= monster.aggression_range as f32
+    {
+        None
+    } else {
+        Some(shared_info.player_entity)
+    }
+}
+
+/// The step of a hurt monster running away from `threat`, logging when it starts to flee.
+/// None when it isn't hurt, or when cornered, as there's nothing left but to fight.
+fn flee_when_hurt(
+    map: &Map,
+    journal: &mut Journal,
+    monster: &mut Monster,
+    combat_stats: &CombatStats,
+    body: &Body,
+    position: Position,
+    threat: Option<Position>,
+) -> Option<(i32, i32)> {
+    let is_hurt = (combat_stats.hp as f32) < monster.flee_threshold * combat_stats.max_hp as f32;
+    if !is_hurt {
+        monster.fleeing = false;
+        return None;
+    }
+
+    let step = flee(map, position, threat?)?;
+    if !monster.fleeing {
+        journal.log(format!("The {} flees in terror.", body.name));
+        monster.fleeing = true;
+    }
+    Some(step)
+}
+
+/// What a monster without a target does: companions follow the player around, the others go
+/// check the noises they hear, and the wanderers roam around otherwise.
+fn idle_step<R: Rng>(
+    map: &Map,
+    monster: &mut Monster,
+    position: Position,
+    hearing: Option<&Hearing>,
+    player_position: Position,
+    rng: &mut R,
+) -> Option<(i32, i32)> {
+    let towards_noise =
+        || hearing.and_then(|hearing| follow_noise(map, position, hearing.threshold));
+
+    match monster.ai {
+        Ai::Companion => {
+            if position.chebyshev_distance_to(player_position) > FOLLOW_DISTANCE {
+                path_towards(map, position, player_position)
+            } else {
+                None
+            }
+        }
+        Ai::Basic => towards_noise(),
+        Ai::Wander => towards_noise().or_else(|| {
+            let step = wander(map, position, monster.last_move, rng)?;
+            monster.last_move = step;
+            Some(step)
+        }),
+    }
+}
+
+/// The closest creature in sight that the faction is hostile to, with where it stands, leaving
+/// out the `unnoticed` one. Ties are broken in reading order, for replays.
+fn closest_hostile(
+    position: Position,
+    faction: Faction,
+    field_of_view: &FieldOfView,
+    creatures: &[(Entity, Position, Faction)],
+    unnoticed: Option<Entity>,
+) -> Option<(Entity, Position)> {
+    creatures
+        .iter()
+        .filter(|(entity, other_position, other_faction)| {
+            Some(*entity) != unnoticed
+                && faction.is_hostile_to(*other_faction)
+                && field_of_view.visible.contains(&(*other_position).into())
+        })
+        .min_by(|(_, a, _), (_, b, _)| {
+            position
+                .distance_to(*a)
+                .partial_cmp(&position.distance_to(*b))
+                .unwrap()
+                .then((a.y, a.x).cmp(&(b.y, b.x)))
+        })
+        .map(|(entity, position, _)| (*entity, *position))
+}
+
+/// The first step of the shortest way to `goal`. None if there is no way there,
+/// or if someone stands in the way.
+fn path_towards(map: &Map, position: Position, goal: Position) -> Option<(i32, i32)> {
+    let path = astar_path_fourwaygrid(map, position.into(), goal.into())?;
+    let &(x, y) = path.get(1)?;
+    if map.is_blocked((x, y).into()) {
+        return None;
+    }
+    Some((x - position.x, y - position.y))
+}
+
+/// The eight tiles around a position, in reading order.
+fn neighbors(position: Position) -> impl Iterator<Item = Position> {
+    (-1..=1)
+        .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+        .filter(|&(dx, dy)| dx != 0 || dy != 0)
+        .map(move |(dx, dy)| Position::new(position.x + dx, position.y + dy))
+}
+
+/// The step taking the monster furthest from what threatens it, preferably out of the player's sight.
+/// None if no step gets the monster any further.
+fn flee(map: &Map, position: Position, threat: Position) -> Option<(i32, i32)> {
+    let mut best_distance = position.distance_to(threat);
+    let mut best_hidden = false;
+    let mut step = None;
+
+    for &(dx, dy) in &[(0, -1), (1, 0), (0, 1), (-1, 0)] {
+        let (x, y) = (position.x + dx, position.y + dy);
+        if !map.is_walkable(x, y) || map.is_blocked((x, y).into()) {
+            continue;
+        }
+
+        let distance = Position::new(x, y).distance_to(threat);
+        let hidden = !map.is_in_player_fov(x, y);
+        if distance > position.distance_to(threat)
+            && (hidden, distance) > (best_hidden, best_distance)
+        {
+            best_distance = distance;
+            best_hidden = hidden;
+            step = Some((dx, dy));
+        }
+    }
+
+    step
+}
+
+/// A random step, most of the time in the same direction as the last one,
+/// so that wandering looks like patrolling rather than jittering around.
+fn wander<R: Rng>(
+    map: &Map,
+    position: Position,
+    last_move: (i32, i32),
+    rng: &mut R,
+) -> Option<(i32, i32)> {
+    let is_free = |(dx, dy): (i32, i32)| {
+        let (x, y) = (position.x + dx, position.y + dy);
+        map.is_walkable(x, y) && !map.is_blocked((x, y).into())
+    };
+
+    if last_move != (0, 0) && is_free(last_move) && rng.gen::<f32>() < WANDER_MOMENTUM {
+        return Some(last_move);
+    }
+
+    let steps: Vec<(i32, i32)> = [(0, -1), (1, 0), (0, 1), (-1, 0)]
+        .iter()
+        .cloned()
+        .filter(|&step| is_free(step))
+        .collect();
+    if steps.is_empty() {
+        None
+    } else {
+        Some(steps[rng.gen_range(0, steps.len())])
+    }
+}
+
+/// The step towards the loudest neighbooring tile, if the noise here is loud enough to be heard.
+fn follow_noise(map: &Map, position: Position, threshold: i32) -> Option<(i32, i32)> {
+    let mut loudest = map.noise_at(position);
+    if loudest < threshold {
+        return None;
+    }
+
+    let mut step = None;
+    for &(dx, dy) in &[(0, -1), (1, 0), (0, 1), (-1, 0)] {
+        let neighboor = Position::new(position.x + dx, position.y + dy);
+        let noise = map.noise_at(neighboor);
+        if noise > loudest && !map.is_blocked(neighboor) {
+            loudest = noise;
+            step = Some((dx, dy));
+        }
+    }
+
+    step
+}
+
+#[system(for_each)]
+pub fn compute_fov(
+    coordinates: &Position,
+    field_of_view: &mut FieldOfView,
+    #[resource] map: &Map,
+    #[resource] shared_info: &SharedInfo,
+) {
+    // Stays dirty until the player comes close enough for it to matter.
+    let is_idle = coordinates.chebyshev_distance_to(shared_info.player_position)
+        > field_of_view.radius + IDLE_MARGIN;
+    if field_of_view.dirty && !is_idle {
+        // Better blind than a crash, should a monster ever end up out of the map.
+        field_of_view.visible =
+            torchbearer::fov::try_field_of_view(map, (*coordinates).into(), field_of_view.radius)
+                .unwrap_or_default();
+        field_of_view.dirty = false;
+    }
 }
 
 #[system]
@@ -89,15 +606,15 @@ pub fn update_map_and_position(
     #[resource] map: &mut Map,
     #[resource] shared_info: &mut SharedInfo,
 ) {
-    for (index, tile) in map.tiles.iter().enumerate() {
-        map.blocked[index] = tile.blocking;
-    }
+    map.blocked = map
+        .iter_tiles()
+        .map(|(_, tile)| tile.blocks_movement())
+        .collect();
 
     let mut body_query = <(&Body, &Position)>::query();
     for (body, coordinates) in body_query.iter_mut(world) {
         if body.blocking {
-            let index = map.index(*coordinates);
-            map.blocked[index] = true;
+            map.set_blocked(*coordinates, true);
         }
     }
     let mut player_query = <&Position>::query().filter(component::<Player>());
@@ -105,14 +622,34 @@ pub fn update_map_and_position(
     shared_info.player_position = *player_coordinates;
 }
 
+/// Let the light sources shine on the map, when something changed.
+#[system]
+#[read_component(Position)]
+#[read_component(LightSource)]
+pub fn lighting(world: &mut SubWorld, #[resource] map: &mut Map) {
+    if !map.lighting_dirty {
+        return;
+    }
+
+    map.clear_ambient_light();
+    for (position, light_source) in <(&Position, &LightSource)>::query().iter(world) {
+        map.add_light(*position, light_source.radius, light_source.color);
+    }
+    map.lighting_dirty = false;
+    // What the player can see depends on the light.
+    map.transparency_changed = true;
+}
+
 #[system(for_each)]
 #[write_component(Position)]
+#[write_component(FieldOfView)]
 pub fn move_actions(
     cmd: &mut CommandBuffer,
     world: &mut SubWorld,
     move_action: &MoveAction,
     entity: &Entity,
     #[resource] map: &mut Map,
+    #[resource] shared_info: &mut SharedInfo,
 ) {
     let mut query = <&mut Position>::query();
 
@@ -124,14 +661,26 @@ pub fn move_actions(
             coordinates.y + move_action.dy,
         )
             .into();
-        if !map.is_blocked(new_position) {
+        // Nobody walks off the edge of the world.
+        if map.can_step(old_position, move_action.dx, move_action.dy) {
             coordinates.set_position(&new_position);
+            cmd.add_component(
+                move_action.entity,
+                Animation::slide(old_position, new_position),
+            );
             // Update map of blocked. It can seem useless but if not for that code,
             // the next entity might try to also move on the same tile.
-            let old_index = map.index(old_position);
-            let new_index = map.index(new_position);
-            map.blocked[old_index] = false;
-            map.blocked[new_index] = true;
+            map.set_blocked(old_position, false);
+            map.set_blocked(new_position, true);
+            if move_action.entity == shared_info.player_entity {
+                shared_info.player_moved = true;
+            }
+
+            if let Ok(field_of_view) =
+                <&mut FieldOfView>::query().get_mut(world, move_action.entity)
+            {
+                field_of_view.dirty = true;
+            }
         }
     }
 
@@ -142,15 +691,32 @@ pub fn move_actions(
 #[read_component(Body)]
 #[write_component(CombatStats)]
 #[write_component(SuffersDamage)]
+#[write_component(Position)]
+#[write_component(FieldOfView)]
+#[read_component(Faction)]
+#[write_component(Monster)]
 pub fn attack_actions(
     cmd: &mut CommandBuffer,
     world: &mut SubWorld,
     move_action: &AttackAction,
     entity: &Entity,
     #[resource] journal: &mut Journal,
+    #[resource] map: &mut Map,
+    #[resource] shared_info: &SharedInfo,
 ) {
     cmd.remove_component::<AttackAction>(*entity);
 
+    if *entity == shared_info.player_entity {
+        // Fighting is loud, anything around might come and check.
+        if let Ok(position) = <&Position>::query().get(world, *entity) {
+            map.make_noise(*position, MELEE_NOISE);
+        }
+        // No hiding from a monster that was just hit.
+        if let Ok(monster) = <&mut Monster>::query().get_mut(world, move_action.target_entity) {
+            monster.awareness = MAX_AWARENESS;
+        }
+    }
+
     let attacker = <(&Body, &CombatStats)>::query().get(world, *entity);
     if attacker.is_err() {
         return;
@@ -159,33 +725,129 @@ pub fn attack_actions(
 
     let attacker_name = attacker_body.name.clone();
     let attacker_attack = attacker_stats.attack;
+    let attacker_knockback = attacker_stats.knockback;
 
     let target = <(&Body, &CombatStats)>::query().get(world, move_action.target_entity);
     if target.is_err() {
         return;
     }
     let (target_body, target_stats): (&Body, &CombatStats) = target.unwrap();
+    let target_name = target_body.name.clone();
+
+    let from = <&Position>::query()
+        .get(world, *entity)
+        .map(|position| *position);
+    let towards = <&Position>::query()
+        .get(world, move_action.target_entity)
+        .map(|position| *position);
+    if let (Ok(from), Ok(towards)) = (from, towards) {
+        cmd.add_component(*entity, Animation::lunge(from, towards));
+    }
+
+    // What the companions do stands out in the journal.
+    let by_companion = *entity != shared_info.player_entity
+        && <&Faction>::query()
+            .get(world, *entity)
+            .map_or(false, |faction| *faction == Faction::Player);
+    let mut log = |entry: String| {
+        if by_companion {
+            journal.log_to(JournalSection::Companion, entry);
+        } else {
+            journal.log(entry);
+        }
+    };
 
-    let damage = attacker_attack - target_stats.defense;
+    let mut damage = attacker_attack - target_stats.defense;
 
     if damage > 0 {
-        journal.log(format!(
+        log(format!(
             "The {} attacks the {} for {} damage.",
-            attacker_name, target_body.name, damage
+            attacker_name, target_name, damage
         ));
+        if let Ok(&position) = <&Position>::query().get(world, move_action.target_entity) {
+            map.add_decal(position, Decal::blood());
+        }
+
+        if attacker_knockback > 0 {
+            let pushed = knock_back(
+                world,
+                map,
+                *entity,
+                move_action.target_entity,
+                attacker_knockback,
+            );
+            if pushed > 0 {
+                log(format!(
+                    "The {} shoves the {} back.",
+                    attacker_name, target_name
+                ));
+            } else {
+                // Nowhere to go, the hit lands all the harder.
+                damage += attacker_knockback;
+                log(format!(
+                    "The {} slams the {} against the wall for {} extra damage.",
+                    attacker_name, target_name, attacker_knockback
+                ));
+            }
+        }
+
         let suffers_damage = SuffersDamage {
             entity: move_action.target_entity,
             damage,
         };
         cmd.push((suffers_damage,));
     } else {
-        journal.log(format!(
+        log(format!(
             "The {} is too weak to damage the {}.",
-            attacker_name, target_body.name
+            attacker_name, target_name
         ));
     }
 }
 
+/// Push the target away from the attacker, tile by tile, until it hits something.
+/// Returns how many tiles the target actually moved.
+fn knock_back(
+    world: &mut SubWorld,
+    map: &mut Map,
+    attacker: Entity,
+    target: Entity,
+    distance: i32,
+) -> i32 {
+    let attacker_position = match <&Position>::query().get(world, attacker) {
+        Ok(position) => *position,
+        Err(_) => return 0,
+    };
+    let mut query = <&mut Position>::query();
+    let target_position = match query.get_mut(world, target) {
+        Ok(position) => position,
+        Err(_) => return 0,
+    };
+
+    let dx = (target_position.x - attacker_position.x).signum();
+    let dy = (target_position.y - attacker_position.y).signum();
+
+    let mut pushed = 0;
+    while pushed < distance {
+        if !map.can_step(*target_position, dx, dy) {
+            break;
+        }
+        let new_position = Position::new(target_position.x + dx, target_position.y + dy);
+
+        map.set_blocked(*target_position, false);
+        map.set_blocked(new_position, true);
+        target_position.set_position(&new_position);
+        pushed += 1;
+    }
+
+    if pushed > 0 {
+        if let Ok(field_of_view) = <&mut FieldOfView>::query().get_mut(world, target) {
+            field_of_view.dirty = true;
+        }
+    }
+
+    pushed
+}
+
 #[system(for_each)]
 #[write_component(CombatStats)]
 pub fn damage(
@@ -193,12 +855,24 @@ pub fn damage(
     world: &mut SubWorld,
     entity: &Entity,
     suffers_damage: &SuffersDamage,
+    #[resource] shared_info: &SharedInfo,
+    #[resource] events: &mut GameEvents,
+    #[resource] wizard: &WizardMode,
 ) {
+    cmd.remove(*entity);
+    if suffers_damage.entity == shared_info.player_entity && wizard.god_mode {
+        return;
+    }
+
     if let Ok(combat_stats) = <&mut CombatStats>::query().get_mut(world, suffers_damage.entity) {
         combat_stats.take_damage(suffers_damage.damage);
-    }
 
-    cmd.remove(*entity);
+        if suffers_damage.entity == shared_info.player_entity {
+            events.push(GameEvent::PlayerDamaged {
+                damage: suffers_damage.damage,
+            });
+        }
+    }
 }
 
 #[system(for_each)]
@@ -206,9 +880,16 @@ pub fn damage(
 #[read_component(ProvidesHealing)]
 #[read_component(Consumable)]
 #[read_component(Burst)]
-#[read_component(Position)]
 #[read_component(InflictsDamage)]
+#[read_component(SpawnsHazard)]
+#[read_component(Teleports)]
+#[read_component(SummonsCompanion)]
+#[read_component(BoostsVision)]
+#[write_component(Vision)]
+#[write_component(Position)]
+#[write_component(FieldOfView)]
 #[write_component(CombatStats)]
+#[write_component(Stack)]
 pub fn use_item(
     cmd: &mut CommandBuffer,
     world: &mut SubWorld,
@@ -216,13 +897,15 @@ pub fn use_item(
     entity: &Entity,
     #[resource] journal: &mut Journal,
     #[resource] map: &Map,
+    #[resource] game_rng: &mut GameRng,
+    #[resource] shared_info: &mut SharedInfo,
 ) {
     cmd.remove_component::<UseItemIntent>(*entity);
 
     let mut targets: Vec<Entity> = vec![];
+    let mut positions: Vec<(i32, i32)> = vec![];
     match use_item_action.target {
         Some((x, y)) => {
-            let positions: Vec<(i32, i32)>;
             let radius =
                 if let Ok(burst) = <&Burst>::query().get(world, use_item_action.item_entity) {
                     Some(burst.radius)
@@ -239,7 +922,10 @@ pub fn use_item(
                 }
             }
 
-            for (entity, coordinates) in <(Entity, &Position)>::query().iter(world) {
+            for (entity, coordinates) in <(Entity, &Position)>::query()
+                .filter(component::<Body>())
+                .iter(world)
+            {
                 if positions.contains(&(coordinates.x, coordinates.y)) {
                     targets.push(*entity);
                 }
@@ -256,6 +942,68 @@ pub fn use_item(
         journal.log(format!("The {} uses the {}", name, item_body.name));
     }
 
+    if let Ok(boost) = <&BoostsVision>::query()
+        .get(world, use_item_action.item_entity)
+        .map(|boost| boost.clone())
+    {
+        if let Ok(vision) = <&mut Vision>::query().get_mut(world, *entity) {
+            vision.boost = boost.amount;
+            vision.boost_turns = boost.turns;
+            journal.log("The light pushes the darkness back.");
+        }
+    }
+
+    if <&Teleports>::query()
+        .get(world, use_item_action.item_entity)
+        .is_ok()
+    {
+        match map.random_tile_outside_player_fov(&mut game_rng.0) {
+            Some(destination) => {
+                if let Ok(position) = <&mut Position>::query().get_mut(world, *entity) {
+                    position.set_position(&destination);
+                }
+                if *entity == shared_info.player_entity {
+                    shared_info.player_moved = true;
+                }
+                if let Ok(field_of_view) = <&mut FieldOfView>::query().get_mut(world, *entity) {
+                    field_of_view.dirty = true;
+                }
+                journal.log("You blink across the dungeon.");
+            }
+            None => {
+                // Keep the scroll, it might work somewhere else.
+                journal.log("Nothing happens.");
+                return;
+            }
+        }
+    }
+
+    if <&SummonsCompanion>::query()
+        .get(world, use_item_action.item_entity)
+        .is_ok()
+    {
+        let free_tile = <&Position>::query()
+            .get(world, *entity)
+            .ok()
+            .and_then(|&position| {
+                neighbors(position)
+                    .find(|&tile| map.is_walkable(tile.x, tile.y) && !map.is_blocked(tile))
+            });
+        match free_tile {
+            Some(Position { x, y }) => {
+                cmd.exec_mut(move |world| {
+                    spawner::companion(world, x, y);
+                });
+                journal.log_to(JournalSection::Companion, "A loyal dog answers your call.");
+            }
+            None => {
+                // No room for a companion, keep the scroll for later.
+                journal.log("Nothing happens.");
+                return;
+            }
+        }
+    }
+
     for target in targets {
         let name = <&Body>::query().get(world, target).unwrap().name.clone();
 
@@ -281,8 +1029,185 @@ pub fn use_item(
         }
     }
 
-    if let Ok(_consumable) = <&Consumable>::query().get(world, use_item_action.item_entity) {
-        cmd.remove(use_item_action.item_entity);
+    if let Ok(spawns_hazard) = <&SpawnsHazard>::query().get(world, use_item_action.item_entity) {
+        for &(x, y) in positions.iter() {
+            cmd.push((
+                Position { x, y },
+                Hazard {
+                    kind: spawns_hazard.kind,
+                    damage: spawns_hazard.damage,
+                    turns_remaining: spawns_hazard.turns,
+                },
+            ));
+        }
+    }
+
+    if <&Consumable>::query()
+        .get(world, use_item_action.item_entity)
+        .is_ok()
+    {
+        // Only the last item of a stack goes away.
+        match <&mut Stack>::query().get_mut(world, use_item_action.item_entity) {
+            Ok(stack) if stack.count > 1 => stack.count -= 1,
+            _ => cmd.remove(use_item_action.item_entity),
+        }
+    }
+}
+
+#[system(for_each)]
+pub fn fade_light(
+    vision: &mut Vision,
+    #[resource] run_state: &RunState,
+    #[resource] journal: &mut Journal,
+) {
+    if *run_state != RunState::AiTurn || vision.boost_turns <= 0 {
+        return;
+    }
+
+    vision.boost_turns -= 1;
+    if vision.boost_turns == 0 {
+        vision.boost = 0;
+        journal.log("The light fades away.");
+    }
+}
+
+#[system(for_each)]
+pub fn decay_corpses(
+    cmd: &mut CommandBuffer,
+    entity: &Entity,
+    corpse: &mut Corpse,
+    #[resource] run_state: &RunState,
+) {
+    if *run_state != RunState::AiTurn {
+        return;
+    }
+
+    corpse.turns_remaining -= 1;
+    if corpse.turns_remaining <= 0 {
+        cmd.remove(*entity);
+    }
+}
+
+#[system]
+#[read_component(Body)]
+#[read_component(Position)]
+#[read_component(CombatStats)]
+#[write_component(Hazard)]
+pub fn hazards(
+    cmd: &mut CommandBuffer,
+    world: &mut SubWorld,
+    #[resource] run_state: &RunState,
+    #[resource] journal: &mut Journal,
+    #[resource] map: &mut Map,
+) {
+    if *run_state != RunState::AiTurn {
+        return;
+    }
+
+    let mut active_hazards = vec![];
+    for (entity, position, hazard) in <(Entity, &Position, &mut Hazard)>::query().iter_mut(world) {
+        active_hazards.push((*position, hazard.kind, hazard.damage));
+        if hazard.kind == HazardKind::Fire {
+            map.add_decal(*position, Decal::scorch());
+        }
+
+        hazard.turns_remaining -= 1;
+        if hazard.turns_remaining <= 0 {
+            cmd.remove(*entity);
+        }
+    }
+
+    for (entity, position, body) in <(Entity, &Position, &Body)>::query()
+        .filter(component::<CombatStats>())
+        .iter(world)
+    {
+        for &(hazard_position, kind, damage) in active_hazards.iter() {
+            if hazard_position == *position {
+                match kind {
+                    HazardKind::Fire => {
+                        journal.log(format!("The {} burns for {} damage.", body.name, damage))
+                    }
+                    HazardKind::Gas => journal.log(format!(
+                        "The {} chokes on the gas for {} damage.",
+                        body.name, damage
+                    )),
+                }
+                cmd.push((SuffersDamage {
+                    entity: *entity,
+                    damage,
+                },));
+            }
+        }
+    }
+}
+
+/// Stop whatever the player does automatically when something needs their attention.
+/// Every multi-turn action relies on this rather than checking on its own.
+#[system]
+#[read_component(Position)]
+#[read_component(Monster)]
+#[read_component(CombatStats)]
+pub fn interrupt(
+    world: &mut SubWorld,
+    #[resource] auto_pilot: &mut AutoPilot,
+    #[resource] shared_info: &SharedInfo,
+    #[resource] map: &Map,
+    #[resource] events: &GameEvents,
+    #[resource] journal: &mut Journal,
+) {
+    if !auto_pilot.is_active() {
+        return;
+    }
+
+    let monster_sighted = <&Position>::query()
+        .filter(component::<Monster>() & component::<CombatStats>())
+        .iter(world)
+        .any(|position| map.is_in_player_fov(position.x, position.y));
+    let took_damage = events
+        .iter()
+        .any(|event| matches!(event, GameEvent::PlayerDamaged { .. }));
+    let hp_low = <&CombatStats>::query()
+        .get(world, shared_info.player_entity)
+        .map_or(false, |combat_stats| {
+            (combat_stats.hp as f32) < LOW_HP_RATIO * combat_stats.max_hp as f32
+        });
+    let reached_goal = auto_pilot.is_done()
+        || auto_pilot
+            .goal()
+            .map_or(false, |goal| goal == shared_info.player_position);
+
+    let reason = if monster_sighted {
+        InterruptReason::MonsterSighted
+    } else if took_damage {
+        InterruptReason::TookDamage
+    } else if hp_low {
+        InterruptReason::HpLow
+    } else if reached_goal {
+        InterruptReason::ReachedGoal
+    } else {
+        return;
+    };
+
+    let activity = auto_pilot.activity();
+    journal.log(match reason {
+        InterruptReason::MonsterSighted => {
+            format!("You stop {}, a monster comes into view.", activity)
+        }
+        InterruptReason::TookDamage => format!("You stop {}, you got hurt.", activity),
+        InterruptReason::HpLow => format!("You stop {}, your wounds are too serious.", activity),
+        InterruptReason::ReachedGoal => format!("You are done {}.", activity),
+    });
+    auto_pilot.interrupt(reason);
+}
+
+#[system]
+pub fn fade_noise(#[resource] map: &mut Map, #[resource] run_state: &RunState) {
+    if *run_state != RunState::AiTurn {
+        return;
+    }
+
+    for noise in map.noise_map.iter_mut() {
+        *noise = (*noise - NOISE_FADE).max(0);
     }
 }
 
@@ -292,18 +1217,34 @@ pub fn cleanup_deads(
     entity: &Entity,
     body: &mut Body,
     combat_stats: &CombatStats,
+    player: Option<&Player>,
     #[resource] journal: &mut Journal,
+    #[resource] events: &mut GameEvents,
 ) {
     if combat_stats.hp == 0 {
         // We found a cadaver!
-        journal.log(format!("The {} is dead.", body.name));
+        journal.log_to(
+            JournalSection::Combat,
+            format!("The {} is dead.", body.name),
+        );
+        events.push(GameEvent::EntityDied { entity: *entity });
 
         body.char = '%';
         body.color = DARK_RED;
         body.blocking = false;
+        body.render_priority = RENDER_PRIORITY_CORPSE;
         body.name = format!("{}'s body", body.name);
 
         cmd.remove_component::<CombatStats>(*entity);
+        // The player's body stays, for the game over screen.
+        if player.is_none() {
+            cmd.add_component(
+                *entity,
+                Corpse {
+                    turns_remaining: CORPSE_DECAY_TURNS,
+                },
+            );
+        }
     }
 }
 
@@ -316,25 +1257,68 @@ pub fn update_game_state(
 ) {
     if body.char == '%' {
         // All is lost.
-        journal.log("All is lost!!!");
+        journal.log_to(JournalSection::Story, "All is lost!!!");
         shared_info.alive = false;
     }
 }
 
+#[system(for_each)]
+#[filter(component::<QuestItem>())]
+pub fn check_victory(
+    in_inventory: &InInventory,
+    #[resource] shared_info: &SharedInfo,
+    #[resource] game_won: &mut GameWon,
+    #[resource] journal: &mut Journal,
+    #[resource] events: &mut GameEvents,
+) {
+    if in_inventory.owner == shared_info.player_entity && !game_won.0 {
+        game_won.0 = true;
+        journal.log_to(
+            JournalSection::Story,
+            "The amulet of Ambergris is yours at last. You won!",
+        );
+        events.push(GameEvent::GameWon);
+    }
+}
+
 #[system(for_each)]
 #[read_component(Body)]
+#[read_component(Consumable)]
+#[read_component(InInventory)]
+#[write_component(Stack)]
 pub fn item_collection(
     cmd: &mut CommandBuffer,
     world: &mut SubWorld,
     action: &PickupItemAction,
     entity: &Entity,
     #[resource] journal: &mut Journal,
+    #[resource] events: &mut GameEvents,
 ) {
-    let in_inventory = InInventory {
-        owner: action.collected_by,
-    };
-    cmd.add_component(action.item, in_inventory);
-    cmd.remove_component::<Position>(action.item);
+    let picked_count = <&Stack>::query()
+        .get(world, action.item)
+        .map_or(1, |stack| stack.count);
+    let existing_stack = find_stack(world, action.collected_by, action.item);
+
+    if let Some(existing_stack) = existing_stack {
+        if let Ok(stack) = <&mut Stack>::query().get_mut(world, existing_stack) {
+            stack.count += picked_count;
+        }
+        cmd.remove(action.item);
+    } else {
+        let in_inventory = InInventory {
+            owner: action.collected_by,
+        };
+        cmd.add_component(action.item, in_inventory);
+        cmd.remove_component::<Position>(action.item);
+        if <&Consumable>::query().get(world, action.item).is_ok() {
+            cmd.add_component(
+                action.item,
+                Stack {
+                    count: picked_count,
+                },
+            );
+        }
+    }
 
     let collector_name = <&Body>::query()
         .get(world, action.collected_by)
@@ -349,10 +1333,26 @@ pub fn item_collection(
             collector_name, item_name
         ));
     }
+    events.push(GameEvent::ItemPickedUp {
+        collected_by: action.collected_by,
+        item: action.item,
+    });
 
     cmd.remove(*entity);
 }
 
+/// Look in the owner's inventory for a stack of consumables identical to the item.
+fn find_stack(world: &SubWorld, owner: Entity, item: Entity) -> Option<Entity> {
+    <&Consumable>::query().get(world, item).ok()?;
+    let item_name = <&Body>::query().get(world, item).ok()?.name.clone();
+
+    <(Entity, &Body, &InInventory)>::query()
+        .filter(component::<Consumable>() & component::<Stack>())
+        .iter(world)
+        .find(|(_, body, in_inventory)| in_inventory.owner == owner && body.name == item_name)
+        .map(|(&stack_entity, _, _)| stack_entity)
+}
+
 #[system(for_each)]
 #[read_component(Body)]
 pub fn drop_item(
@@ -381,3 +1381,427 @@ pub fn drop_item(
         journal.log(format!("The {} dropped the {}", owner_name, item_body.name));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        abilities_system, check_victory_system, cleanup_deads_system, damage_system,
+        decay_corpses_system, hazards_system, interrupt_system, item_collection_system,
+        monster_action_system, move_actions_system, CORPSE_DECAY_TURNS,
+    };
+    use crate::components::{
+        AttackAction, Body, CombatStats, Corpse, Faction, FieldOfView, Hazard, HazardKind, Monster,
+        MoveAction, PickupItemAction, Stealth, SuffersDamage, AWARENESS_THRESHOLD,
+    };
+    use crate::game::{Action, Journal, RunState};
+    use crate::map::{Decal, Map, Position};
+    use crate::resources::{
+        AutoPilot, GameEvent, GameEvents, GameRng, GameWon, InterruptReason, SharedInfo, WizardMode,
+    };
+    use crate::spawner::{self, MonsterType};
+    use legion::{Entity, IntoQuery, Resources, Schedule, World};
+    use torchbearer::Map as FieldOfVisionMap;
+
+    /// Everything the systems need, on an empty 10x10 map during the AI turn, the player
+    /// standing on `position`.
+    fn test_resources(player: Entity, position: Position) -> Resources {
+        let mut resources = Resources::default();
+        resources.insert(SharedInfo {
+            player_entity: player,
+            player_position: position,
+            player_moved: false,
+            alive: true,
+        });
+        resources.insert(RunState::AiTurn);
+        resources.insert(Map::empty(10, 10));
+        resources.insert(Journal::new());
+        resources.insert(GameEvents::new());
+        resources.insert(GameWon(false));
+        resources.insert(GameRng::new(0));
+        resources.insert(WizardMode::default());
+        resources
+    }
+
+    #[test]
+    fn cannot_walk_off_the_map() {
+        let mut schedule = Schedule::builder()
+            .add_system(move_actions_system())
+            .build();
+
+        for &(x, y, dx, dy) in &[(0, 2, -1, 0), (4, 2, 1, 0), (2, 0, 0, -1), (2, 4, 0, 1)] {
+            let mut world = World::default();
+            let walker = world.push((Position::new(x, y),));
+            let mut resources = test_resources(walker, Position::new(x, y));
+            resources.insert(Map::empty(5, 5));
+            world.push((MoveAction {
+                entity: walker,
+                dx,
+                dy,
+            },));
+            schedule.execute(&mut world, &mut resources);
+
+            let position = <&Position>::query().get(&world, walker).unwrap();
+            assert_eq!(*position, Position::new(x, y));
+            assert!(!resources.get::<SharedInfo>().unwrap().player_moved);
+        }
+    }
+
+    #[test]
+    fn picking_up_the_amulet_wins_the_game() {
+        let mut schedule = Schedule::builder()
+            .add_system(item_collection_system())
+            .flush()
+            .add_system(check_victory_system())
+            .build();
+        let mut world = World::default();
+
+        let player = world.push((Position::new(1, 1),));
+        let amulet = spawner::macguffin(&mut world, 1, 1);
+        let mut resources = test_resources(player, Position::new(1, 1));
+
+        schedule.execute(&mut world, &mut resources);
+        assert!(!resources.get::<GameWon>().unwrap().0);
+
+        world.push((PickupItemAction {
+            collected_by: player,
+            item: amulet,
+        },));
+        schedule.execute(&mut world, &mut resources);
+
+        assert!(resources.get::<GameWon>().unwrap().0);
+        assert!(resources
+            .get_mut::<GameEvents>()
+            .unwrap()
+            .drain()
+            .contains(&GameEvent::GameWon));
+    }
+
+    #[test]
+    fn trolls_regenerate_during_the_ai_turn() {
+        let mut schedule = Schedule::builder().add_system(abilities_system()).build();
+        let mut world = World::default();
+
+        let player = world.push((Position::new(0, 0),));
+        spawner::monster(&mut world, MonsterType::Troll, 3, 3);
+        for (_, combat_stats) in <(&Monster, &mut CombatStats)>::query().iter_mut(&mut world) {
+            combat_stats.hp = 10;
+        }
+        let mut resources = test_resources(player, Position::new(0, 0));
+
+        let troll_hp = |world: &World| {
+            <(&Monster, &CombatStats)>::query()
+                .iter(world)
+                .map(|(_, combat_stats)| combat_stats.hp)
+                .next()
+                .unwrap()
+        };
+
+        resources.insert(RunState::PlayerTurn);
+        schedule.execute(&mut world, &mut resources);
+        assert_eq!(troll_hp(&world), 10);
+
+        resources.insert(RunState::AiTurn);
+        schedule.execute(&mut world, &mut resources);
+        assert_eq!(troll_hp(&world), 11);
+    }
+
+    #[test]
+    fn monsters_go_for_the_closest_hostile_creature() {
+        let mut schedule = Schedule::builder()
+            .add_system(monster_action_system())
+            .build();
+        let mut world = World::default();
+
+        let player = spawner::player(&mut world, 6, 2, &Default::default());
+        spawner::monster(&mut world, MonsterType::Orc, 2, 2);
+        spawner::monster(&mut world, MonsterType::Orc, 3, 2);
+        let monster_at = |world: &World, x: i32| {
+            <(Entity, &Position, &Monster)>::query()
+                .iter(world)
+                .find(|(_, position, _)| position.x == x)
+                .map(|(entity, _, _)| *entity)
+                .unwrap()
+        };
+        let (orc, pet) = (monster_at(&world, 2), monster_at(&world, 3));
+        for (entity, field_of_view) in <(Entity, &mut FieldOfView)>::query().iter_mut(&mut world) {
+            // The orc sees everything, the pet stays out of it.
+            field_of_view.visible = if *entity == orc {
+                (0..10).map(|x| (x, 2)).collect()
+            } else {
+                vec![]
+            };
+        }
+        let mut resources = test_resources(player, Position::new(6, 2));
+        let attacked = |world: &World| {
+            <&AttackAction>::query()
+                .get(world, orc)
+                .ok()
+                .map(|attack_action| attack_action.target_entity)
+        };
+
+        // Both orcs are on the same side, the player is the only target.
+        schedule.execute(&mut world, &mut resources);
+        assert_eq!(attacked(&world), None);
+
+        *<&mut Faction>::query().get_mut(&mut world, pet).unwrap() = Faction::Player;
+        schedule.execute(&mut world, &mut resources);
+        assert_eq!(attacked(&world), Some(pet));
+
+        world.entry(orc).unwrap().remove_component::<AttackAction>();
+        *<&mut Faction>::query().get_mut(&mut world, pet).unwrap() = Faction::Neutral;
+        schedule.execute(&mut world, &mut resources);
+        assert_eq!(attacked(&world), None);
+    }
+
+    #[test]
+    fn companions_catch_up_with_the_player() {
+        let mut schedule = Schedule::builder()
+            .add_system(monster_action_system())
+            .build();
+        let mut world = World::default();
+
+        let player = spawner::player(&mut world, 6, 2, &Default::default());
+        let dog = spawner::companion(&mut world, 1, 2);
+        let mut resources = test_resources(player, Position::new(6, 2));
+
+        schedule.execute(&mut world, &mut resources);
+
+        let moves: Vec<(i32, i32)> = <&MoveAction>::query()
+            .iter(&world)
+            .filter(|move_action| move_action.entity == dog)
+            .map(|move_action| (move_action.dx, move_action.dy))
+            .collect();
+        assert_eq!(moves, vec![(1, 0)]);
+    }
+
+    #[test]
+    fn stealthy_players_are_noticed_over_time() {
+        let mut schedule = Schedule::builder()
+            .add_system(monster_action_system())
+            .build();
+        let mut world = World::default();
+
+        let player = spawner::player(&mut world, 3, 2, &Default::default());
+        <&mut Stealth>::query()
+            .get_mut(&mut world, player)
+            .unwrap()
+            .level = 1;
+        spawner::monster(&mut world, MonsterType::Troll, 2, 2);
+        for field_of_view in <&mut FieldOfView>::query().iter_mut(&mut world) {
+            field_of_view.visible = (0..10).map(|x| (x, 2)).collect();
+        }
+        let mut resources = test_resources(player, Position::new(3, 2));
+        let awareness = |world: &World| <&Monster>::query().iter(world).next().unwrap().awareness;
+        let attacked = |world: &World| {
+            <&AttackAction>::query()
+                .iter(world)
+                .next()
+                .map(|attack_action| attack_action.target_entity)
+        };
+        let mut take_turn = |world: &mut World, x: i32| {
+            *<&mut Position>::query().get_mut(world, player).unwrap() = Position::new(x, 2);
+            resources.get_mut::<SharedInfo>().unwrap().player_position = Position::new(x, 2);
+            schedule.execute(world, &mut resources);
+        };
+
+        // Waiting in the dark goes unnoticed, even right next to the troll.
+        take_turn(&mut world, 3);
+        assert_eq!(awareness(&world), 0.0);
+        assert_eq!(attacked(&world), None);
+
+        // Moving around in plain sight doesn't.
+        take_turn(&mut world, 4);
+        let noticed = awareness(&world);
+        assert!(noticed > 0.0 && noticed < AWARENESS_THRESHOLD);
+
+        // Until the player hides again.
+        take_turn(&mut world, 4);
+        assert!(awareness(&world) < noticed);
+
+        let mut x = 4;
+        while awareness(&world) < AWARENESS_THRESHOLD {
+            x = 7 - x;
+            take_turn(&mut world, x);
+        }
+        if x == 4 {
+            take_turn(&mut world, 3);
+        }
+        assert_eq!(attacked(&world), Some(player));
+    }
+
+    #[test]
+    fn corpses_rot_away() {
+        let mut schedule = Schedule::builder()
+            .add_system(cleanup_deads_system())
+            .add_system(decay_corpses_system())
+            .build();
+        let mut world = World::default();
+        let mut resources = Resources::default();
+
+        spawner::monster(&mut world, MonsterType::Orc, 2, 2);
+        for combat_stats in <&mut CombatStats>::query().iter_mut(&mut world) {
+            combat_stats.hp = 0;
+        }
+        resources.insert(RunState::AiTurn);
+        resources.insert(Journal::new());
+        resources.insert(GameEvents::new());
+        let corpses = |world: &World| <&Corpse>::query().iter(world).count();
+
+        schedule.execute(&mut world, &mut resources);
+        assert_eq!(corpses(&world), 1);
+        assert_eq!(<&Body>::query().iter(&world).next().unwrap().char, '%');
+
+        // Only the turns of the monsters count.
+        resources.insert(RunState::PlayerTurn);
+        for _ in 0..CORPSE_DECAY_TURNS {
+            schedule.execute(&mut world, &mut resources);
+        }
+        assert_eq!(corpses(&world), 1);
+
+        resources.insert(RunState::AiTurn);
+        for _ in 0..CORPSE_DECAY_TURNS - 1 {
+            schedule.execute(&mut world, &mut resources);
+        }
+        assert_eq!(corpses(&world), 1);
+        schedule.execute(&mut world, &mut resources);
+        assert_eq!(<&Body>::query().iter(&world).count(), 0);
+    }
+
+    #[test]
+    fn territorial_monsters_wait_for_the_player_to_come_close() {
+        let mut schedule = Schedule::builder()
+            .add_system(monster_action_system())
+            .build();
+        let mut world = World::default();
+
+        let player = spawner::player(&mut world, 3, 3, &Default::default());
+        // Spotted right away, this is only about how close the player is.
+        world.entry(player).unwrap().remove_component::<Stealth>();
+        spawner::monster(&mut world, MonsterType::Troll, 2, 2);
+        for field_of_view in <&mut FieldOfView>::query().iter_mut(&mut world) {
+            field_of_view.visible = vec![(2, 2), (3, 3)];
+        }
+        let mut resources = test_resources(player, Position::new(3, 3));
+        let set_aggression_range = |world: &mut World, range: i32| {
+            for monster in <&mut Monster>::query().iter_mut(world) {
+                monster.aggression_range = range;
+            }
+        };
+        let attacks = |world: &World| <&AttackAction>::query().iter(world).count();
+
+        // Diagonally next to the troll is still a bit too far.
+        set_aggression_range(&mut world, 1);
+        schedule.execute(&mut world, &mut resources);
+        assert_eq!(attacks(&world), 0);
+
+        set_aggression_range(&mut world, 2);
+        schedule.execute(&mut world, &mut resources);
+        assert_eq!(attacks(&world), 1);
+    }
+
+    #[test]
+    fn fire_scorches_the_floor() {
+        let mut schedule = Schedule::builder().add_system(hazards_system()).build();
+        let mut world = World::default();
+        let mut resources = Resources::default();
+
+        world.push((
+            Position::new(1, 1),
+            Hazard {
+                kind: HazardKind::Fire,
+                damage: 1,
+                turns_remaining: 1,
+            },
+        ));
+        world.push((
+            Position::new(2, 1),
+            Hazard {
+                kind: HazardKind::Gas,
+                damage: 1,
+                turns_remaining: 1,
+            },
+        ));
+        resources.insert(RunState::AiTurn);
+        resources.insert(Journal::new());
+        resources.insert(Map::empty(5, 5));
+
+        schedule.execute(&mut world, &mut resources);
+
+        // The flames are gone, the marks stay.
+        assert_eq!(<&Hazard>::query().iter(&world).count(), 0);
+        let map = resources.get::<Map>().unwrap();
+        assert_eq!(map.decal_at(Position::new(1, 1)), Some(Decal::scorch()));
+        assert_eq!(map.decal_at(Position::new(2, 1)), None);
+        assert!(map.is_walkable(1, 1) && map.is_transparent(1, 1));
+    }
+
+    #[test]
+    fn god_mode_keeps_the_player_unharmed() {
+        let mut schedule = Schedule::builder().add_system(damage_system()).build();
+        let mut world = World::default();
+
+        let player = spawner::player(&mut world, 1, 1, &Default::default());
+        let mut resources = test_resources(player, Position::new(1, 1));
+        let player_hp = |world: &World| <&CombatStats>::query().get(world, player).unwrap().hp;
+
+        for &god_mode in &[true, false] {
+            resources.insert(WizardMode {
+                enabled: true,
+                god_mode,
+                ..WizardMode::default()
+            });
+            world.push((SuffersDamage {
+                entity: player,
+                damage: 5,
+            },));
+            schedule.execute(&mut world, &mut resources);
+        }
+
+        assert_eq!(player_hp(&world), 25);
+        assert_eq!(<&SuffersDamage>::query().iter(&world).count(), 0);
+        assert_eq!(resources.get_mut::<GameEvents>().unwrap().drain().len(), 1);
+    }
+
+    #[test]
+    fn resting_stops_when_something_happens() {
+        let mut schedule = Schedule::builder().add_system(interrupt_system()).build();
+        let mut world = World::default();
+
+        let player = spawner::player(&mut world, 1, 1, &Default::default());
+        spawner::monster(&mut world, MonsterType::Orc, 3, 3);
+        let mut resources = test_resources(player, Position::new(1, 1));
+        let mut auto_pilot = AutoPilot::new();
+        auto_pilot.start("resting", Action::Wait, None, 10);
+        resources.insert(auto_pilot);
+
+        // Nothing to see, nothing happens.
+        assert_eq!(
+            resources.get_mut::<AutoPilot>().unwrap().next_action(),
+            Some(Action::Wait)
+        );
+        schedule.execute(&mut world, &mut resources);
+        assert!(resources.get::<AutoPilot>().unwrap().is_active());
+
+        resources
+            .get_mut::<GameEvents>()
+            .unwrap()
+            .push(GameEvent::PlayerDamaged { damage: 1 });
+        schedule.execute(&mut world, &mut resources);
+        let auto_pilot = resources.get::<AutoPilot>().unwrap();
+        assert!(!auto_pilot.is_active());
+        assert_eq!(auto_pilot.interrupted(), Some(InterruptReason::TookDamage));
+        drop(auto_pilot);
+
+        resources.get_mut::<GameEvents>().unwrap().drain();
+        resources
+            .get_mut::<AutoPilot>()
+            .unwrap()
+            .start("resting", Action::Wait, None, 10);
+        resources.get_mut::<Map>().unwrap().player_fov = vec![(1, 1), (3, 3)];
+        schedule.execute(&mut world, &mut resources);
+        assert_eq!(
+            resources.get::<AutoPilot>().unwrap().interrupted(),
+            Some(InterruptReason::MonsterSighted)
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use rand::Rng;
+
+/// A list of entries to pick randomly from, each entry being picked proportionally to its weight.
+pub struct WeightedTable<T> {
+    entries: Vec<(T, u32)>,
+    total_weight: u32,
+}
+
+impl<T> WeightedTable<T> {
+    pub fn new() -> Self {
+        WeightedTable {
+            entries: vec![],
+            total_weight: 0,
+        }
+    }
+
+    /// Add an entry to the table. Entries with a weight of 0 are never picked, so they are left out.
+    pub fn add(&mut self, item: T, weight: u32) -> &mut Self {
+        if weight > 0 {
+            self.entries.push((item, weight));
+            self.total_weight += weight;
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Pick an entry at random.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the table is empty.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> &T {
+        assert!(!self.is_empty(), "Can't sample an empty table");
+
+        let mut roll = rng.gen_range(0, self.total_weight);
+        for (item, weight) in self.entries.iter() {
+            if roll < *weight {
+                return item;
+            }
+            roll -= weight;
+        }
+        unreachable!("The roll is always lower than the total weight")
+    }
+}
+
+impl<T> Default for WeightedTable<T> {
+    fn default() -> Self {
+        WeightedTable::new()
+    }
+}
+
+impl<T: Clone> From<&[(T, u32)]> for WeightedTable<T> {
+    /// Build a table from a list of `(entry, weight)`, typically a constant.
+    fn from(entries: &[(T, u32)]) -> Self {
+        let mut table = WeightedTable::new();
+        for (item, weight) in entries {
+            table.add(item.clone(), *weight);
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WeightedTable;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const SAMPLES: usize = 100_000;
+
+    #[test]
+    fn distribution_matches_weights() {
+        let weights: &[(usize, u32)] = &[(0, 10), (1, 30), (2, 60)];
+        let table = WeightedTable::from(weights);
+        let mut rng = StdRng::seed_from_u64(42);
+
+        let mut counts = [0; 3];
+        for _ in 0..SAMPLES {
+            counts[*table.sample(&mut rng)] += 1;
+        }
+
+        for &(index, weight) in weights {
+            let expected = weight as f32 / 100.0;
+            let actual = counts[index] as f32 / SAMPLES as f32;
+            assert!(
+                (expected - actual).abs() < 0.01,
+                "Entry {} was picked {} of the time instead of {}",
+                index,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn zero_weights_are_never_picked() {
+        let mut table = WeightedTable::new();
+        assert!(table.is_empty());
+
+        table.add("never", 0).add("always", 5);
+        assert!(!table.is_empty());
+
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..1000 {
+            assert_eq!(*table.sample(&mut rng), "always");
+        }
+    }
+}
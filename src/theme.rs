@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use crate::colors::{self, Color};
+use crate::palette;
+
+/// How the map and everything on it is drawn, consulted by the renderer only: switching the
+/// theme changes the looks without touching the game.
+pub struct Theme {
+    pub name: &'static str,
+    pub dark_wall: Color,
+    pub light_wall: Color,
+    pub dark_ground: Color,
+    pub light_ground: Color,
+    /// Glyph and color by body name. Bodies missing from the table keep the look they were spawned with.
+    glyphs: HashMap<&'static str, (char, Color)>,
+}
+
+impl Default for Theme {
+    /// The original looks.
+    fn default() -> Self {
+        Theme {
+            name: "classic",
+            dark_wall: palette::DARK_WALL,
+            light_wall: palette::LIGHT_WALL,
+            dark_ground: palette::DARK_GROUND,
+            light_ground: palette::LIGHT_GROUND,
+            glyphs: HashMap::new(),
+        }
+    }
+}
+
+impl Theme {
+    /// Bright glyphs on a black ground, one distinct glyph per kind of item.
+    pub fn high_contrast() -> Self {
+        let glyphs = [
+            ("player", '@', colors::YELLOW),
+            ("orc", 'o', Color::from_rgb(0x00ff00)),
+            ("troll", 'T', Color::from_rgb(0xff3030)),
+            ("orc shaman", 's', colors::MAGENTA),
+            ("dog", 'd', Color::from_rgb(0x78c8ff)),
+            ("potion", '!', Color::from_rgb(0xff6060)),
+            ("scroll of lightning bolt", '?', colors::CYAN),
+            ("scroll of fireball", '?', colors::ORANGE),
+            ("scroll of poison cloud", '?', Color::from_rgb(0x80ff80)),
+            ("scroll of teleport", '?', colors::MAGENTA),
+            ("scroll of companionship", '?', Color::from_rgb(0x78c8ff)),
+            ("torch", '/', colors::ORANGE),
+            ("amulet of Ambergris", '"', colors::YELLOW),
+            ("stairs", '>', colors::WHITE),
+            ("stairs up", '<', colors::WHITE),
+        ]
+        .iter()
+        .map(|&(name, char, color)| (name, (char, color)))
+        .collect();
+
+        Theme {
+            name: "high contrast",
+            dark_wall: Color::from_rgb(0x606060),
+            light_wall: colors::WHITE,
+            dark_ground: colors::BLACK,
+            light_ground: Color::from_rgb(0x303030),
+            glyphs,
+        }
+    }
+
+    /// The theme coming after this one, to cycle through them.
+    pub fn next(&self) -> Self {
+        if self.name == Theme::default().name {
+            Theme::high_contrast()
+        } else {
+            Theme::default()
+        }
+    }
+
+    /// How a body with this name is drawn, `char` and `color` being its own looks.
+    pub fn glyph(&self, name: &str, char: char, color: Color) -> (char, Color) {
+        self.glyphs.get(name).copied().unwrap_or((char, color))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Theme;
+    use crate::colors;
+
+    #[test]
+    fn themes_only_change_the_looks_they_know() {
+        let classic = Theme::default();
+        assert_eq!(
+            classic.glyph("potion", 'i', colors::DARK_RED),
+            ('i', colors::DARK_RED)
+        );
+
+        let high_contrast = classic.next();
+        assert_eq!(high_contrast.glyph("potion", 'i', colors::DARK_RED).0, '!');
+        assert_eq!(
+            high_contrast.glyph("orc's body", '%', colors::DARK_RED),
+            ('%', colors::DARK_RED)
+        );
+        assert_eq!(high_contrast.next().name, classic.name);
+    }
+}
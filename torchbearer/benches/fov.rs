@@ -1,7 +1,7 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use rand::{rngs::StdRng, Rng, SeedableRng};
 use tcod::Map as TcodMap;
-use torchbearer::Map;
+use torchbearer::{fov::FovMap, Grid, Map};
 
 const WIDTH: i32 = 45;
 const HEIGHT: i32 = 45;
@@ -9,28 +9,20 @@ const POSITION_X: i32 = 22;
 const POSITION_Y: i32 = 22;
 const RADIUS: i32 = 12;
 const RANDOM_WALLS: i32 = 10;
+const LARGE_SIZE: i32 = 1000;
 
 pub struct SampleMap {
-    /// Vector to store the transparent tiles.
-    transparent: Vec<bool>,
-    /// The width of the map
-    width: i32,
-    /// The height of the map
-    height: i32,
+    /// Grid to store the transparent tiles.
+    transparent: Grid<bool>,
 }
 
 impl Map for SampleMap {
     fn dimensions(&self) -> (i32, i32) {
-        (self.width, self.height)
+        self.transparent.dimensions()
     }
 
     fn is_transparent(&self, x: i32, y: i32) -> bool {
-        let index = (x + y * self.width) as usize;
-        self.transparent[index]
-    }
-
-    fn is_walkable(&self, _x: i32, _y: i32) -> bool {
-        false
+        self.transparent.get(x, y).copied().unwrap_or(false)
     }
 }
 
@@ -42,22 +34,20 @@ impl bracket_pathfinding::prelude::BaseMap for SampleMap {
 
 impl bracket_pathfinding::prelude::Algorithm2D for SampleMap {
     fn dimensions(&self) -> bracket_pathfinding::prelude::Point {
-        (self.width as i32, self.height as i32).into()
+        self.transparent.dimensions().into()
     }
 }
 
 impl SampleMap {
     pub fn new(width: i32, height: i32) -> Self {
-        if width <= 0 && height <= 0 {
-            panic!(format!(
-                "Width and height should be > 0, got ({},{})",
-                width, height
-            ));
+        if width <= 0 {
+            panic!("Width should be > 0, got {}", width);
+        }
+        if height <= 0 {
+            panic!("Height should be > 0, got {}", height);
         }
         SampleMap {
-            transparent: vec![true; (width * height) as usize],
-            width,
-            height,
+            transparent: Grid::new(width, height, true),
         }
     }
 
@@ -73,7 +63,7 @@ impl SampleMap {
 
     /// Flag a tile as transparent or visible.
     pub fn set_transparent(&mut self, x: i32, y: i32, is_transparent: bool) {
-        self.transparent[(x + y * self.width) as usize] = is_transparent;
+        self.transparent.set(x, y, is_transparent);
     }
 }
 
@@ -93,6 +83,34 @@ pub fn torchbearer_fov_random_walls(c: &mut Criterion) {
     });
 }
 
+/// Run with and without the `bitset` feature to compare the storage backends.
+pub fn torchbearer_fovmap_large_map(c: &mut Criterion) {
+    let mut map = FovMap::new(LARGE_SIZE, LARGE_SIZE);
+    let mut rng = StdRng::seed_from_u64(42);
+    for _ in 0..(LARGE_SIZE * LARGE_SIZE / 100) {
+        let (x, y) = (rng.gen_range(0, LARGE_SIZE), rng.gen_range(0, LARGE_SIZE));
+        map.set_transparent(x, y, false);
+    }
+    let backend = if cfg!(feature = "bitset") {
+        "bitset"
+    } else {
+        "bool vec"
+    };
+    println!(
+        "FovMap {}x{} ({}): {} bytes",
+        LARGE_SIZE,
+        LARGE_SIZE,
+        backend,
+        map.memory_usage()
+    );
+
+    let center = LARGE_SIZE / 2;
+    map.set_transparent(center, center, true);
+    c.bench_function("torchbearer_fovmap_large_map", |bencher| {
+        bencher.iter(|| map.calculate_fov(center, center, RADIUS));
+    });
+}
+
 pub fn tcod_fov_no_walls(c: &mut Criterion) {
     let mut map = TcodMap::new(WIDTH as i32, HEIGHT as i32);
     for x in 0..WIDTH as i32 {
@@ -164,6 +182,7 @@ criterion_group!(
     benches,
     torchbearer_fov_no_walls,
     torchbearer_fov_random_walls,
+    torchbearer_fovmap_large_map,
     tcod_fov_no_walls,
     tcod_fov_random_walls,
     bracket_fov_no_walls,
@@ -1,6 +1,17 @@
 //! Collection of utility function to calculate field of vision.
 
-use crate::{bresenham::BresenhamLine, Map, Point};
+use std::{
+    collections::{hash_map::DefaultHasher, VecDeque},
+    error::Error,
+    fmt::{Debug, Display},
+    hash::{Hash, Hasher},
+};
+
+use crate::{
+    bresenham::BresenhamLine,
+    grid::{write_ascii, BoolGrid},
+    Map, Point,
+};
 
 /// An implementation of the field of view algorithm using basic raycasting.
 /// Returns a vector containing all points visible from the starting position, including the starting position.
@@ -46,10 +57,7 @@ use crate::{bresenham::BresenhamLine, Map, Point};
 ///         self.transparent[(x + y * self.width) as usize]
 ///     }
 ///
-///     fn is_walkable(&self, x: i32, y: i32) -> bool {
-///         // field of vision only considers transparency.
-///         unreachable!("Not used in field of view.")
-///     }
+///     // Field of view only considers transparency, no need to implement `is_walkable`.
 /// }
 ///
 /// let sample_map = SampleMap::new(16, 10);
@@ -63,7 +71,365 @@ use crate::{bresenham::BresenhamLine, Map, Point};
 ///     // (…)
 /// }
 /// ```
-pub fn field_of_view<T: Map>(map: &T, from: Point, radius: i32) -> Vec<(i32, i32)> {
+pub fn field_of_view<T: Map + ?Sized>(map: &T, from: Point, radius: i32) -> Vec<(i32, i32)> {
+    let mut visibles = Vec::new();
+    field_of_view_into(map, from, radius, &mut visibles);
+    visibles
+}
+
+/// Why a field of view couldn't be calculated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FovError {
+    /// The origin `(x, y)` is out of the map, which is `width` by `height` tiles.
+    OutOfBounds {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
+    /// The radius is negative.
+    NegativeRadius(i32),
+}
+
+impl Display for FovError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FovError::OutOfBounds {
+                x,
+                y,
+                width,
+                height,
+            } => write!(
+                f,
+                "(x, y) should be between (0,0) and ({}, {}), got ({}, {})",
+                width, height, x, y
+            ),
+            FovError::NegativeRadius(radius) => {
+                write!(f, "A radius >= 0 is required, you used {}", radius)
+            }
+        }
+    }
+}
+
+impl Error for FovError {}
+
+/// Same as [`field_of_view`], but returns an error instead of panicking when the origin is out of
+/// the map or the radius is negative. Handy when the origin comes from somewhere that can't be trusted.
+///
+/// # Examples
+/// ```
+/// # use torchbearer::Map;
+/// use torchbearer::fov::{try_field_of_view, FovError};
+///
+/// # struct SampleMap;
+/// # impl Map for SampleMap {
+/// #     fn dimensions(&self) -> (i32, i32) {
+/// #         (16, 10)
+/// #     }
+/// #     fn is_transparent(&self, _x: i32, _y: i32) -> bool {
+/// #         true
+/// #     }
+/// # }
+/// let sample_map = SampleMap;
+///
+/// assert!(try_field_of_view(&sample_map, (1, 1), 5).is_ok());
+/// assert_eq!(
+///     try_field_of_view(&sample_map, (16, 1), 5),
+///     Err(FovError::OutOfBounds { x: 16, y: 1, width: 16, height: 10 })
+/// );
+/// ```
+pub fn try_field_of_view<T: Map + ?Sized>(
+    map: &T,
+    from: Point,
+    radius: i32,
+) -> Result<Vec<(i32, i32)>, FovError> {
+    check_fov_arguments(map, from, radius)?;
+    Ok(field_of_view(map, from, radius))
+}
+
+/// Same as [`field_of_view`], but writes the visible positions into a caller-provided buffer,
+/// so that a buffer can be reused between calls instead of allocating a new vector each time.
+///
+/// The buffer is cleared before being filled.
+///
+/// # Examples
+/// ```
+/// # use torchbearer::Map;
+/// use torchbearer::fov::field_of_view_into;
+///
+/// # struct SampleMap;
+/// # impl Map for SampleMap {
+/// #     fn dimensions(&self) -> (i32, i32) {
+/// #         (16, 10)
+/// #     }
+/// #     fn is_transparent(&self, _x: i32, _y: i32) -> bool {
+/// #         true
+/// #     }
+/// # }
+/// let sample_map = SampleMap;
+/// let mut visible_positions = Vec::new();
+///
+/// for turn in 0..3 {
+///     field_of_view_into(&sample_map, (1 + turn, 1), 5, &mut visible_positions);
+///     assert!(visible_positions.contains(&(1 + turn, 1)));
+/// }
+/// ```
+pub fn field_of_view_into<T: Map + ?Sized>(
+    map: &T,
+    from: Point,
+    radius: i32,
+    out: &mut Vec<Point>,
+) {
+    out.clear();
+    out.extend(raycast_vision(map, from, radius, FovOptions::default()).points());
+}
+
+/// The field of view from each of the origins, in the same order, to compare vantage points.
+/// Same as calling [`field_of_view`] for each of them, but the buffers are reused, and the origins
+/// are spread over several threads with the `rayon` feature.
+///
+/// # Panics
+///
+/// Like [`field_of_view`], if an origin is out of the map or the radius is negative.
+///
+/// # Examples
+/// ```
+/// # use torchbearer::Map;
+/// use torchbearer::fov::{field_of_view, field_of_view_multi};
+///
+/// # struct SampleMap;
+/// # impl Map for SampleMap {
+/// #     fn dimensions(&self) -> (i32, i32) {
+/// #         (16, 10)
+/// #     }
+/// #     fn is_transparent(&self, x: i32, _y: i32) -> bool {
+/// #         x != 8
+/// #     }
+/// # }
+/// let sample_map = SampleMap;
+/// let posts = [(2, 5), (8, 5), (12, 5)];
+///
+/// let views = field_of_view_multi(&sample_map, &posts, 6);
+/// assert_eq!(views.len(), posts.len());
+/// assert_eq!(views[2], field_of_view(&sample_map, (12, 5), 6));
+/// ```
+#[cfg(not(feature = "rayon"))]
+pub fn field_of_view_multi<T: Map + ?Sized>(
+    map: &T,
+    origins: &[Point],
+    radius: i32,
+) -> Vec<Vec<Point>> {
+    let mut buffer = Vec::new();
+    origins
+        .iter()
+        .map(|&origin| {
+            let vision = raycast_vision_in(
+                map,
+                origin,
+                radius,
+                FovOptions::default(),
+                std::mem::take(&mut buffer),
+            );
+            let (points, visibles) = vision.collect_points();
+            buffer = visibles;
+            points
+        })
+        .collect()
+}
+
+/// The field of view from each of the origins, in the same order, to compare vantage points.
+/// Same as calling [`field_of_view`] for each of them, but the buffers are reused, and the origins
+/// are spread over several threads.
+///
+/// # Panics
+///
+/// Like [`field_of_view`], if an origin is out of the map or the radius is negative.
+#[cfg(feature = "rayon")]
+pub fn field_of_view_multi<T: Map + Sync + ?Sized>(
+    map: &T,
+    origins: &[Point],
+    radius: i32,
+) -> Vec<Vec<Point>> {
+    use rayon::prelude::*;
+
+    origins
+        .par_iter()
+        .map_init(Vec::new, |buffer, &origin| {
+            let vision = raycast_vision_in(
+                map,
+                origin,
+                radius,
+                FovOptions::default(),
+                std::mem::take(buffer),
+            );
+            let (points, visibles) = vision.collect_points();
+            *buffer = visibles;
+            points
+        })
+        .collect()
+}
+
+/// Which walls next to the visible floors get revealed, after the rays are cast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WallReveal {
+    /// Reveal the walls next to any visible floor. Looks nice on the walls of rooms, but can light up
+    /// the backside of pillars.
+    #[default]
+    Adjacent,
+    /// Only reveal the walls when the floor revealing them lies between them and the origin.
+    TowardsOrigin,
+}
+
+/// Fine tuning of the raycasting field of view. The defaults match [`field_of_view`].
+///
+/// Diagonal gaps, where a ray squeezes between two walls touching by their corners, can be treated
+/// independently from how the creatures move, giving four combinations:
+///
+/// * Sight and movement both go through: a classic, open feel.
+/// * Sight goes through but movement doesn't: arrow slits, you can see but not squeeze by.
+/// * Movement goes through but sight doesn't: tight, claustrophobic corners.
+/// * Both are blocked: diagonal walls behave like solid ones.
+///
+/// Movement is up to the game, only the sight side is handled here, with `diagonal_walls_block`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FovOptions {
+    pub wall_reveal: WallReveal,
+    /// Whether a ray stops when going diagonally between two walls. Off by default.
+    pub diagonal_walls_block: bool,
+}
+
+/// Same as [`field_of_view`], with some fine tuning.
+///
+/// # Examples
+/// ```
+/// # use torchbearer::Map;
+/// use torchbearer::fov::{field_of_view_with_options, FovOptions, WallReveal};
+///
+/// # struct SampleMap;
+/// # impl Map for SampleMap {
+/// #     fn dimensions(&self) -> (i32, i32) {
+/// #         (16, 10)
+/// #     }
+/// #     fn is_transparent(&self, x: i32, y: i32) -> bool {
+/// #         (x, y) != (4, 3)
+/// #     }
+/// # }
+/// let sample_map = SampleMap;
+/// let options = FovOptions {
+///     wall_reveal: WallReveal::TowardsOrigin,
+///     diagonal_walls_block: true,
+/// };
+/// let visible_positions = field_of_view_with_options(&sample_map, (1, 1), 5, options);
+///
+/// assert!(visible_positions.contains(&(1, 1)));
+/// ```
+pub fn field_of_view_with_options<T: Map + ?Sized>(
+    map: &T,
+    from: Point,
+    radius: i32,
+    options: FovOptions,
+) -> Vec<Point> {
+    raycast_vision(map, from, radius, options)
+        .points()
+        .collect()
+}
+
+/// How many tiles are visible from `from`, the same ones [`field_of_view`] would return, without collecting them.
+///
+/// # Examples
+/// ```
+/// # use torchbearer::Map;
+/// use torchbearer::fov::{field_of_view, visible_count};
+///
+/// # struct SampleMap;
+/// # impl Map for SampleMap {
+/// #     fn dimensions(&self) -> (i32, i32) {
+/// #         (16, 10)
+/// #     }
+/// #     fn is_transparent(&self, _x: i32, _y: i32) -> bool {
+/// #         true
+/// #     }
+/// # }
+/// let sample_map = SampleMap;
+///
+/// assert_eq!(
+///     visible_count(&sample_map, (1, 1), 5),
+///     field_of_view(&sample_map, (1, 1), 5).len()
+/// );
+/// ```
+pub fn visible_count<T: Map + ?Sized>(map: &T, from: Point, radius: i32) -> usize {
+    raycast_vision(map, from, radius, FovOptions::default()).count()
+}
+
+/// The visible tiles of the area around the origin, as computed by the raycasting.
+struct Vision {
+    visibles: Vec<bool>,
+    width: i32,
+    offset: Point,
+}
+
+impl Vision {
+    fn points(self) -> impl Iterator<Item = Point> {
+        let Vision {
+            visibles,
+            width,
+            offset: (offset_x, offset_y),
+        } = self;
+        visibles
+            .into_iter()
+            .enumerate()
+            .filter_map(move |(index, visible)| {
+                if visible {
+                    Some((
+                        index as i32 % width + offset_x,
+                        index as i32 / width + offset_y,
+                    ))
+                } else {
+                    None
+                }
+            })
+    }
+
+    fn count(&self) -> usize {
+        self.visibles.iter().filter(|&&visible| visible).count()
+    }
+
+    /// Collect the visible points, giving the buffer back to be reused.
+    fn collect_points(self) -> (Vec<Point>, Vec<bool>) {
+        let (width, (offset_x, offset_y)) = (self.width, self.offset);
+        let points = self
+            .visibles
+            .iter()
+            .enumerate()
+            .filter(|(_, &visible)| visible)
+            .map(|(index, _)| {
+                (
+                    index as i32 % width + offset_x,
+                    index as i32 / width + offset_y,
+                )
+            })
+            .collect();
+        (points, self.visibles)
+    }
+}
+
+fn raycast_vision<T: Map + ?Sized>(
+    map: &T,
+    from: Point,
+    radius: i32,
+    options: FovOptions,
+) -> Vision {
+    raycast_vision_in(map, from, radius, options, Vec::new())
+}
+
+/// Same as `raycast_vision`, filling `visibles` rather than allocating a new buffer.
+fn raycast_vision_in<T: Map + ?Sized>(
+    map: &T,
+    from: Point,
+    radius: i32,
+    options: FovOptions,
+    mut visibles: Vec<bool>,
+) -> Vision {
     let (x, y) = from;
     let radius_square = radius * radius;
     assert_in_bounds(map, x, y);
@@ -72,156 +438,606 @@ pub fn field_of_view<T: Map>(map: &T, from: Point, radius: i32) -> Vec<(i32, i32
     }
 
     if radius < 1 {
-        return vec![(x, y)];
+        visibles.clear();
+        visibles.push(true);
+        return Vision {
+            visibles,
+            width: 1,
+            offset: from,
+        };
     }
 
-    let (width, height) = map.dimensions();
-
-    let minx = (x - radius).max(0);
-    let miny = (y - radius).max(0);
-    let maxx = (x + radius).min(width - 1);
-    let maxy = (y + radius).min(height - 1);
+    let (minx, miny, maxx, maxy) = fov_bounds(map, x, y, radius);
 
     if maxx - minx == 0 || maxy - miny == 0 {
-        // Well, no area to check.
-        return vec![];
+        // Well, no area to check, but the origin is always visible.
+        visibles.clear();
+        visibles.push(true);
+        return Vision {
+            visibles,
+            width: 1,
+            offset: from,
+        };
     }
 
     let (sub_width, sub_height) = (maxx - minx + 1, maxy - miny + 1);
     let (offset_x, offset_y) = (minx, miny);
-    let sub_origin = (x - offset_x, y - offset_y);
+    let context = RaycastContext {
+        map,
+        width: sub_width,
+        offset: (offset_x, offset_y),
+        origin: (x - offset_x, y - offset_y),
+        radius_square,
+        options,
+    };
 
-    let mut visibles = vec![false; (sub_width * sub_height) as usize];
+    visibles.clear();
+    visibles.resize((sub_width * sub_height) as usize, false);
     // Set origin as visible.
     visibles[(x - offset_x + (y - offset_y) * sub_width) as usize] = true;
 
     for x in minx..maxx + 1 {
-        cast_ray(
-            map,
-            &mut visibles,
-            sub_width,
-            sub_origin,
-            (x - offset_x, miny - offset_y),
-            radius_square,
-            offset_x,
-            offset_y,
-        );
-        cast_ray(
-            map,
-            &mut visibles,
-            sub_width,
-            sub_origin,
-            (x - offset_x, maxy - offset_y),
-            radius_square,
-            offset_x,
-            offset_y,
-        );
+        context.cast_ray(&mut visibles, (x - offset_x, miny - offset_y));
+        context.cast_ray(&mut visibles, (x - offset_x, maxy - offset_y));
     }
     for y in miny + 1..maxy {
-        cast_ray(
-            map,
-            &mut visibles,
-            sub_width,
-            sub_origin,
-            (minx - offset_x, y - offset_y),
-            radius_square,
-            offset_x,
-            offset_y,
-        );
-        cast_ray(
-            map,
-            &mut visibles,
-            sub_width,
-            sub_origin,
-            (maxx - offset_x, y - offset_y),
-            radius_square,
-            offset_x,
-            offset_y,
-        );
+        context.cast_ray(&mut visibles, (minx - offset_x, y - offset_y));
+        context.cast_ray(&mut visibles, (maxx - offset_x, y - offset_y));
     }
 
     // SE
-    post_process_vision(
-        map,
+    context.post_process_vision(
         &mut visibles,
-        sub_width,
-        x - offset_x + 1,
-        y - offset_y + 1,
-        maxx - offset_x,
-        maxy - offset_y,
-        -1,
-        -1,
-        offset_x,
-        offset_y,
+        (x - offset_x + 1, y - offset_y + 1),
+        (maxx - offset_x, maxy - offset_y),
+        (-1, -1),
     );
 
     // SW
-    post_process_vision(
-        map,
+    context.post_process_vision(
         &mut visibles,
-        sub_width,
-        minx - offset_x,
-        y - offset_y + 1,
-        x - offset_x - 1,
-        maxy - offset_y,
-        1,
-        -1,
-        offset_x,
-        offset_y,
+        (minx - offset_x, y - offset_y + 1),
+        (x - offset_x - 1, maxy - offset_y),
+        (1, -1),
     );
 
     // NW
-    post_process_vision(
-        map,
+    context.post_process_vision(
         &mut visibles,
-        sub_width,
-        minx - offset_x,
-        miny - offset_y,
-        x - offset_x - 1,
-        y - offset_y - 1,
-        1,
-        1,
-        offset_x,
-        offset_y,
+        (minx - offset_x, miny - offset_y),
+        (x - offset_x - 1, y - offset_y - 1),
+        (1, 1),
     );
 
     // NE
-    post_process_vision(
-        map,
+    context.post_process_vision(
         &mut visibles,
-        sub_width,
-        x - offset_x + 1,
-        miny - offset_y,
-        maxx - offset_x,
-        y - offset_y - 1,
-        -1,
-        1,
-        offset_x,
-        offset_y,
+        (x - offset_x + 1, miny - offset_y),
+        (maxx - offset_x, y - offset_y - 1),
+        (-1, 1),
     );
 
-    visibles
-        .into_iter()
-        .enumerate()
-        .filter_map(|(index, visible)| {
-            if visible {
-                Some((
-                    index as i32 % sub_width + offset_x,
-                    index as i32 / sub_width + offset_y,
-                ))
-            } else {
-                None
+    Vision {
+        visibles,
+        width: sub_width,
+        offset: (offset_x, offset_y),
+    }
+}
+
+/// A field of view algorithm, usable as a trait object so that it can be picked at runtime,
+/// for instance a different one for the player and for the monsters.
+///
+/// # Examples
+/// ```
+/// use torchbearer::fov::{FovMap, FovProvider, RaycastFov};
+///
+/// let fov_map = FovMap::new(16, 10);
+/// let provider: Box<dyn FovProvider> = Box::new(RaycastFov);
+///
+/// let visible_positions = provider.compute(&fov_map, (1, 1), 5);
+/// assert!(visible_positions.contains(&(1, 1)));
+/// ```
+pub trait FovProvider {
+    /// Returns all points visible from `origin`, including `origin`.
+    fn compute(&self, map: &dyn Map, origin: Point, radius: i32) -> Vec<Point>;
+}
+
+/// The raycasting algorithm implemented by [`field_of_view`].
+pub struct RaycastFov;
+
+impl FovProvider for RaycastFov {
+    fn compute(&self, map: &dyn Map, origin: Point, radius: i32) -> Vec<Point> {
+        field_of_view(map, origin, radius)
+    }
+}
+
+/// A ready to use map, storing which tiles are transparent and the result of the last field of view calculation.
+///
+/// Can optionally remember the last few calculations, which helps when the origin keeps going back and forth
+/// between the same positions.
+///
+/// # Examples
+/// ```
+/// use torchbearer::fov::FovMap;
+///
+/// let mut fov_map = FovMap::new(16, 10);
+/// fov_map.set_transparent(4, 2, false);
+/// fov_map.set_cache_size(8);
+///
+/// fov_map.calculate_fov(1, 1, 5);
+/// assert!(fov_map.is_in_fov(3, 1));
+/// ```
+pub struct FovMap {
+    /// Grid to store the transparent tiles, its dimensions are the map's.
+    transparent: BoolGrid,
+    /// Grid to store the computed field of vision.
+    vision: BoolGrid,
+    /// The last position where the field of view was calculated. If never calculated, initialized to (-1, -1).
+    last_origin: (i32, i32),
+    /// How many calculations to remember. 0 disables the cache.
+    cache_size: usize,
+    /// Last calculations, the most recently used first.
+    cache: VecDeque<(FovCacheKey, Vec<Point>)>,
+    /// Hash of the transparent tiles, computed lazily when caching.
+    transparency_hash: Option<u64>,
+    /// The area covered by the last calculation.
+    last_bounds: Option<(i32, i32, i32, i32)>,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+struct FovCacheKey {
+    origin: Point,
+    radius: i32,
+    transparency_hash: u64,
+}
+
+impl FovMap {
+    pub fn new(width: i32, height: i32) -> Self {
+        if width <= 0 {
+            panic!("Width should be > 0, got {}", width);
+        }
+        if height <= 0 {
+            panic!("Height should be > 0, got {}", height);
+        }
+        FovMap {
+            transparent: BoolGrid::new(width, height, true),
+            vision: BoolGrid::new(width, height, false),
+            last_origin: (-1, -1),
+            cache_size: 0,
+            cache: VecDeque::new(),
+            transparency_hash: None,
+            last_bounds: None,
+        }
+    }
+
+    /// Snapshot the transparency of any [`Map`] into a new `FovMap`.
+    /// Later changes to the source map are not reflected, use [`FovMap::set_transparent`] for those.
+    pub fn from_map<M: Map + ?Sized>(map: &M) -> Self {
+        let (width, height) = map.dimensions();
+        let mut fov_map = FovMap::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                fov_map.transparent.set(x, y, map.is_transparent(x, y));
+            }
+        }
+        fov_map
+    }
+
+    /// Flag a tile as transparent or not. Invalidates the cached calculations.
+    /// Does nothing out of the map.
+    pub fn set_transparent(&mut self, x: i32, y: i32, is_transparent: bool) {
+        self.transparent.set(x, y, is_transparent);
+        self.cache.clear();
+        self.transparency_hash = None;
+    }
+
+    /// How many field of view calculations to remember. Off (0) by default.
+    pub fn set_cache_size(&mut self, cache_size: usize) {
+        self.cache_size = cache_size;
+        self.cache.truncate(cache_size);
+    }
+
+    /// Calculate the field of view from `(x, y)`, replacing the previous one.
+    ///
+    /// # Panics
+    ///
+    /// If `(x, y)` is out of the map or the radius is negative, see [`FovMap::calculate_fov_checked`]
+    /// for a version that doesn't.
+    pub fn calculate_fov(&mut self, x: i32, y: i32, radius: i32) {
+        if let Err(error) = self.calculate_fov_checked(x, y, radius) {
+            panic!("{}", error);
+        }
+    }
+
+    /// Same as [`FovMap::calculate_fov`], but returns an error instead of panicking on bad arguments,
+    /// like an origin that went stale. On error, the previous field of view is left untouched.
+    ///
+    /// # Examples
+    /// ```
+    /// use torchbearer::fov::{FovError, FovMap};
+    ///
+    /// let mut fov_map = FovMap::new(16, 10);
+    /// fov_map.calculate_fov(1, 1, 5);
+    ///
+    /// assert_eq!(
+    ///     fov_map.calculate_fov_checked(16, 1, 5),
+    ///     Err(FovError::OutOfBounds { x: 16, y: 1, width: 16, height: 10 })
+    /// );
+    /// assert!(fov_map.is_in_fov(1, 1));
+    /// ```
+    pub fn calculate_fov_checked(&mut self, x: i32, y: i32, radius: i32) -> Result<(), FovError> {
+        check_fov_arguments(self, (x, y), radius)?;
+        self.vision.fill(false);
+
+        let visibles = if self.cache_size > 0 {
+            self.cached_field_of_view((x, y), radius)
+        } else {
+            field_of_view(self, (x, y), radius)
+        };
+
+        for (x, y) in visibles {
+            self.vision.set(x, y, true)
+        }
+        self.last_origin = (x, y);
+        self.last_bounds = Some(fov_bounds(self, x, y, radius));
+        Ok(())
+    }
+
+    /// The area the last calculation could have affected, as `(minx, miny, maxx, maxy)`, clamped to the map.
+    /// `None` if the field of view was never calculated.
+    pub fn last_fov_bounds(&self) -> Option<(i32, i32, i32, i32)> {
+        self.last_bounds
+    }
+
+    /// How many tiles were visible during the last calculation.
+    pub fn visible_count(&self) -> usize {
+        self.vision.count_true()
+    }
+
+    /// The tiles along a Bresenham ray going from `origin` in `direction`, at most `max_len` of them.
+    /// The origin itself is not part of the ray. The ray stops on the first opaque tile, which is
+    /// still yielded, or at the edge of the map.
+    ///
+    /// Lazy and allocation free, handy to check a firing lane without calculating a whole field of view.
+    ///
+    /// # Examples
+    /// ```
+    /// use torchbearer::fov::FovMap;
+    ///
+    /// let mut fov_map = FovMap::new(10, 10);
+    /// fov_map.set_transparent(4, 1, false);
+    ///
+    /// let ray: Vec<_> = fov_map.ray((1, 1), (1, 0), 8).collect();
+    /// assert_eq!(ray, vec![(2, 1), (3, 1), (4, 1)]);
+    /// ```
+    pub fn ray(
+        &self,
+        origin: Point,
+        direction: Point,
+        max_len: i32,
+    ) -> impl Iterator<Item = Point> + '_ {
+        let (dx, dy) = direction;
+        // Any point further along the direction than max_len will do, the iteration stops before.
+        let end = (origin.0 + dx * max_len, origin.1 + dy * max_len);
+        let mut blocked = false;
+
+        BresenhamLine::new(origin, end)
+            .skip(1)
+            .take(max_len.max(0) as usize)
+            .take_while(move |&(x, y)| {
+                if blocked || self.try_index(x, y).is_none() {
+                    return false;
+                }
+                blocked = !self.is_transparent(x, y);
+                true
+            })
+    }
+
+    /// Bytes used by the transparency and vision grids, to compare the storage backends.
+    pub fn memory_usage(&self) -> usize {
+        self.transparent.memory_usage() + self.vision.memory_usage()
+    }
+
+    /// Whether the tile at `(x, y)` was visible during the last calculation.
+    /// Tiles out of the map are never visible.
+    pub fn is_in_fov(&self, x: i32, y: i32) -> bool {
+        self.vision.get(x, y).unwrap_or(false)
+    }
+
+    /// The index of `(x, y)` in a row major grid the size of the map, or `None` if out of bounds.
+    /// Handy to build other grids aligned with this one.
+    pub fn try_index(&self, x: i32, y: i32) -> Option<usize> {
+        self.transparent.index(x, y)
+    }
+
+    fn cached_field_of_view(&mut self, origin: Point, radius: i32) -> Vec<Point> {
+        let transparent = &self.transparent;
+        let transparency_hash = *self.transparency_hash.get_or_insert_with(|| {
+            let mut hasher = DefaultHasher::new();
+            transparent.hash(&mut hasher);
+            hasher.finish()
+        });
+        let key = FovCacheKey {
+            origin,
+            radius,
+            transparency_hash,
+        };
+
+        if let Some(index) = self.cache.iter().position(|(cached, _)| *cached == key) {
+            let entry = self.cache.remove(index).unwrap();
+            let visibles = entry.1.clone();
+            self.cache.push_front(entry);
+            return visibles;
+        }
+
+        let visibles = field_of_view(self, origin, radius);
+        self.cache.push_front((key, visibles.clone()));
+        self.cache.truncate(self.cache_size);
+        visibles
+    }
+}
+
+impl Map for FovMap {
+    fn dimensions(&self) -> (i32, i32) {
+        self.transparent.dimensions()
+    }
+
+    fn is_transparent(&self, x: i32, y: i32) -> bool {
+        self.transparent.get(x, y).unwrap_or(false)
+    }
+}
+
+impl Debug for FovMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (width, height) = self.dimensions();
+        if width <= 0 || self.vision.dimensions() != (width, height) {
+            // Better a diagnostic than a panic in the middle of formatting.
+            return write!(
+                f,
+                "<FovMap invariant violated: {}x{} map, {} transparent tiles, {} vision tiles>",
+                width,
+                height,
+                self.transparent.len(),
+                self.vision.len()
+            );
+        }
+
+        write_ascii(f, width, height, |x, y| {
+            match (
+                (x, y) == self.last_origin,
+                self.transparent.get(x, y),
+                self.vision.get(x, y),
+            ) {
+                (true, _, _) => '*',
+                (_, Some(true), Some(true)) => ' ',
+                (_, Some(false), Some(true)) => '□',
+                _ => '?',
             }
         })
+    }
+}
+
+/// An implementation of the field of view algorithm using symmetric shadowcasting.
+/// Returns a vector containing all points visible from the starting position, including the starting position.
+///
+/// Unlike raycasting, shadowcasting is symmetric: if a floor tile can be seen from another one, the other one can be seen from it too.
+/// Implements the algorithm described in [symmetric shadowcasting](https://www.albertford.com/shadowcasting/).
+///
+/// # Arguments
+///
+/// * `map` - A struct implementing the `Map` trait.
+/// * `from` - The origin/center of the field of vision.
+/// * `radius` - How far the vision should go. Should be higher or equal to 0 (If 0, you only see yourself).
+///
+/// # Examples
+/// ```
+/// # use torchbearer::Map;
+/// use torchbearer::fov::shadowcast_field_of_view;
+///
+/// # struct SampleMap;
+/// # impl Map for SampleMap {
+/// #     fn dimensions(&self) -> (i32, i32) {
+/// #         (16, 10)
+/// #     }
+/// #     fn is_transparent(&self, _x: i32, _y: i32) -> bool {
+/// #         true
+/// #     }
+/// # }
+/// let sample_map = SampleMap;
+/// let visible_positions = shadowcast_field_of_view(&sample_map, (1, 1), 5);
+///
+/// assert!(visible_positions.contains(&(1, 1)));
+/// assert!(visible_positions.contains(&(6, 1)));
+/// ```
+pub fn shadowcast_field_of_view<T: Map + ?Sized>(
+    map: &T,
+    from: Point,
+    radius: i32,
+) -> Vec<(i32, i32)> {
+    let (x, y) = from;
+    assert_in_bounds(map, x, y);
+    if radius < 0 {
+        panic!("A radius >= 0 is required, you used {}", radius);
+    }
+
+    // Only the tiles within the radius can be seen, no need to go over the whole map.
+    let (minx, miny, maxx, maxy) = fov_bounds(map, x, y, radius);
+    let mut visibles = BoolGrid::new(maxx - minx + 1, maxy - miny + 1, false);
+    visibles.set(x - minx, y - miny, true);
+
+    let radius_square = radius * radius;
+    for &quadrant in &[
+        Quadrant::North,
+        Quadrant::East,
+        Quadrant::South,
+        Quadrant::West,
+    ] {
+        let mut rows = vec![Row {
+            depth: 1,
+            start_slope: Slope::new(-1, 1),
+            end_slope: Slope::new(1, 1),
+        }];
+
+        while let Some(mut row) = rows.pop() {
+            if row.depth > radius {
+                continue;
+            }
+
+            let mut previous_is_wall = None;
+            for col in row.min_col()..=row.max_col() {
+                let (tile_x, tile_y) = quadrant.transform(from, row.depth, col);
+                // Out of the map is as good as a wall.
+                let is_wall =
+                    is_out_of_bounds(map, tile_x, tile_y) || !map.is_transparent(tile_x, tile_y);
+
+                let (dx, dy) = (tile_x - x, tile_y - y);
+                if (is_wall || row.is_symmetric(col))
+                    && dx * dx + dy * dy <= radius_square
+                    && !is_out_of_bounds(map, tile_x, tile_y)
+                {
+                    visibles.set(tile_x - minx, tile_y - miny, true);
+                }
+
+                match previous_is_wall {
+                    Some(true) if !is_wall => row.start_slope = Slope::of(row.depth, col),
+                    Some(false) if is_wall => {
+                        let mut next_row = row.next();
+                        next_row.end_slope = Slope::of(row.depth, col);
+                        rows.push(next_row);
+                    }
+                    _ => {}
+                }
+                previous_is_wall = Some(is_wall);
+            }
+
+            if previous_is_wall == Some(false) {
+                rows.push(row.next());
+            }
+        }
+    }
+
+    visibles
+        .positions()
+        .map(|(x, y)| (x + minx, y + miny))
         .collect()
 }
 
-fn is_out_of_bounds<M: Map>(map: &M, x: i32, y: i32) -> bool {
+#[derive(Clone, Copy)]
+enum Quadrant {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Quadrant {
+    /// The map coordinates of the tile at `(depth, col)` in this quadrant, relative to `origin`.
+    fn transform(self, origin: Point, depth: i32, col: i32) -> Point {
+        let (x, y) = origin;
+        match self {
+            Quadrant::North => (x + col, y - depth),
+            Quadrant::East => (x + depth, y + col),
+            Quadrant::South => (x + col, y + depth),
+            Quadrant::West => (x - depth, y + col),
+        }
+    }
+}
+
+/// A fraction, kept as integers so that the rounding of the tiles on the edges is exact.
+#[derive(Clone, Copy)]
+struct Slope {
+    numerator: i32,
+    denominator: i32,
+}
+
+impl Slope {
+    fn new(numerator: i32, denominator: i32) -> Self {
+        Slope {
+            numerator,
+            denominator,
+        }
+    }
+
+    /// The slope of the left edge of the tile at `(depth, col)`.
+    fn of(depth: i32, col: i32) -> Self {
+        Slope::new(2 * col - 1, 2 * depth)
+    }
+}
+
+struct Row {
+    depth: i32,
+    start_slope: Slope,
+    end_slope: Slope,
+}
+
+impl Row {
+    fn min_col(&self) -> i32 {
+        // Round depth * start_slope, ties going up.
+        let Slope {
+            numerator,
+            denominator,
+        } = self.start_slope;
+        (2 * self.depth * numerator + denominator).div_euclid(2 * denominator)
+    }
+
+    fn max_col(&self) -> i32 {
+        // Round depth * end_slope, ties going down.
+        let Slope {
+            numerator,
+            denominator,
+        } = self.end_slope;
+        -(-(2 * self.depth * numerator - denominator)).div_euclid(2 * denominator)
+    }
+
+    /// Floors are only visible if their center is within the row's slopes, keeping the vision symmetric.
+    fn is_symmetric(&self, col: i32) -> bool {
+        col * self.start_slope.denominator >= self.depth * self.start_slope.numerator
+            && col * self.end_slope.denominator <= self.depth * self.end_slope.numerator
+    }
+
+    fn next(&self) -> Row {
+        Row {
+            depth: self.depth + 1,
+            start_slope: self.start_slope,
+            end_slope: self.end_slope,
+        }
+    }
+}
+
+/// The area a field of view calculation can reach, clamped to the map, as `(minx, miny, maxx, maxy)`.
+fn fov_bounds<M: Map + ?Sized>(map: &M, x: i32, y: i32, radius: i32) -> (i32, i32, i32, i32) {
+    let (width, height) = map.dimensions();
+
+    (
+        (x - radius).max(0),
+        (y - radius).max(0),
+        (x + radius).min(width - 1),
+        (y + radius).min(height - 1),
+    )
+}
+
+fn is_out_of_bounds<M: Map + ?Sized>(map: &M, x: i32, y: i32) -> bool {
     let (width, height) = map.dimensions();
     x < 0 || y < 0 || x >= width || y >= height
 }
 
-fn assert_in_bounds<M: Map>(map: &M, x: i32, y: i32) {
+fn check_fov_arguments<M: Map + ?Sized>(map: &M, from: Point, radius: i32) -> Result<(), FovError> {
+    if is_out_of_bounds(map, from.0, from.1) {
+        let (width, height) = map.dimensions();
+        Err(FovError::OutOfBounds {
+            x: from.0,
+            y: from.1,
+            width,
+            height,
+        })
+    } else if radius < 0 {
+        Err(FovError::NegativeRadius(radius))
+    } else {
+        Ok(())
+    }
+}
+
+fn assert_in_bounds<M: Map + ?Sized>(map: &M, x: i32, y: i32) {
     let (width, height) = map.dimensions();
     if is_out_of_bounds(map, x, y) {
         panic!(format!(
@@ -231,61 +1047,97 @@ fn assert_in_bounds<M: Map>(map: &M, x: i32, y: i32) {
     }
 }
 
-fn cast_ray<T: Map>(
-    map: &T,
-    visibles: &mut Vec<bool>,
+/// What the rays and the post processing of a raycasting calculation share. They work on the area
+/// around the origin, `visibles` being a row major grid of that area.
+struct RaycastContext<'a, T: Map + ?Sized> {
+    map: &'a T,
+    /// The width of the area.
     width: i32,
+    /// Where the area starts on the map.
+    offset: Point,
+    /// The origin, relative to the area.
     origin: Point,
-    destination: Point,
     radius_square: i32,
-    offset_x: i32,
-    offset_y: i32,
-) {
-    let (origin_x, origin_y) = origin;
-    let bresenham = BresenhamLine::new(origin, destination).skip(1);
-    for (x, y) in bresenham {
-        let distance_square = (x - origin_x) * (x - origin_x) + (y - origin_y) * (y - origin_y);
-        // If we are within radius.
-        if distance_square <= radius_square {
-            visibles[(x + y * width) as usize] = true;
-        }
+    options: FovOptions,
+}
 
-        if !map.is_transparent(x + offset_x, y + offset_y) {
-            return;
+impl<'a, T: Map + ?Sized> RaycastContext<'a, T> {
+    /// Whether the tile at `(x, y)`, relative to the area, is transparent.
+    fn is_transparent(&self, x: i32, y: i32) -> bool {
+        self.map
+            .is_transparent(x + self.offset.0, y + self.offset.1)
+    }
+
+    fn cast_ray(&self, visibles: &mut [bool], destination: Point) {
+        let (origin_x, origin_y) = self.origin;
+        let bresenham = BresenhamLine::new(self.origin, destination).skip(1);
+        let mut previous = self.origin;
+        for (x, y) in bresenham {
+            if self.options.diagonal_walls_block
+                && x != previous.0
+                && y != previous.1
+                && !self.is_transparent(previous.0, y)
+                && !self.is_transparent(x, previous.1)
+            {
+                // Squeezing between two walls touching by their corners.
+                return;
+            }
+            previous = (x, y);
+
+            let distance_square = (x - origin_x) * (x - origin_x) + (y - origin_y) * (y - origin_y);
+            // If we are within radius.
+            if distance_square <= self.radius_square {
+                visibles[(x + y * self.width) as usize] = true;
+            }
+
+            if !self.is_transparent(x, y) {
+                return;
+            }
         }
     }
-}
 
-fn post_process_vision<T: Map>(
-    map: &T,
-    visibles: &mut Vec<bool>,
-    width: i32,
-    minx: i32,
-    miny: i32,
-    maxx: i32,
-    maxy: i32,
-    dx: i32,
-    dy: i32,
-    offset_x: i32,
-    offset_y: i32,
-) {
-    for x in minx..=maxx {
-        for y in miny..=maxy {
-            let index = (x + y * width) as usize;
-            let is_see_through = map.is_transparent(x + offset_x, y + offset_y);
-            if !is_see_through && !visibles[index] {
-                // We check for walls that are not in vision only.
-                let neighboor_x = x + dx;
-                let neighboor_y = y + dy;
-
-                let index_0 = (neighboor_x + y * width) as usize;
-                let index_1 = (x + neighboor_y * width) as usize;
-
-                if (map.is_transparent(neighboor_x + offset_x, y + offset_y) && visibles[index_0])
-                    || (map.is_transparent(x + offset_x, neighboor_y + offset_y)
-                        && visibles[index_1])
-                {
-                    visibles[index] = true;
+    /// Reveal the walls between `min` and `max` next to a visible floor, `direction` pointing
+    /// towards the origin.
+    fn post_process_vision(
+        &self,
+        visibles: &mut [bool],
+        (minx, miny): Point,
+        (maxx, maxy): Point,
+        (dx, dy): Point,
+    ) {
+        let width = self.width;
+        let origin = self.origin;
+        for x in minx..=maxx {
+            for y in miny..=maxy {
+                let index = (x + y * width) as usize;
+                let (distance_x, distance_y) = ((x - origin.0).abs(), (y - origin.1).abs());
+                if distance_x * distance_x + distance_y * distance_y > self.radius_square {
+                    // The corners of the area are out of reach, even next to a visible floor.
+                    continue;
+                }
+                let is_see_through = self.is_transparent(x, y);
+                if !is_see_through && !visibles[index] {
+                    // We check for walls that are not in vision only.
+                    let neighboor_x = x + dx;
+                    let neighboor_y = y + dy;
+
+                    let index_0 = (neighboor_x + y * width) as usize;
+                    let index_1 = (x + neighboor_y * width) as usize;
+
+                    // The horizontal neighboor is between the wall and the origin when the wall is at least as far
+                    // horizontally as vertically, and the other way around.
+                    let (check_0, check_1) = match self.options.wall_reveal {
+                        WallReveal::Adjacent => (true, true),
+                        WallReveal::TowardsOrigin => {
+                            (distance_x >= distance_y, distance_y >= distance_x)
+                        }
+                    };
+
+                    if (check_0 && self.is_transparent(neighboor_x, y) && visibles[index_0])
+                        || (check_1 && self.is_transparent(x, neighboor_y) && visibles[index_1])
+                    {
+                        visibles[index] = true;
+                    }
                 }
             }
         }
@@ -295,9 +1147,13 @@ fn post_process_vision<T: Map>(
 #[cfg(test)]
 mod tests {
     use rand::{prelude::StdRng, Rng, SeedableRng};
-    use std::fmt::Debug;
 
-    use super::{field_of_view, Map};
+    use super::{
+        field_of_view, field_of_view_into, field_of_view_multi, field_of_view_with_options,
+        shadowcast_field_of_view, try_field_of_view, visible_count, FovError, FovMap, FovOptions,
+        WallReveal,
+    };
+    use crate::{grid::BoolGrid, sample::SampleMap, Map};
     const WIDTH: i32 = 45;
     const HEIGHT: i32 = 45;
     const POSITION_X: i32 = 22;
@@ -305,115 +1161,277 @@ mod tests {
     const RADIUS: i32 = 24;
     const RANDOM_WALLS: i32 = 10;
 
-    pub struct SampleMap {
-        /// Vector to store the transparent tiles.
-        transparent: Vec<bool>,
-        /// Vector to store the computed field of vision.
-        vision: Vec<bool>,
-        /// The width of the map
-        width: i32,
-        /// The height of the map
-        height: i32,
-        /// The last position where the field of view was calculated. If never calculated, initialized to (-1, -1).
-        last_origin: (i32, i32),
-    }
+    const PILLAR: &str = "
+...........
+...........
+...........
+.....@#....
+...........
+...........
+...........";
 
-    impl Map for SampleMap {
-        fn dimensions(&self) -> (i32, i32) {
-            (self.width, self.height)
-        }
+    const CORRIDOR: &str = "
+###########
+#.........#
+#####.#####
+#####.#####
+#####@#####
+#####.#####
+###########";
 
-        fn is_transparent(&self, x: i32, y: i32) -> bool {
-            let index = (x + y * self.width) as usize;
-            self.transparent[index]
-        }
+    const ROOM_WITH_DOOR: &str = "
+.............
+.............
+...###.###...
+...#.....#...
+...#..@..#...
+...#######...
+.............";
+
+    const PILLAR_SHADOW: &str = "
+.........
+.@.......
+...#.....
+.........
+.......#.
+.........
+.........
+.........
+.........";
 
-        fn is_walkable(&self, _x: i32, _y: i32) -> bool {
-            false
+    const DIAGONAL_GAP: &str = "
+.......
+.@#....
+.#.....
+.......
+.......";
+
+    /// Build a map from ascii art: `#` for walls, `.` for floors and `@` for the origin, which is a floor too.
+    fn parse(ascii: &str) -> (FovMap, (i32, i32)) {
+        let lines: Vec<&str> = ascii.trim().lines().collect();
+        let (width, height) = (lines[0].len() as i32, lines.len() as i32);
+        let mut fov = FovMap::new(width, height);
+        let mut origin = (0, 0);
+        for (y, line) in lines.iter().enumerate() {
+            for (x, tile) in line.chars().enumerate() {
+                let (x, y) = (x as i32, y as i32);
+                match tile {
+                    '#' => fov.set_transparent(x, y, false),
+                    '@' => origin = (x, y),
+                    _ => {}
+                }
+            }
         }
+        (fov, origin)
     }
 
-    impl SampleMap {
-        pub fn new(width: i32, height: i32) -> Self {
-            if width <= 0 && height <= 0 {
-                panic!(format!(
-                    "Width and height should be > 0, got ({},{})",
-                    width, height
-                ));
-            }
-            SampleMap {
-                transparent: vec![true; (width * height) as usize],
-                vision: vec![false; (width * height) as usize],
-                width,
-                height,
-                last_origin: (-1, -1),
+    /// Render what's visible as ascii art: `@` for the origin, `#` and `.` for visible walls and floors,
+    /// and `?` for everything out of sight.
+    fn render<F: Fn(i32, i32) -> bool>(map: &FovMap, origin: (i32, i32), is_visible: F) -> String {
+        let (width, height) = map.dimensions();
+        let mut ascii = String::new();
+        for y in 0..height {
+            ascii.push('\n');
+            for x in 0..width {
+                let tile = if (x, y) == origin {
+                    '@'
+                } else if !is_visible(x, y) {
+                    '?'
+                } else if map.is_transparent(x, y) {
+                    '.'
+                } else {
+                    '#'
+                };
+                ascii.push(tile);
             }
         }
-        /// Flag a tile as transparent or visible.
-        pub fn set_transparent(&mut self, x: i32, y: i32, is_transparent: bool) {
-            self.transparent[(x + y * self.width) as usize] = is_transparent;
-        }
+        ascii
+    }
 
-        pub fn calculate_fov(&mut self, x: i32, y: i32, radius: i32) {
-            for see in self.vision.iter_mut() {
-                *see = false;
-            }
+    fn render_field_of_view(ascii: &str, radius: i32) -> String {
+        let (map, origin) = parse(ascii);
+        let visibles = field_of_view(&map, origin, radius);
+        render(&map, origin, |x, y| visibles.contains(&(x, y)))
+    }
 
-            let visibles = field_of_view(self, (x, y), radius);
+    fn render_with_options(ascii: &str, radius: i32, options: FovOptions) -> String {
+        let (map, origin) = parse(ascii);
+        let visibles = field_of_view_with_options(&map, origin, radius, options);
+        render(&map, origin, |x, y| visibles.contains(&(x, y)))
+    }
 
-            for (x, y) in visibles {
-                self.vision[(x + y * self.width) as usize] = true
-            }
-            self.last_origin = (x, y);
-        }
+    fn render_shadowcast(ascii: &str, radius: i32) -> String {
+        let (map, origin) = parse(ascii);
+        let visibles = shadowcast_field_of_view(&map, origin, radius);
+        render(&map, origin, |x, y| visibles.contains(&(x, y)))
     }
 
-    impl Debug for SampleMap {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            let (width, _height) = self.dimensions();
+    fn render_fov_map(ascii: &str, radius: i32) -> String {
+        let (mut map, origin) = parse(ascii);
+        map.calculate_fov(origin.0, origin.1, radius);
+        render(&map, origin, |x, y| map.is_in_fov(x, y))
+    }
+
+    fn assert_golden(actual: String, golden: &str) {
+        assert_eq!(
+            actual.trim(),
+            golden.trim(),
+            "\nGot:\n{}\nExpected:\n{}\n",
+            actual.trim(),
+            golden.trim()
+        );
+    }
+
+    #[test]
+    fn golden_pillar() {
+        let raycast = "
+?........??
+?.......???
+?......????
+.....@#????
+?......????
+?.......???
+?........??";
+        assert_golden(render_field_of_view(PILLAR, 5), raycast);
+        assert_golden(render_fov_map(PILLAR, 5), raycast);
+
+        let shadowcast = "
+?.........?
+?.........?
+?.......???
+.....@#????
+?.......???
+?.........?
+?.........?";
+        assert_golden(render_shadowcast(PILLAR, 5), shadowcast);
+    }
 
-            let last_origin_index = if self.last_origin.0 >= 0 && self.last_origin.1 >= 0 {
-                Some((self.last_origin.0 + self.last_origin.1 * width) as usize)
-            } else {
-                None
+    #[test]
+    fn golden_wall_reveal() {
+        let render_wall_reveal = |ascii, radius, wall_reveal| {
+            let options = FovOptions {
+                wall_reveal,
+                ..FovOptions::default()
             };
+            render_with_options(ascii, radius, options)
+        };
 
-            let mut display_string = String::from("+");
-            display_string.push_str("-".repeat(self.width as usize).as_str());
-            display_string.push_str("+\n");
-            for index in 0..self.vision.len() {
-                if index % self.width as usize == 0 {
-                    display_string.push('|');
-                }
+        let adjacent = "
+.........
+.@.......
+...#.....
+....??...
+.....??#.
+......???
+.......??
+.......??
+.....????";
+        assert_golden(
+            render_wall_reveal(PILLAR_SHADOW, 8, WallReveal::Adjacent),
+            adjacent,
+        );
+        assert_golden(render_field_of_view(PILLAR_SHADOW, 8), adjacent);
 
-                let is_last_origin = if let Some(last_origin_index) = last_origin_index {
-                    last_origin_index == index
-                } else {
-                    false
-                };
-                let tile = match (is_last_origin, self.transparent[index], self.vision[index]) {
-                    (true, _, _) => '*',
-                    (_, true, true) => ' ',
-                    (_, false, true) => '□',
-                    _ => '?',
-                };
-                display_string.push(tile);
-                if index > 0 && (index + 1) % self.width as usize == 0 {
-                    display_string.push_str("|\n");
-                }
-            }
-            display_string.truncate(display_string.len() - 1);
-            display_string.push('\n');
-            display_string.push('+');
-            display_string.push_str("-".repeat(self.width as usize).as_str());
-            display_string.push('+');
+        // The wall in the shadow of the pillar is not lit up by the floor above it anymore.
+        let towards_origin = "
+.........
+.@.......
+...#.....
+....??...
+.....???.
+......???
+.......??
+.......??
+.....????";
+        assert_golden(
+            render_wall_reveal(PILLAR_SHADOW, 8, WallReveal::TowardsOrigin),
+            towards_origin,
+        );
 
-            write!(f, "{}", display_string)
-        }
+        // Walls of a room are still revealed.
+        assert_golden(
+            render_wall_reveal(ROOM_WITH_DOOR, 5, WallReveal::TowardsOrigin),
+            &render_field_of_view(ROOM_WITH_DOOR, 5),
+        );
     }
+
+    #[test]
+    fn golden_diagonal_gap() {
+        let open = "
+...????
+.@#????
+.#.????
+???.???
+????.??";
+        assert_golden(
+            render_with_options(DIAGONAL_GAP, 6, FovOptions::default()),
+            open,
+        );
+
+        let blocked = "
+...????
+.@#????
+.#?????
+???????
+???????";
+        let options = FovOptions {
+            diagonal_walls_block: true,
+            ..FovOptions::default()
+        };
+        assert_golden(render_with_options(DIAGONAL_GAP, 6, options), blocked);
+    }
+
+    #[test]
+    fn golden_corridor() {
+        let golden = "
+????###????
+?????.?????
+????#.#????
+????#.#????
+????#@#????
+????#.#????
+????###????";
+        assert_golden(render_field_of_view(CORRIDOR, 5), golden);
+        assert_golden(render_fov_map(CORRIDOR, 5), golden);
+        assert_golden(render_shadowcast(CORRIDOR, 5), golden);
+    }
+
+    #[test]
+    fn golden_room_with_door() {
+        let golden = "
+?????...?????
+??????.??????
+???###.###???
+???#.....#???
+???#..@..#???
+???#######???
+?????????????";
+        assert_golden(render_field_of_view(ROOM_WITH_DOOR, 5), golden);
+        assert_golden(render_fov_map(ROOM_WITH_DOOR, 5), golden);
+        assert_golden(render_shadowcast(ROOM_WITH_DOOR, 5), golden);
+    }
+
+    #[test]
+    #[should_panic(expected = "Height should be > 0, got 0")]
+    fn fov_map_rejects_a_zero_height() {
+        FovMap::new(10, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Width should be > 0, got 0")]
+    fn fov_map_rejects_a_zero_width() {
+        FovMap::new(0, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "Width should be > 0, got -1")]
+    fn fov_map_rejects_a_negative_width() {
+        FovMap::new(-1, 5);
+    }
+
     #[test]
     fn fov_with_sample_map() {
-        let mut fov = SampleMap::new(10, 10);
+        let mut fov = FovMap::new(10, 10);
         for x in 1..10 {
             fov.set_transparent(x, 3, false);
         }
@@ -427,14 +1445,14 @@ mod tests {
 
     #[test]
     fn fov_to_vector() {
-        let mut fov = SampleMap::new(WIDTH, HEIGHT);
+        let mut fov = FovMap::new(WIDTH, HEIGHT);
 
         fov.calculate_fov(POSITION_X, POSITION_Y, RADIUS);
     }
 
     #[test]
     fn fov_with_wall_to_vector() {
-        let mut fov = SampleMap::new(WIDTH, HEIGHT);
+        let mut fov = FovMap::new(WIDTH, HEIGHT);
         let mut rng = StdRng::seed_from_u64(42);
         for _ in 0..RANDOM_WALLS {
             let (x, y) = (rng.gen_range(0, WIDTH), rng.gen_range(0, HEIGHT));
@@ -446,4 +1464,321 @@ mod tests {
 
         println!("{:?}", fov);
     }
+
+    #[test]
+    fn field_of_view_into_clears_the_buffer() {
+        let mut fov = FovMap::new(WIDTH, HEIGHT);
+        fov.set_transparent(POSITION_X + 1, POSITION_Y, false);
+
+        let mut visibles = vec![(-1, -1)];
+        field_of_view_into(&fov, (POSITION_X, POSITION_Y), RADIUS, &mut visibles);
+        assert_eq!(
+            visibles,
+            field_of_view(&fov, (POSITION_X, POSITION_Y), RADIUS)
+        );
+
+        field_of_view_into(&fov, (POSITION_X, POSITION_Y), 0, &mut visibles);
+        assert_eq!(visibles, vec![(POSITION_X, POSITION_Y)]);
+    }
+
+    #[test]
+    fn shadowcast_hides_behind_pillars() {
+        let mut fov = FovMap::new(11, 11);
+        fov.set_transparent(6, 5, false);
+
+        let visibles = shadowcast_field_of_view(&fov, (5, 5), 5);
+        assert!(visibles.contains(&(5, 5)));
+        assert!(visibles.contains(&(6, 5)));
+        assert!(!visibles.contains(&(7, 5)));
+        assert!(!visibles.contains(&(9, 5)));
+        assert!(visibles.contains(&(6, 4)));
+        assert!(visibles.contains(&(9, 2)));
+        // Out of reach.
+        assert!(!visibles.contains(&(9, 1)));
+    }
+
+    #[test]
+    fn shadowcast_is_symmetric() {
+        let mut fov = FovMap::new(WIDTH, HEIGHT);
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..RANDOM_WALLS * 20 {
+            let (x, y) = (rng.gen_range(0, WIDTH), rng.gen_range(0, HEIGHT));
+            fov.set_transparent(x, y, false);
+        }
+        fov.set_transparent(POSITION_X, POSITION_Y, true);
+
+        for (x, y) in shadowcast_field_of_view(&fov, (POSITION_X, POSITION_Y), RADIUS) {
+            if fov.is_transparent(x, y) {
+                let seen_from = shadowcast_field_of_view(&fov, (x, y), RADIUS);
+                assert!(seen_from.contains(&(POSITION_X, POSITION_Y)));
+            }
+        }
+    }
+
+    #[test]
+    fn cached_fov_matches_calculated_fov() {
+        let mut cached = FovMap::new(WIDTH, HEIGHT);
+        let mut uncached = FovMap::new(WIDTH, HEIGHT);
+        cached.set_cache_size(2);
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..RANDOM_WALLS {
+            let (x, y) = (rng.gen_range(0, WIDTH), rng.gen_range(0, HEIGHT));
+            cached.set_transparent(x, y, false);
+            uncached.set_transparent(x, y, false);
+        }
+
+        for &(x, y) in &[(10, 10), (11, 10), (10, 10), (11, 10)] {
+            cached.calculate_fov(x, y, RADIUS);
+            uncached.calculate_fov(x, y, RADIUS);
+            assert_eq!(cached.vision, uncached.vision);
+        }
+        assert_eq!(cached.cache.len(), 2);
+
+        cached.set_transparent(12, 10, false);
+        uncached.set_transparent(12, 10, false);
+        assert!(cached.cache.is_empty());
+
+        cached.calculate_fov(10, 10, RADIUS);
+        uncached.calculate_fov(10, 10, RADIUS);
+        assert_eq!(cached.vision, uncached.vision);
+    }
+
+    #[test]
+    fn from_map_copies_transparency() {
+        let mut original = FovMap::new(WIDTH, HEIGHT);
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..RANDOM_WALLS {
+            let (x, y) = (rng.gen_range(0, WIDTH), rng.gen_range(0, HEIGHT));
+            original.set_transparent(x, y, false);
+        }
+
+        let mut copy = FovMap::from_map(&original);
+        assert_eq!(copy.transparent, original.transparent);
+
+        original.calculate_fov(POSITION_X, POSITION_Y, RADIUS);
+        copy.calculate_fov(POSITION_X, POSITION_Y, RADIUS);
+        assert_eq!(copy.vision, original.vision);
+    }
+
+    #[test]
+    fn try_index_is_bounds_checked() {
+        let mut fov = FovMap::new(WIDTH, HEIGHT);
+        assert_eq!(fov.try_index(0, 0), Some(0));
+        assert_eq!(fov.try_index(2, 1), Some(2 + WIDTH as usize));
+        assert_eq!(fov.try_index(-1, 0), None);
+        assert_eq!(fov.try_index(0, HEIGHT), None);
+
+        fov.calculate_fov(POSITION_X, POSITION_Y, RADIUS);
+        assert!(!fov.is_in_fov(WIDTH, 0));
+    }
+
+    #[test]
+    fn set_transparent_ignores_tiles_out_of_the_map() {
+        let mut fov = FovMap::new(WIDTH, HEIGHT);
+        fov.set_transparent(WIDTH, 0, false);
+        fov.set_transparent(-1, 1, false);
+        fov.set_transparent(0, HEIGHT, false);
+
+        // Past the end of a row isn't the start of the next one.
+        assert!(fov.is_transparent(0, 1));
+        assert!(fov.is_transparent(WIDTH - 1, 0));
+        assert_eq!(fov.transparent, BoolGrid::new(WIDTH, HEIGHT, true));
+    }
+
+    #[test]
+    fn debug_reports_mismatched_grids() {
+        let mut fov = FovMap::new(4, 4);
+        fov.vision = BoolGrid::new(3, 1, false);
+
+        let debug = format!("{:?}", fov);
+        assert_eq!(
+            debug,
+            "<FovMap invariant violated: 4x4 map, 16 transparent tiles, 3 vision tiles>"
+        );
+    }
+
+    #[test]
+    fn last_fov_bounds_are_clamped() {
+        let mut fov = FovMap::new(WIDTH, HEIGHT);
+        assert_eq!(fov.last_fov_bounds(), None);
+
+        fov.calculate_fov(2, 40, 5);
+        assert_eq!(fov.last_fov_bounds(), Some((0, 35, 7, 44)));
+    }
+
+    #[test]
+    fn origin_is_visible_when_the_area_collapses_to_a_line() {
+        let fov = FovMap::new(WIDTH, 1);
+        assert_eq!(field_of_view(&fov, (0, 0), RADIUS), vec![(0, 0)]);
+        assert_eq!(visible_count(&fov, (WIDTH - 1, 0), RADIUS), 1);
+    }
+
+    #[test]
+    fn origin_is_always_visible_on_an_open_map() {
+        let fov = FovMap::new(WIDTH, HEIGHT);
+        for radius in 0..=2 {
+            for &origin in &[(0, 0), (POSITION_X, POSITION_Y), (WIDTH - 1, HEIGHT - 1)] {
+                assert!(
+                    field_of_view(&fov, origin, radius).contains(&origin),
+                    "{:?} with radius {}",
+                    origin,
+                    radius
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn try_field_of_view_reports_bad_arguments() {
+        let fov = FovMap::new(WIDTH, HEIGHT);
+
+        assert_eq!(
+            try_field_of_view(&fov, (POSITION_X, POSITION_Y), RADIUS),
+            Ok(field_of_view(&fov, (POSITION_X, POSITION_Y), RADIUS))
+        );
+        assert_eq!(
+            try_field_of_view(&fov, (-1, POSITION_Y), RADIUS),
+            Err(FovError::OutOfBounds {
+                x: -1,
+                y: POSITION_Y,
+                width: WIDTH,
+                height: HEIGHT
+            })
+        );
+        assert_eq!(
+            try_field_of_view(&fov, (POSITION_X, POSITION_Y), -1),
+            Err(FovError::NegativeRadius(-1))
+        );
+    }
+
+    #[test]
+    fn checked_calculation_keeps_the_previous_vision_on_error() {
+        let mut fov = FovMap::new(WIDTH, HEIGHT);
+        fov.calculate_fov(POSITION_X, POSITION_Y, RADIUS);
+        let visible_count = fov.visible_count();
+        let bounds = fov.last_fov_bounds();
+
+        assert_eq!(
+            fov.calculate_fov_checked(WIDTH, POSITION_Y, RADIUS),
+            Err(FovError::OutOfBounds {
+                x: WIDTH,
+                y: POSITION_Y,
+                width: WIDTH,
+                height: HEIGHT
+            })
+        );
+        assert_eq!(
+            fov.calculate_fov_checked(POSITION_X, POSITION_Y, -1),
+            Err(FovError::NegativeRadius(-1))
+        );
+        assert_eq!(fov.visible_count(), visible_count);
+        assert_eq!(fov.last_fov_bounds(), bounds);
+
+        assert_eq!(fov.calculate_fov_checked(0, 0, 0), Ok(()));
+        assert_eq!(fov.visible_count(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "A radius >= 0 is required, you used -1")]
+    fn calculate_fov_still_panics_on_bad_arguments() {
+        let mut fov = FovMap::new(WIDTH, HEIGHT);
+        fov.calculate_fov(POSITION_X, POSITION_Y, -1);
+    }
+
+    #[test]
+    fn ray_stops_on_walls_edges_and_length() {
+        let mut fov_map = FovMap::new(10, 10);
+        fov_map.set_transparent(5, 5, false);
+
+        let diagonal: Vec<_> = fov_map.ray((2, 2), (1, 1), 8).collect();
+        assert_eq!(diagonal, vec![(3, 3), (4, 4), (5, 5)]);
+
+        let to_the_edge: Vec<_> = fov_map.ray((7, 1), (1, 0), 8).collect();
+        assert_eq!(to_the_edge, vec![(8, 1), (9, 1)]);
+
+        assert_eq!(fov_map.ray((0, 0), (0, 1), 3).count(), 3);
+        assert_eq!(fov_map.ray((0, 0), (0, 0), 3).count(), 0);
+    }
+
+    #[test]
+    fn visible_count_matches_field_of_view() {
+        let mut fov = FovMap::new(WIDTH, HEIGHT);
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..200 {
+            let (x, y) = (rng.gen_range(0, WIDTH), rng.gen_range(0, HEIGHT));
+            fov.set_transparent(x, y, false);
+        }
+        fov.set_transparent(POSITION_X, POSITION_Y, true);
+
+        let visibles = field_of_view(&fov, (POSITION_X, POSITION_Y), RADIUS);
+        assert_eq!(
+            visible_count(&fov, (POSITION_X, POSITION_Y), RADIUS),
+            visibles.len()
+        );
+
+        fov.calculate_fov(POSITION_X, POSITION_Y, RADIUS);
+        assert_eq!(fov.visible_count(), visibles.len());
+        assert_eq!(visible_count(&fov, (POSITION_X, POSITION_Y), 0), 1);
+    }
+
+    #[test]
+    fn field_of_view_multi_matches_each_origin() {
+        let mut fov = FovMap::new(WIDTH, HEIGHT);
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..200 {
+            let (x, y) = (rng.gen_range(0, WIDTH), rng.gen_range(0, HEIGHT));
+            fov.set_transparent(x, y, false);
+        }
+        // Different sizes, so that a reused buffer with leftovers would show.
+        let origins = [(22, 22), (0, 0), (44, 10), (3, 40), (22, 22)];
+
+        for &radius in &[0, 5, RADIUS] {
+            let views = field_of_view_multi(&fov, &origins, radius);
+            assert_eq!(views.len(), origins.len());
+            for (view, &origin) in views.iter().zip(origins.iter()) {
+                assert_eq!(*view, field_of_view(&fov, origin, radius));
+            }
+        }
+        assert!(field_of_view_multi(&fov, &[], RADIUS).is_empty());
+    }
+
+    #[test]
+    fn nothing_is_visible_beyond_the_radius() {
+        let mut rng = StdRng::seed_from_u64(11);
+        for seed in 0..300 {
+            let (width, height) = (rng.gen_range(1, 40), rng.gen_range(1, 40));
+            let map = SampleMap::random(width, height, rng.gen_range(0.0, 0.6), seed);
+            let origin = (rng.gen_range(0, width), rng.gen_range(0, height));
+            let radius = rng.gen_range(0, 20);
+
+            let views = [
+                field_of_view(&map, origin, radius),
+                field_of_view_with_options(
+                    &map,
+                    origin,
+                    radius,
+                    FovOptions {
+                        wall_reveal: WallReveal::TowardsOrigin,
+                        diagonal_walls_block: true,
+                    },
+                ),
+                shadowcast_field_of_view(&map, origin, radius),
+            ];
+            for view in views.iter() {
+                for &(x, y) in view {
+                    let (dx, dy) = (x - origin.0, y - origin.1);
+                    assert!(
+                        dx * dx + dy * dy <= radius * radius,
+                        "{:?} seen from {:?} with a radius of {} on map {}\n{:?}",
+                        (x, y),
+                        origin,
+                        radius,
+                        seed,
+                        map
+                    );
+                    assert!(x >= 0 && y >= 0 && x < width && y < height);
+                }
+            }
+        }
+    }
 }
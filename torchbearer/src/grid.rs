@@ -0,0 +1,447 @@
+//! Grids of values, indexed by `(x, y)` positions.
+//!
+//! [`Grid`] is a general purpose grid, handy to store the tiles of a map.
+//! The boolean grids of [`FovMap`](crate::fov::FovMap) are stored in a `BoolGrid` instead,
+//! backed by a `Vec<bool>` by default, or by a packed bitset with the `bitset` feature,
+//! which uses 8 times less memory on large maps.
+
+use std::{
+    fmt::{self, Formatter},
+    ops::{Index, IndexMut},
+};
+
+use crate::Point;
+
+/// A `width * height` grid of values, stored row by row.
+///
+/// # Examples
+/// ```
+/// use torchbearer::Grid;
+///
+/// let mut grid = Grid::new(16, 10, '.');
+/// grid.set(4, 2, '#');
+///
+/// assert_eq!(grid.get(4, 2), Some(&'#'));
+/// assert_eq!(grid.get(16, 2), None);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Grid<T> {
+    width: i32,
+    height: i32,
+    values: Vec<T>,
+}
+
+impl<T: Clone> Grid<T> {
+    /// Create a grid filled with `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the width or the height isn't strictly positive.
+    pub fn new(width: i32, height: i32, value: T) -> Self {
+        if width <= 0 {
+            panic!("Width should be > 0, got {}", width);
+        }
+        if height <= 0 {
+            panic!("Height should be > 0, got {}", height);
+        }
+        Grid {
+            width,
+            height,
+            values: vec![value; (width * height) as usize],
+        }
+    }
+
+    pub fn fill(&mut self, value: T) {
+        for stored in self.values.iter_mut() {
+            *stored = value.clone();
+        }
+    }
+}
+
+impl<T> Grid<T> {
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn dimensions(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        in_bounds(self.width, self.height, x, y)
+    }
+
+    /// The index of `(x, y)` in the underlying vector, or `None` if out of bounds.
+    /// Handy to keep other grids of the same size aligned with this one.
+    pub fn index(&self, x: i32, y: i32) -> Option<usize> {
+        index_of(self.width, self.height, x, y)
+    }
+
+    /// The value at `(x, y)`, or `None` if out of bounds.
+    pub fn get(&self, x: i32, y: i32) -> Option<&T> {
+        self.index(x, y).map(|index| &self.values[index])
+    }
+
+    pub fn get_mut(&mut self, x: i32, y: i32) -> Option<&mut T> {
+        match self.index(x, y) {
+            Some(index) => Some(&mut self.values[index]),
+            None => None,
+        }
+    }
+
+    /// Replace the value at `(x, y)`. Does nothing out of bounds.
+    pub fn set(&mut self, x: i32, y: i32, value: T) {
+        if let Some(stored) = self.get_mut(x, y) {
+            *stored = value;
+        }
+    }
+
+    /// All the values, row by row, with their position.
+    pub fn iter(&self) -> impl Iterator<Item = (Point, &T)> {
+        let width = self.width;
+        self.values
+            .iter()
+            .enumerate()
+            .map(move |(index, value)| (position_of(index, width), value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Point, &mut T)> {
+        let width = self.width;
+        self.values
+            .iter_mut()
+            .enumerate()
+            .map(move |(index, value)| (position_of(index, width), value))
+    }
+
+    /// Render the grid as ASCII art in a frame, one character per value, as picked by `tile`.
+    /// Meant to implement `Debug` for maps built on top of a grid.
+    pub fn fmt_ascii<F: Fn(Point, &T) -> char>(
+        &self,
+        f: &mut Formatter<'_>,
+        tile: F,
+    ) -> fmt::Result {
+        write_ascii(f, self.width, self.height, |x, y| {
+            tile((x, y), &self.values[(x + y * self.width) as usize])
+        })
+    }
+}
+
+impl<T> Index<usize> for Grid<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        &self.values[index]
+    }
+}
+
+impl<T> IndexMut<usize> for Grid<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        &mut self.values[index]
+    }
+}
+
+fn in_bounds(width: i32, height: i32, x: i32, y: i32) -> bool {
+    x >= 0 && y >= 0 && x < width && y < height
+}
+
+/// The index of `(x, y)` in a row major `width * height` grid, or `None` if out of bounds.
+fn index_of(width: i32, height: i32, x: i32, y: i32) -> Option<usize> {
+    if in_bounds(width, height, x, y) {
+        Some((x + y * width) as usize)
+    } else {
+        None
+    }
+}
+
+fn position_of(index: usize, width: i32) -> Point {
+    let index = index as i32;
+    (index % width, index / width)
+}
+
+/// Draw a `width * height` area in a frame, with the character returned by `tile` for each position.
+pub(crate) fn write_ascii<F: Fn(i32, i32) -> char>(
+    f: &mut Formatter<'_>,
+    width: i32,
+    height: i32,
+    tile: F,
+) -> fmt::Result {
+    let border = "-".repeat(width.max(0) as usize);
+    writeln!(f, "+{}+", border)?;
+    for y in 0..height {
+        let row: String = (0..width).map(|x| tile(x, y)).collect();
+        writeln!(f, "|{}|", row)?;
+    }
+    write!(f, "+{}+", border)
+}
+
+/// A `width * height` grid of booleans, stored row by row. Like [`Grid`], positions out of bounds
+/// have no value, and setting them does nothing.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct BoolGrid {
+    width: i32,
+    height: i32,
+    storage: BoolStorage,
+}
+
+impl BoolGrid {
+    pub fn new(width: i32, height: i32, value: bool) -> Self {
+        BoolGrid {
+            width,
+            height,
+            storage: BoolStorage::new((width.max(0) * height.max(0)) as usize, value),
+        }
+    }
+
+    pub fn dimensions(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    /// The index of `(x, y)` in the grid, or `None` if out of bounds.
+    pub fn index(&self, x: i32, y: i32) -> Option<usize> {
+        index_of(self.width, self.height, x, y)
+    }
+
+    /// The value at `(x, y)`, or `None` if out of bounds.
+    pub fn get(&self, x: i32, y: i32) -> Option<bool> {
+        self.index(x, y).map(|index| self.storage.get(index))
+    }
+
+    /// Replace the value at `(x, y)`. Does nothing out of bounds.
+    pub fn set(&mut self, x: i32, y: i32, value: bool) {
+        if let Some(index) = self.index(x, y) {
+            self.storage.set(index, value);
+        }
+    }
+
+    pub fn fill(&mut self, value: bool) {
+        self.storage.fill(value);
+    }
+
+    /// How many values are set to `true`.
+    pub fn count_true(&self) -> usize {
+        self.storage.count_true()
+    }
+
+    /// Bytes used to store the values.
+    pub fn memory_usage(&self) -> usize {
+        self.storage.memory_usage()
+    }
+
+    /// The positions set to `true`, row by row.
+    pub fn positions(&self) -> impl Iterator<Item = Point> + '_ {
+        let width = self.width;
+        (0..self.len())
+            .filter(move |&index| self.storage.get(index))
+            .map(move |index| position_of(index, width))
+    }
+}
+
+/// The booleans of a [`BoolGrid`], backed by a `Vec<bool>`.
+#[cfg(not(feature = "bitset"))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BoolStorage {
+    values: Vec<bool>,
+}
+
+#[cfg(not(feature = "bitset"))]
+impl BoolStorage {
+    fn new(len: usize, value: bool) -> Self {
+        BoolStorage {
+            values: vec![value; len],
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    fn get(&self, index: usize) -> bool {
+        self.values[index]
+    }
+
+    fn set(&mut self, index: usize, value: bool) {
+        self.values[index] = value;
+    }
+
+    fn fill(&mut self, value: bool) {
+        for stored in self.values.iter_mut() {
+            *stored = value;
+        }
+    }
+
+    /// How many values are set to `true`.
+    fn count_true(&self) -> usize {
+        self.values.iter().filter(|&&value| value).count()
+    }
+
+    /// Bytes used to store the values.
+    fn memory_usage(&self) -> usize {
+        self.values.len() * std::mem::size_of::<bool>()
+    }
+}
+
+/// The booleans of a [`BoolGrid`], packed in a bitset.
+#[cfg(feature = "bitset")]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BoolStorage {
+    bits: Vec<u64>,
+    len: usize,
+}
+
+#[cfg(feature = "bitset")]
+impl BoolStorage {
+    fn new(len: usize, value: bool) -> Self {
+        let mut storage = BoolStorage {
+            bits: vec![0; len.div_ceil(64)],
+            len,
+        };
+        storage.fill(value);
+        storage
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, index: usize) -> bool {
+        assert!(index < self.len, "index {} out of {}", index, self.len);
+        self.bits[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn set(&mut self, index: usize, value: bool) {
+        assert!(index < self.len, "index {} out of {}", index, self.len);
+        if value {
+            self.bits[index / 64] |= 1 << (index % 64);
+        } else {
+            self.bits[index / 64] &= !(1 << (index % 64));
+        }
+    }
+
+    fn fill(&mut self, value: bool) {
+        let word = if value { !0 } else { 0 };
+        for bits in self.bits.iter_mut() {
+            *bits = word;
+        }
+        // Keep the unused bits cleared, so that equality and hashing only depend on the values.
+        let remainder = self.len % 64;
+        if value && remainder != 0 {
+            if let Some(last) = self.bits.last_mut() {
+                *last = (1 << remainder) - 1;
+            }
+        }
+    }
+
+    /// How many values are set to `true`. The unused bits are always cleared, so a popcount does it.
+    fn count_true(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|bits| bits.count_ones() as usize)
+            .sum()
+    }
+
+    /// Bytes used to store the values.
+    fn memory_usage(&self) -> usize {
+        self.bits.len() * std::mem::size_of::<u64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BoolGrid, Grid};
+    use std::fmt::{self, Debug, Formatter};
+
+    struct Tiles(Grid<bool>);
+
+    impl Debug for Tiles {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            self.0.fmt_ascii(f, |_, &wall| if wall { '#' } else { ' ' })
+        }
+    }
+
+    #[test]
+    fn grid_get_and_set() {
+        let mut grid = Grid::new(3, 2, 0);
+        grid.set(2, 1, 5);
+        grid.set(3, 1, 7);
+
+        assert_eq!(grid.dimensions(), (3, 2));
+        assert_eq!(grid.get(2, 1), Some(&5));
+        assert_eq!(grid.get(-1, 0), None);
+        assert_eq!(grid.index(2, 1), Some(5));
+        assert_eq!(grid[5], 5);
+        assert_eq!(grid.iter().filter(|(_, &value)| value == 0).count(), 5);
+        assert_eq!(grid.iter().nth(3), Some(((0, 1), &0)));
+    }
+
+    #[test]
+    fn grid_renders_with_a_closure() {
+        let mut grid = Grid::new(3, 2, false);
+        grid.set(1, 0, true);
+
+        assert_eq!(format!("{:?}", Tiles(grid)), "+---+\n| # |\n|   |\n+---+");
+    }
+
+    #[test]
+    fn set_and_get() {
+        // 130 values, so that the bitset spans three words.
+        let mut grid = BoolGrid::new(10, 13, false);
+        grid.set(0, 0, true);
+        grid.set(4, 6, true);
+        grid.set(9, 12, true);
+
+        assert_eq!(grid.len(), 130);
+        assert_eq!(grid.get(0, 0), Some(true));
+        assert_eq!(grid.get(1, 0), Some(false));
+        assert_eq!(grid.get(4, 6), Some(true));
+        assert_eq!(grid.get(9, 12), Some(true));
+        assert_eq!(
+            grid.positions().collect::<Vec<_>>(),
+            vec![(0, 0), (4, 6), (9, 12)]
+        );
+
+        grid.set(4, 6, false);
+        assert_eq!(grid.get(4, 6), Some(false));
+    }
+
+    #[test]
+    fn bool_grids_ignore_positions_out_of_bounds() {
+        let mut grid = BoolGrid::new(10, 13, false);
+        grid.set(10, 0, true);
+        grid.set(-1, 1, true);
+
+        assert_eq!(grid.get(10, 0), None);
+        assert_eq!(grid.get(0, 13), None);
+        // Past the end of a row isn't the start of the next one.
+        assert_eq!(grid.get(0, 1), Some(false));
+        assert_eq!(grid.get(9, 0), Some(false));
+        assert_eq!(grid.count_true(), 0);
+    }
+
+    #[test]
+    fn filled_grids_are_equal() {
+        let mut grid = BoolGrid::new(7, 10, false);
+        grid.fill(true);
+
+        assert_eq!(grid, BoolGrid::new(7, 10, true));
+        assert_eq!(grid.count_true(), 70);
+
+        grid.set(3, 0, false);
+        assert_eq!(grid.count_true(), 69);
+    }
+}
@@ -67,7 +67,12 @@
 
 pub mod bresenham;
 pub mod fov;
+mod grid;
 pub mod path;
+pub mod sample;
+pub mod topology;
+
+pub use grid::Grid;
 
 /// A convenience type alias for a position tuple.
 pub type Point = (i32, i32);
@@ -80,6 +85,8 @@ pub trait Map {
     /// Used by field of view algorithm.
     fn is_transparent(&self, x: i32, y: i32) -> bool;
     /// Wether it is possible or not to walk through the tile at position `(x, y)`.
-    /// Used by pathfinding algorithm.
-    fn is_walkable(&self, x: i32, y: i32) -> bool;
+    /// Used by pathfinding algorithm. Defaults to `false`, so maps only used for field of view can skip it.
+    fn is_walkable(&self, _x: i32, _y: i32) -> bool {
+        false
+    }
 }
@@ -2,7 +2,7 @@
 
 use std::{cmp::Ordering, collections::BinaryHeap};
 
-use crate::{Map, Point};
+use crate::{topology::Topology, Map, Point};
 
 pub type NodeId = usize;
 
@@ -75,6 +75,58 @@ pub fn astar_path_fourwaygrid<T: Map>(map: &T, from: Point, to: Point) -> Option
     })
 }
 
+/// An A* pathfinding implementation for a grid base map, where the neighboors of a tile and the distance
+/// between tiles are given by a `Topology`: squares with or without diagonals, hexagons...
+/// Returns an optional vector containing the several points on the map to walk through, including the origin and destination.
+///
+/// # Arguments
+///
+/// * `map` - a struct implementing the `Map` trait.
+/// * `topology` - the shape of the grid.
+/// * `from` - the origin.
+/// * `to` - the destination.
+///
+/// # Examples
+/// ```
+/// use torchbearer::{Map, Point};
+/// use torchbearer::path::astar_path_with_topology;
+/// use torchbearer::topology::HexGrid;
+///
+/// struct OpenMap;
+///
+/// impl Map for OpenMap {
+///     fn dimensions(&self) -> (i32, i32) {
+///         (10, 10)
+///     }
+///
+///     fn is_transparent(&self, _x: i32, _y: i32) -> bool {
+///         unreachable!("Not used in pathfinding.")
+///     }
+///
+///     fn is_walkable(&self, _x: i32, _y: i32) -> bool {
+///         true
+///     }
+/// }
+///
+/// let path = astar_path_with_topology(&OpenMap, HexGrid, (0, 0), (2, 4)).unwrap();
+/// // Going down the rows, the path shifts sideways without extra steps.
+/// assert_eq!(path.len(), 5);
+/// ```
+pub fn astar_path_with_topology<T: Map, P: Topology>(
+    map: &T,
+    topology: P,
+    from: Point,
+    to: Point,
+) -> Option<Vec<Point>> {
+    let graph = TopologyGraph::new(map, topology);
+    astar_path(&graph, graph.point_to_index(from), graph.point_to_index(to)).map(|indices| {
+        indices
+            .into_iter()
+            .map(|index| graph.index_to_point(index))
+            .collect()
+    })
+}
+
 /// An A* pathfinding implementation for a grid base map.
 /// Returns an optional vector containing the several points on the map to walk through, including the origin and destination.
 ///
@@ -354,11 +406,71 @@ impl<'a, T: Map> Graph for FourWayGridGraph<'a, T> {
     }
 }
 
+/// A wrapper around a Map, where the neighboors of each node are given by a `Topology`.
+/// Every step costs the same, whatever its direction.
+pub struct TopologyGraph<'a, T: Map, P: Topology> {
+    map: &'a T,
+    topology: P,
+    width: i32,
+    height: i32,
+}
+
+impl<'a, T: Map, P: Topology> TopologyGraph<'a, T, P> {
+    pub fn new(map: &'a T, topology: P) -> Self {
+        let (width, height) = map.dimensions();
+        TopologyGraph {
+            map,
+            topology,
+            width,
+            height,
+        }
+    }
+
+    fn point_to_index(&self, (x, y): Point) -> usize {
+        (x + y * self.width) as usize
+    }
+
+    fn index_to_point(&self, index: usize) -> Point {
+        (index as i32 % self.width, index as i32 / self.width)
+    }
+}
+
+impl<'a, T: Map, P: Topology> Graph for TopologyGraph<'a, T, P> {
+    fn node_count(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+
+    fn cost_between(&self, _a: NodeId, _b: NodeId) -> f32 {
+        1.
+    }
+
+    fn heuristic(&self, a: NodeId, b: NodeId) -> f32 {
+        self.topology
+            .distance(self.index_to_point(a), self.index_to_point(b)) as f32
+    }
+
+    fn neighboors(&self, a: NodeId, into: &mut Vec<NodeId>) {
+        let (x, y) = self.index_to_point(a);
+        for &(dx, dy) in self.topology.neighboor_offsets((x, y)) {
+            let (x, y) = (x + dx, y + dy);
+            if x < 0 || y < 0 || x >= self.width || y >= self.height || !self.map.is_walkable(x, y)
+            {
+                continue;
+            }
+            into.push(self.point_to_index((x, y)));
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{bresenham::BresenhamLine, Map, Point};
+    use crate::{
+        bresenham::BresenhamLine,
+        topology::{EightWayGrid, FourWayGrid, HexGrid},
+        Map, Point,
+    };
 
-    use super::astar_path_fourwaygrid;
+    use super::{astar_path_fourwaygrid, astar_path_with_topology};
 
     struct SampleMap {
         width: i32,
@@ -446,4 +558,31 @@ mod tests {
         let path = astar_path_fourwaygrid(&map, from, to);
         assert!(path.is_none());
     }
+
+    #[test]
+    fn astar_with_topologies() {
+        let mut map = SampleMap::new(10, 10);
+        map.build_wall((3, 0), (3, 8));
+
+        let from = (0, 0);
+        let to = (6, 0);
+
+        let square = astar_path_with_topology(&map, FourWayGrid, from, to).unwrap();
+        assert_eq!(square.len(), 25);
+        assert!(square.windows(2).all(|step| {
+            let ((x1, y1), (x2, y2)) = (step[0], step[1]);
+            (x1 - x2).abs() + (y1 - y2).abs() == 1
+        }));
+
+        let diagonal = astar_path_with_topology(&map, EightWayGrid, from, to).unwrap();
+        assert_eq!(diagonal.len(), 19);
+
+        let hex = astar_path_with_topology(&map, HexGrid, from, to).unwrap();
+        assert_eq!(hex.first(), Some(&from));
+        assert_eq!(hex.last(), Some(&to));
+        assert!(hex.len() < square.len());
+
+        map.build_wall((3, 9), (3, 9));
+        assert!(astar_path_with_topology(&map, HexGrid, from, to).is_none());
+    }
 }
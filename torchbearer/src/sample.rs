@@ -0,0 +1,161 @@
+//! Ready made maps, to test and benchmark the field of view and the pathfinding over many layouts,
+//! or to fuzz whatever is built on top of them.
+
+use std::fmt::{self, Debug, Formatter};
+
+use crate::{Grid, Map, Point};
+
+/// A map where every tile is either a wall or a floor. Walls block both the sight and the way.
+///
+/// # Examples
+/// ```
+/// use torchbearer::fov::field_of_view;
+/// use torchbearer::sample::SampleMap;
+/// use torchbearer::Map;
+///
+/// // The same seed always gives the same walls.
+/// let map = SampleMap::random(20, 10, 0.3, 42);
+/// assert_eq!(map.walls(), SampleMap::random(20, 10, 0.3, 42).walls());
+///
+/// for seed in 0..10 {
+///     let mut map = SampleMap::random(20, 10, 0.3, seed);
+///     map.set_wall(5, 5, false);
+///     assert!(field_of_view(&map, (5, 5), 4).contains(&(5, 5)));
+/// }
+/// ```
+#[derive(Clone)]
+pub struct SampleMap {
+    walls: Grid<bool>,
+}
+
+impl SampleMap {
+    /// A map without any wall.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the width or the height isn't strictly positive.
+    pub fn new(width: i32, height: i32) -> Self {
+        SampleMap {
+            walls: Grid::new(width, height, false),
+        }
+    }
+
+    /// A map where each tile has `wall_fraction` chances of being a wall, from 0.0 (no walls)
+    /// to 1.0 (only walls). The layout only depends on the seed, so that failing cases can be replayed.
+    pub fn random(width: i32, height: i32, wall_fraction: f32, seed: u64) -> Self {
+        let mut map = SampleMap::new(width, height);
+        let mut rng = SplitMix64(seed);
+        for (_, wall) in map.walls.iter_mut() {
+            *wall = rng.next_f32() < wall_fraction;
+        }
+        map
+    }
+
+    pub fn is_wall(&self, x: i32, y: i32) -> bool {
+        self.walls.get(x, y).copied().unwrap_or(true)
+    }
+
+    pub fn set_wall(&mut self, x: i32, y: i32, is_wall: bool) {
+        self.walls.set(x, y, is_wall);
+    }
+
+    /// The position of every wall, row by row.
+    pub fn walls(&self) -> Vec<Point> {
+        self.walls
+            .iter()
+            .filter(|(_, wall)| **wall)
+            .map(|(position, _)| position)
+            .collect()
+    }
+}
+
+impl Map for SampleMap {
+    fn dimensions(&self) -> (i32, i32) {
+        self.walls.dimensions()
+    }
+
+    fn is_transparent(&self, x: i32, y: i32) -> bool {
+        !self.is_wall(x, y)
+    }
+
+    fn is_walkable(&self, x: i32, y: i32) -> bool {
+        !self.is_wall(x, y)
+    }
+}
+
+impl Debug for SampleMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.walls
+            .fmt_ascii(f, |_, wall| if *wall { '#' } else { '.' })
+    }
+}
+
+/// A tiny random generator, good enough to lay walls, so that the crate doesn't depend on `rand`.
+/// See [Sebastiano Vigna's](https://prng.di.unimi.it/splitmix64.c) reference implementation.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed in `[0, 1)`.
+    fn next_f32(&mut self) -> f32 {
+        // The 24 upper bits fit exactly in the mantissa.
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SampleMap;
+    use crate::Map;
+
+    #[test]
+    fn random_maps_are_reproducible() {
+        let map = SampleMap::random(30, 20, 0.4, 7);
+        assert_eq!(map.walls(), SampleMap::random(30, 20, 0.4, 7).walls());
+        assert_ne!(map.walls(), SampleMap::random(30, 20, 0.4, 8).walls());
+    }
+
+    #[test]
+    fn wall_fraction_is_respected() {
+        assert!(SampleMap::random(30, 20, 0.0, 7).walls().is_empty());
+        assert_eq!(SampleMap::random(30, 20, 1.0, 7).walls().len(), 600);
+
+        let walls = SampleMap::random(100, 100, 0.25, 7).walls().len();
+        assert!((2000..3000).contains(&walls), "{} walls", walls);
+    }
+
+    #[test]
+    #[should_panic(expected = "Height should be > 0, got 0")]
+    fn rejects_a_zero_height() {
+        SampleMap::new(10, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Width should be > 0, got 0")]
+    fn rejects_a_zero_width() {
+        SampleMap::new(0, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "Width should be > 0, got -1")]
+    fn rejects_a_negative_width() {
+        SampleMap::new(-1, 5);
+    }
+
+    #[test]
+    fn walls_block_sight_and_movement() {
+        let mut map = SampleMap::new(3, 3);
+        map.set_wall(1, 1, true);
+
+        assert!(!map.is_transparent(1, 1) && !map.is_walkable(1, 1));
+        assert!(map.is_transparent(0, 1) && map.is_walkable(0, 1));
+        assert!(!map.is_transparent(-1, 0));
+    }
+}
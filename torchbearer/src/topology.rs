@@ -0,0 +1,122 @@
+//! How the tiles of a map connect to each other: which ones are neighboors, and how far apart they are.
+//! Used by the pathfinding, the field of view only deals with square grids so far.
+
+use crate::Point;
+
+/// The shape of a grid. Implement it to walk on something else than squares.
+pub trait Topology {
+    /// The offsets from a tile to each of its neighboors. They may depend on the tile itself,
+    /// like on hex grids stored as offset coordinates.
+    fn neighboor_offsets(&self, point: Point) -> &'static [Point];
+
+    /// The least amount of steps to go from `a` to `b`, walls aside.
+    fn distance(&self, a: Point, b: Point) -> i32;
+}
+
+/// Squares, walking north, east, south and west. The default behavior of the pathfinding.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FourWayGrid;
+
+impl Topology for FourWayGrid {
+    fn neighboor_offsets(&self, _point: Point) -> &'static [Point] {
+        &[(0, 1), (0, -1), (-1, 0), (1, 0)]
+    }
+
+    fn distance(&self, (xa, ya): Point, (xb, yb): Point) -> i32 {
+        (xa - xb).abs() + (ya - yb).abs()
+    }
+}
+
+/// Squares, diagonals included.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EightWayGrid;
+
+impl Topology for EightWayGrid {
+    fn neighboor_offsets(&self, _point: Point) -> &'static [Point] {
+        &[
+            (0, 1),
+            (0, -1),
+            (-1, 0),
+            (1, 0),
+            (-1, -1),
+            (1, -1),
+            (-1, 1),
+            (1, 1),
+        ]
+    }
+
+    fn distance(&self, (xa, ya): Point, (xb, yb): Point) -> i32 {
+        (xa - xb).abs().max((ya - yb).abs())
+    }
+}
+
+/// Pointy topped hexagons, stored as rows where the odd ones are shoved half a tile to the right
+/// (the "odd-r" layout of [redblobgames.com](https://www.redblobgames.com/grids/hexagons/#coordinates-offset)).
+///
+/// # Examples
+/// ```
+/// use torchbearer::topology::{HexGrid, Topology};
+///
+/// // On an odd row, the neighboors above and below lean to the right.
+/// assert!(HexGrid.neighboor_offsets((2, 1)).contains(&(1, 1)));
+/// assert_eq!(HexGrid.distance((2, 1), (3, 0)), 1);
+/// assert_eq!(HexGrid.distance((0, 0), (0, 4)), 4);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HexGrid;
+
+impl HexGrid {
+    /// Axial coordinates, where the distance is easy to compute.
+    fn to_axial((x, y): Point) -> Point {
+        (x - (y - (y & 1)) / 2, y)
+    }
+}
+
+impl Topology for HexGrid {
+    fn neighboor_offsets(&self, (_, y): Point) -> &'static [Point] {
+        if y & 1 == 0 {
+            &[(1, 0), (0, -1), (-1, -1), (-1, 0), (-1, 1), (0, 1)]
+        } else {
+            &[(1, 0), (1, -1), (0, -1), (-1, 0), (0, 1), (1, 1)]
+        }
+    }
+
+    fn distance(&self, a: Point, b: Point) -> i32 {
+        let (qa, ra) = HexGrid::to_axial(a);
+        let (qb, rb) = HexGrid::to_axial(b);
+        let (dq, dr) = (qa - qb, ra - rb);
+        (dq.abs() + dr.abs() + (dq + dr).abs()) / 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EightWayGrid, FourWayGrid, HexGrid, Topology};
+
+    #[test]
+    fn neighboors_are_one_step_away() {
+        for &point in &[(3, 3), (3, 4), (-2, -1)] {
+            for &(dx, dy) in HexGrid.neighboor_offsets(point) {
+                assert_eq!(HexGrid.distance(point, (point.0 + dx, point.1 + dy)), 1);
+            }
+            for &(dx, dy) in EightWayGrid.neighboor_offsets(point) {
+                assert_eq!(
+                    EightWayGrid.distance(point, (point.0 + dx, point.1 + dy)),
+                    1
+                );
+            }
+            for &(dx, dy) in FourWayGrid.neighboor_offsets(point) {
+                assert_eq!(FourWayGrid.distance(point, (point.0 + dx, point.1 + dy)), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn hex_distance() {
+        assert_eq!(HexGrid.distance((0, 0), (3, 0)), 3);
+        // Zigzagging down the rows also moves sideways for free.
+        assert_eq!(HexGrid.distance((0, 0), (2, 4)), 4);
+        assert_eq!(HexGrid.distance((0, 0), (3, 4)), 5);
+        assert_eq!(HexGrid.distance((3, 4), (0, 0)), 5);
+    }
+}